@@ -0,0 +1,362 @@
+//! Admin API, served on its own listener.
+//!
+//! Exposes the state of the running router (configuration, schema, plugins, caches, in flight
+//! requests) and lets operators trigger a reload of the configuration and schema files.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use futures::future::BoxFuture;
+use futures::stream;
+use futures::FutureExt;
+use futures::Stream;
+use http::header::AUTHORIZATION;
+use http::header::CONTENT_TYPE;
+use http::HeaderValue;
+use http::Method;
+use http::StatusCode;
+use mime::APPLICATION_JSON;
+use multimap::MultiMap;
+use serde_json::json;
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tower::service_fn;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use crate::configuration::redact;
+use crate::configuration::Configuration;
+use crate::configuration::ListenAddr;
+use crate::router_factory::Endpoint;
+use crate::services::layers::apq::APQLayer;
+use crate::services::router;
+use crate::services::HasPlugins;
+use crate::services::SupergraphCreator;
+
+/// Asks the configuration and schema files to be read again.
+#[derive(Clone, Debug)]
+pub(crate) struct ReloadTrigger {
+    sender: broadcast::Sender<()>,
+    /// The sources read again on reload, with how many streams listen for each
+    sources: Arc<Mutex<BTreeMap<&'static str, usize>>>,
+}
+
+impl ReloadTrigger {
+    pub(crate) fn new() -> Self {
+        Self {
+            sender: broadcast::channel(1).0,
+            sources: Default::default(),
+        }
+    }
+
+    /// Returns the sources that are read again, empty if nothing listens for reloads
+    pub(crate) fn reload(&self) -> Vec<&'static str> {
+        let sources: Vec<_> = self
+            .sources
+            .lock()
+            .expect("lock poisoned")
+            .keys()
+            .copied()
+            .collect();
+        if sources.is_empty() || self.sender.send(()).is_err() {
+            return Vec::new();
+        }
+        sources
+    }
+
+    /// Listen for reloads of a source, like `configuration` or `schema`, until the stream is
+    /// dropped
+    pub(crate) fn subscribe(&self, source: &'static str) -> impl Stream<Item = ()> {
+        *self
+            .sources
+            .lock()
+            .expect("lock poisoned")
+            .entry(source)
+            .or_default() += 1;
+        let subscription = Subscription {
+            source,
+            sources: self.sources.clone(),
+        };
+        stream::unfold(
+            (self.sender.subscribe(), subscription),
+            |(mut receiver, subscription)| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(()) => return Some(((), (receiver, subscription))),
+                        // several reloads were requested in a row, one is enough
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            },
+        )
+    }
+}
+
+/// Unregisters a source when its reload stream is dropped
+struct Subscription {
+    source: &'static str,
+    sources: Arc<Mutex<BTreeMap<&'static str, usize>>>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut sources = self.sources.lock().expect("lock poisoned");
+        if let Some(count) = sources.get_mut(self.source) {
+            *count -= 1;
+            if *count == 0 {
+                sources.remove(self.source);
+            }
+        }
+    }
+}
+
+impl Default for ReloadTrigger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts the requests currently processed by a router.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InFlightRequests(Arc<AtomicUsize>);
+
+impl InFlightRequests {
+    pub(crate) fn start(&self) -> InFlightGuard {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard(self.0.clone())
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Decrements the in flight request count when the request is done, even if it was cancelled
+pub(crate) struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Everything the admin API reports on
+#[derive(Clone)]
+struct AdminState {
+    token: Arc<String>,
+    configuration: Arc<Value>,
+    supergraph_creator: Arc<SupergraphCreator>,
+    apq_layer: APQLayer,
+    in_flight: InFlightRequests,
+}
+
+type Handler = fn(AdminState, router::Request) -> BoxFuture<'static, (StatusCode, Value)>;
+
+/// Admin API endpoints, if enabled
+pub(crate) fn endpoints(
+    configuration: &Configuration,
+    supergraph_creator: Arc<SupergraphCreator>,
+    apq_layer: APQLayer,
+    in_flight: InFlightRequests,
+) -> MultiMap<ListenAddr, Endpoint> {
+    let mut endpoints = MultiMap::new();
+    let token = match (
+        configuration.experimental_admin.enabled,
+        &configuration.experimental_admin.token,
+    ) {
+        (true, Some(token)) => token.clone(),
+        _ => return endpoints,
+    };
+
+    let raw_configuration = match &configuration.validated_yaml {
+        Some(yaml) => yaml.clone(),
+        None => serde_json::to_value(configuration).unwrap_or_default(),
+    };
+    let state = AdminState {
+        token: Arc::new(token),
+        configuration: Arc::new(redact(raw_configuration, &configuration.expanded_pointers)),
+        supergraph_creator,
+        apq_layer,
+        in_flight,
+    };
+
+    let routes: [(&str, Method, Handler); 6] = [
+        ("/configuration", Method::GET, |state, _| {
+            async move { (StatusCode::OK, (*state.configuration).clone()) }.boxed()
+        }),
+        ("/schema", Method::GET, |state, _| {
+            async move {
+                let schema = state.supergraph_creator.schema();
+                (
+                    StatusCode::OK,
+                    json!({ "id": schema.schema_id, "sdl": schema.raw_sdl.as_str() }),
+                )
+            }
+            .boxed()
+        }),
+        ("/plugins", Method::GET, |state, _| {
+            async move {
+                let plugins: Vec<String> =
+                    state.supergraph_creator.plugins().keys().cloned().collect();
+                (StatusCode::OK, json!(plugins))
+            }
+            .boxed()
+        }),
+        ("/caches", Method::GET, |state, _| {
+            async move {
+                (
+                    StatusCode::OK,
+                    json!({
+                        "query_planner": state.supergraph_creator.query_plan_cache_size().await,
+                        "apq": state.apq_layer.cache_size().await,
+                    }),
+                )
+            }
+            .boxed()
+        }),
+        ("/requests", Method::GET, |state, _| {
+            async move {
+                (
+                    StatusCode::OK,
+                    json!({ "in_flight": state.in_flight.count() }),
+                )
+            }
+            .boxed()
+        }),
+        ("/reload", Method::POST, |_, req| {
+            async move {
+                let sources = match req.router_request.extensions().get::<ReloadTrigger>() {
+                    Some(reload_trigger) => reload_trigger.reload(),
+                    None => {
+                        return (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            json!({ "error": "reloading is not available" }),
+                        )
+                    }
+                };
+                if sources.is_empty() {
+                    (
+                        StatusCode::CONFLICT,
+                        json!({
+                            "error": "the configuration and schema are not read from files, they can't be reloaded"
+                        }),
+                    )
+                } else {
+                    tracing::info!(
+                        "reload of the {} requested through the admin API",
+                        sources.join(" and ")
+                    );
+                    (
+                        StatusCode::ACCEPTED,
+                        json!({ "status": "reloading", "sources": sources }),
+                    )
+                }
+            }
+            .boxed()
+        }),
+    ];
+
+    for (path, method, handler) in routes {
+        let state = state.clone();
+        endpoints.insert(
+            configuration.experimental_admin.listen.clone(),
+            Endpoint::from_router_service(
+                path.to_string(),
+                service_fn(move |req: router::Request| {
+                    let state = state.clone();
+                    let method = method.clone();
+                    async move {
+                        let context = req.context.clone();
+                        let (status, body) = if !is_authorized(&state.token, &req) {
+                            (
+                                StatusCode::UNAUTHORIZED,
+                                json!({ "error": "invalid or missing admin token" }),
+                            )
+                        } else if req.router_request.method() != method {
+                            (
+                                StatusCode::METHOD_NOT_ALLOWED,
+                                json!({ "error": format!("expected a {method} request") }),
+                            )
+                        } else {
+                            handler(state, req).await
+                        };
+
+                        Ok::<_, BoxError>(router::Response {
+                            response: http::Response::builder()
+                                .status(status)
+                                .header(
+                                    CONTENT_TYPE,
+                                    HeaderValue::from_static(APPLICATION_JSON.essence_str()),
+                                )
+                                .body::<hyper::Body>(serde_json::to_vec(&body)?.into())?,
+                            context,
+                        })
+                    }
+                })
+                .boxed(),
+            ),
+        );
+    }
+
+    endpoints
+}
+
+fn is_authorized(token: &str, req: &router::Request) -> bool {
+    let provided = req
+        .router_request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided {
+        // compare every byte so the time taken does not depend on the matching prefix
+        Some(provided) => {
+            provided.len() == token.len()
+                && provided
+                    .bytes()
+                    .zip(token.bytes())
+                    .fold(0, |acc, (a, b)| acc | (a ^ b))
+                    == 0
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reports_the_reloaded_sources() {
+        let reload_trigger = ReloadTrigger::new();
+        assert!(reload_trigger.reload().is_empty());
+
+        let configuration = reload_trigger.subscribe("configuration");
+        let schema = reload_trigger.subscribe("schema");
+        assert_eq!(reload_trigger.reload(), vec!["configuration", "schema"]);
+
+        drop(schema);
+        assert_eq!(reload_trigger.reload(), vec!["configuration"]);
+        drop(configuration);
+        assert!(reload_trigger.reload().is_empty());
+    }
+
+    #[test]
+    fn it_checks_the_token() {
+        let request = |value: &str| {
+            router::Request::fake_builder()
+                .header(AUTHORIZATION, value)
+                .build()
+                .unwrap()
+        };
+        assert!(is_authorized("secret", &request("Bearer secret")));
+        assert!(!is_authorized("secret", &request("Bearer secreT")));
+        assert!(!is_authorized("secret", &request("Bearer secret2")));
+        assert!(!is_authorized("secret", &request("secret")));
+    }
+}
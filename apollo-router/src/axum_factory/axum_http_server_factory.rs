@@ -18,6 +18,7 @@ use axum::Router;
 use futures::channel::oneshot;
 use futures::future::join;
 use futures::future::join_all;
+use futures::future::BoxFuture;
 use futures::prelude::*;
 use http::Request;
 use http_body::combinators::UnsyncBoxBody;
@@ -45,6 +46,7 @@ use super::listeners::ListenersAndRouters;
 use super::utils::decompress_request_body;
 use super::utils::PropagatingMakeSpan;
 use super::ListenAddrAndRouter;
+use crate::admin::ReloadTrigger;
 use crate::axum_factory::listeners::get_extra_listeners;
 use crate::axum_factory::listeners::serve_router_on_listen_addr;
 use crate::configuration::Configuration;
@@ -52,7 +54,11 @@ use crate::configuration::ListenAddr;
 use crate::http_server_factory::HttpServerFactory;
 use crate::http_server_factory::HttpServerHandle;
 use crate::http_server_factory::Listener;
+use crate::http_server_factory::ShutdownState;
+use crate::http_server_factory::StartupHealth;
+use crate::http_server_factory::StartupHealthHandle;
 use crate::plugins::traffic_shaping::Elapsed;
+use crate::plugins::traffic_shaping::Overloaded;
 use crate::plugins::traffic_shaping::RateLimited;
 use crate::router::ApolloRouterError;
 use crate::router_factory::Endpoint;
@@ -64,17 +70,26 @@ use crate::uplink::entitlement::ENTITLEMENT_EXPIRED_SHORT_MESSAGE;
 /// A basic http server using Axum.
 /// Uses streaming as primary method of response.
 #[derive(Debug)]
-pub(crate) struct AxumHttpServerFactory;
+pub(crate) struct AxumHttpServerFactory {
+    reload_trigger: Option<ReloadTrigger>,
+}
 
 impl AxumHttpServerFactory {
     pub(crate) fn new() -> Self {
-        Self
+        Self {
+            reload_trigger: None,
+        }
+    }
+
+    /// Make the reload trigger available to the endpoints, for the admin API
+    pub(crate) fn with_reload_trigger(mut self, reload_trigger: ReloadTrigger) -> Self {
+        self.reload_trigger = Some(reload_trigger);
+        self
     }
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "UPPERCASE")]
-#[allow(dead_code)]
 enum HealthStatus {
     Up,
     Down,
@@ -83,6 +98,91 @@ enum HealthStatus {
 #[derive(Debug, Serialize)]
 struct Health {
     status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<ReadinessDetails>,
+}
+
+impl Health {
+    fn up(details: Option<ReadinessDetails>) -> Self {
+        Health {
+            status: HealthStatus::Up,
+            details,
+        }
+    }
+
+    fn down(details: Option<ReadinessDetails>) -> Self {
+        Health {
+            status: HealthStatus::Down,
+            details,
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self.status {
+            HealthStatus::Up => StatusCode::OK,
+            HealthStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ReadinessDetails {
+    /// The router is shutting down and should not receive new requests
+    draining: bool,
+    /// Critical subgraphs that are currently unavailable
+    unhealthy_subgraphs: Vec<String>,
+    /// The router is starting and did not receive these inputs yet
+    waiting_for: Vec<&'static str>,
+    /// The router is starting and its query planner is warming up
+    warming_up: bool,
+}
+
+fn is_draining(req: &router::Request) -> bool {
+    req.router_request
+        .extensions()
+        .get::<ShutdownState>()
+        .map(ShutdownState::is_draining)
+        .unwrap_or(false)
+}
+
+fn health_endpoint<F>(path: &str, check: F) -> Endpoint
+where
+    F: Fn(&router::Request) -> Health + Send + Sync + 'static,
+{
+    Endpoint::from_router_service(
+        path.to_string(),
+        service_fn(move |req: router::Request| {
+            let health = check(&req);
+            tracing::trace!(?health, request = ?req.router_request, "health check");
+            async move {
+                Ok(router::Response {
+                    response: http::Response::builder()
+                        .status(health.status_code())
+                        .body::<hyper::Body>(
+                            serde_json::to_vec(&health).map_err(BoxError::from)?.into(),
+                        )?,
+                    context: req.context,
+                })
+            }
+        })
+        .boxed(),
+    )
+}
+
+/// The health checks served while the router starts: it is live, but not ready yet
+pub(super) fn startup_health_router(startup_health: StartupHealth) -> Router {
+    health_endpoint("/health/live", |_| Health::up(None))
+        .into_router()
+        .merge(
+            health_endpoint("/health/ready", move |_| {
+                Health::down(Some(ReadinessDetails {
+                    waiting_for: startup_health.waiting_for(),
+                    warming_up: startup_health.warming_up(),
+                    ..Default::default()
+                }))
+            })
+            .into_router(),
+        )
 }
 
 pub(crate) fn make_axum_router<RF>(
@@ -98,29 +198,53 @@ where
 
     if configuration.health_check.enabled {
         tracing::info!(
-            "Health check endpoint exposed at {}/health",
+            "Health check endpoints exposed at {}/health, {}/health/live and {}/health/ready",
+            configuration.health_check.listen,
+            configuration.health_check.listen,
             configuration.health_check.listen
         );
+        // the router is reported as down as soon as it starts shutting down
         endpoints.insert(
             configuration.health_check.listen.clone(),
-            Endpoint::from_router_service(
-                "/health".to_string(),
-                service_fn(move |req: router::Request| {
-                    let health = Health {
-                        status: HealthStatus::Up,
-                    };
-                    tracing::trace!(?health, request = ?req.router_request, "health check");
-                    async move {
-                        Ok(router::Response {
-                            response: http::Response::builder().body::<hyper::Body>(
-                                serde_json::to_vec(&health).map_err(BoxError::from)?.into(),
-                            )?,
-                            context: req.context,
-                        })
-                    }
-                })
-                .boxed(),
-            ),
+            health_endpoint("/health", |req| {
+                if is_draining(req) {
+                    Health::down(None)
+                } else {
+                    Health::up(None)
+                }
+            }),
+        );
+        // the process is running and able to answer, whatever the state of its dependencies
+        endpoints.insert(
+            configuration.health_check.listen.clone(),
+            health_endpoint("/health/live", |_| Health::up(None)),
+        );
+        let readiness_factory = service_factory.clone();
+        let critical_subgraphs = configuration
+            .health_check
+            .experimental_critical_subgraphs
+            .clone();
+        endpoints.insert(
+            configuration.health_check.listen.clone(),
+            health_endpoint("/health/ready", move |req| {
+                let draining = is_draining(req);
+                let unhealthy_subgraphs: Vec<String> = readiness_factory
+                    .unhealthy_subgraphs()
+                    .into_iter()
+                    .filter(|name| critical_subgraphs.contains(name))
+                    .collect();
+                let ready = !draining && unhealthy_subgraphs.is_empty();
+                let details = Some(ReadinessDetails {
+                    draining,
+                    unhealthy_subgraphs,
+                    ..Default::default()
+                });
+                if ready {
+                    Health::up(details)
+                } else {
+                    Health::down(details)
+                }
+            }),
         );
     }
 
@@ -165,13 +289,31 @@ impl HttpServerFactory for AxumHttpServerFactory {
     where
         RF: RouterFactory,
     {
+        let reload_trigger = self.reload_trigger.clone();
         Box::pin(async move {
-            let all_routers = make_axum_router(
+            let mut all_routers = make_axum_router(
                 service_factory,
                 &configuration,
                 extra_endpoints,
                 entitlement,
             )?;
+            if let Some(reload_trigger) = reload_trigger {
+                all_routers.main.1 = all_routers.main.1.layer(Extension(reload_trigger.clone()));
+                all_routers.extra = all_routers
+                    .extra
+                    .into_iter()
+                    .flat_map(|(listen_addr, routers)| {
+                        let reload_trigger = reload_trigger.clone();
+                        routers.into_iter().map(move |router| {
+                            (
+                                listen_addr.clone(),
+                                router.layer(Extension(reload_trigger.clone())),
+                            )
+                        })
+                    })
+                    .collect();
+            }
+            let shutdown_state = ShutdownState::from_configuration(&configuration);
 
             // serve main router
 
@@ -232,6 +374,7 @@ impl HttpServerFactory for AxumHttpServerFactory {
                 actual_main_listen_address.clone(),
                 all_routers.main.1,
                 all_connections_stopped_sender.clone(),
+                shutdown_state.clone(),
             );
 
             tracing::info!(
@@ -270,6 +413,7 @@ impl HttpServerFactory for AxumHttpServerFactory {
                             listen_addr.clone(),
                             router,
                             all_connections_stopped_sender.clone(),
+                            shutdown_state.clone(),
                         );
                         (
                             server.map(|listener| (listen_addr, listener)),
@@ -303,9 +447,55 @@ impl HttpServerFactory for AxumHttpServerFactory {
                 Some(actual_main_listen_address),
                 actual_extra_listen_adresses,
                 all_connections_stopped_sender,
+                shutdown_state,
             ))
         })
     }
+
+    fn create_startup_health(
+        &self,
+        configuration: &Configuration,
+        startup_health: StartupHealth,
+    ) -> BoxFuture<'static, Result<Option<StartupHealthHandle>, ApolloRouterError>> {
+        if !configuration.health_check.enabled {
+            return future::ready(Ok(None)).boxed();
+        }
+        let listen_address = configuration.health_check.listen.clone();
+
+        Box::pin(async move {
+            let listener = match listen_address.clone() {
+                ListenAddr::SocketAddr(addr) => Listener::new_from_socket_addr(addr, None).await?,
+                #[cfg(unix)]
+                ListenAddr::UnixSocket(path) => Listener::Unix(
+                    UnixListener::bind(path).map_err(ApolloRouterError::ServerCreationError)?,
+                ),
+            };
+            tracing::info!(
+                "Health check endpoints exposed at {}/health/live and {}/health/ready while the router starts",
+                listen_address,
+                listen_address
+            );
+
+            // the sessions of the health checks are not waited for when shutting down
+            let (all_connections_stopped_sender, _) = mpsc::channel::<()>(1);
+            let (server, shutdown_sender) = serve_router_on_listen_addr(
+                listener,
+                listen_address.clone(),
+                startup_health_router(startup_health),
+                all_connections_stopped_sender,
+                ShutdownState::default(),
+            );
+            let server_future = tokio::task::spawn(server)
+                .map_err(|_| ApolloRouterError::HttpServerLifecycleError)
+                .boxed();
+
+            Ok(Some(StartupHealthHandle::new(
+                listen_address,
+                shutdown_sender,
+                server_future,
+            )))
+        })
+    }
 }
 
 fn main_endpoint<RF>(
@@ -445,19 +635,25 @@ async fn handle_graphql(
         Err(e) => {
             tracing::info!(counter.apollo_router_session_count_active = -1,);
             if let Some(source_err) = e.source() {
-                if source_err.is::<RateLimited>() {
-                    return RateLimited::new().into_response();
+                if let Some(rate_limited) = source_err.downcast_ref::<RateLimited>() {
+                    return rate_limited.clone().into_response();
                 }
                 if source_err.is::<Elapsed>() {
                     return Elapsed::new().into_response();
                 }
+                if source_err.is::<Overloaded>() {
+                    return Overloaded::new().into_response();
+                }
             }
-            if e.is::<RateLimited>() {
-                return RateLimited::new().into_response();
+            if let Some(rate_limited) = e.downcast_ref::<RateLimited>() {
+                return rate_limited.clone().into_response();
             }
             if e.is::<Elapsed>() {
                 return Elapsed::new().into_response();
             }
+            if e.is::<Overloaded>() {
+                return Overloaded::new().into_response();
+            }
 
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
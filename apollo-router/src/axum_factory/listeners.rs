@@ -2,28 +2,52 @@
 
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
+use axum::body::StreamBody;
+use axum::extract::State;
+use axum::middleware;
+use axum::middleware::Next;
 use axum::response::*;
+use axum::Extension;
 use axum::Router;
+use bytes::Bytes;
 use futures::channel::oneshot;
 use futures::prelude::*;
+use http::header::CONTENT_TYPE;
+use http::Request;
+use http_body::Body as _;
 use hyper::server::conn::Http;
 use multimap::MultiMap;
 #[cfg(unix)]
 use tokio::net::UnixListener;
 use tokio::sync::mpsc;
 use tokio::sync::Notify;
+use tower_http::add_extension::AddExtension;
 
 use crate::configuration::Configuration;
+use crate::graphql;
 use crate::http_server_factory::Listener;
 use crate::http_server_factory::NetworkStream;
+use crate::http_server_factory::ShutdownState;
 use crate::router::ApolloRouterError;
 use crate::router_factory::Endpoint;
 use crate::ListenAddr;
 
+/// Information about the client connection, added to the request extensions
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ConnectionInfo {
+    /// Address of the client, for TCP connections
+    pub(crate) peer_address: Option<SocketAddr>,
+}
+
+/// Time given to the connections to send the last responses once the shutdown grace period
+/// has elapsed
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Clone, Debug)]
 pub(crate) struct ListenAddrAndRouter(pub(crate) ListenAddr, pub(crate) Router);
 
@@ -185,8 +209,15 @@ pub(super) fn serve_router_on_listen_addr(
     address: ListenAddr,
     router: axum::Router,
     all_connections_stopped_sender: mpsc::Sender<()>,
+    shutdown_state: ShutdownState,
 ) -> (impl Future<Output = Listener>, oneshot::Sender<()>) {
     let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+    let router = router
+        .layer(middleware::from_fn_with_state(
+            shutdown_state.clone(),
+            end_streams_on_shutdown,
+        ))
+        .layer(Extension(shutdown_state.clone()));
     // this server reproduces most of hyper::server::Server's behaviour
     // we select over the stop_listen_receiver channel and the listener's
     // accept future. If the channel received something or the sender
@@ -209,6 +240,7 @@ pub(super) fn serve_router_on_listen_addr(
                     let app = router.clone();
                     let connection_shutdown = connection_shutdown.clone();
                     let connection_stop_signal = all_connections_stopped_sender.clone();
+                    let shutdown_state = shutdown_state.clone();
 
                     match res {
                         Ok(res) => {
@@ -234,6 +266,9 @@ pub(super) fn serve_router_on_listen_addr(
                                             .expect(
                                                 "this should not fail unless the socket is invalid",
                                             );
+                                            let app = AddExtension::new(app, ConnectionInfo {
+                                                peer_address: stream.peer_addr().ok(),
+                                            });
                                             let connection = Http::new()
                                             .http1_keep_alive(true)
                                             .http1_header_read_timeout(Duration::from_secs(10))
@@ -251,7 +286,13 @@ pub(super) fn serve_router_on_listen_addr(
                                                 let c = connection.as_mut();
                                                 c.graceful_shutdown();
 
-                                                let _= connection.await;
+                                                tokio::select! {
+                                                    _ = &mut connection => {}
+                                                    // give the remaining responses a last chance to be sent
+                                                    _ = shutdown_state.deadline_reached() => {
+                                                        let _ = tokio::time::timeout(FLUSH_TIMEOUT, connection).await;
+                                                    }
+                                                }
                                             }
                                         }
                                     }
@@ -273,7 +314,13 @@ pub(super) fn serve_router_on_listen_addr(
                                                 let c = connection.as_mut();
                                                 c.graceful_shutdown();
 
-                                                let _= connection.await;
+                                                tokio::select! {
+                                                    _ = &mut connection => {}
+                                                    // give the remaining responses a last chance to be sent
+                                                    _ = shutdown_state.deadline_reached() => {
+                                                        let _ = tokio::time::timeout(FLUSH_TIMEOUT, connection).await;
+                                                    }
+                                                }
                                             }
                                         }
                                     },
@@ -286,6 +333,9 @@ pub(super) fn serve_router_on_listen_addr(
 
                                             let protocol = stream.get_ref().1.alpn_protocol();
                                             let http2 = protocol == Some(&b"h2"[..]);
+                                            let app = AddExtension::new(app, ConnectionInfo {
+                                                peer_address: stream.get_ref().0.peer_addr().ok(),
+                                            });
 
                                             let connection = Http::new()
                                             .http1_keep_alive(true)
@@ -305,7 +355,13 @@ pub(super) fn serve_router_on_listen_addr(
                                                 let c = connection.as_mut();
                                                 c.graceful_shutdown();
 
-                                                let _= connection.await;
+                                                tokio::select! {
+                                                    _ = &mut connection => {}
+                                                    // give the remaining responses a last chance to be sent
+                                                    _ = shutdown_state.deadline_reached() => {
+                                                        let _ = tokio::time::timeout(FLUSH_TIMEOUT, connection).await;
+                                                    }
+                                                }
                                             }
                                         }
                                     }
@@ -405,6 +461,60 @@ pub(super) fn serve_router_on_listen_addr(
     (server, shutdown_sender)
 }
 
+/// Once the shutdown grace period has elapsed, end the multipart responses that are still
+/// streamed with an error
+async fn end_streams_on_shutdown<B>(
+    State(shutdown_state): State<ShutdownState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let response = next.run(request).await;
+    let is_multipart = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("multipart/"))
+        .unwrap_or(false);
+    if !is_multipart {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = stream::unfold(
+        (body, Box::pin(shutdown_state.deadline_reached()), false),
+        |(mut body, mut deadline, ended)| async move {
+            if ended {
+                return None;
+            }
+            tokio::select! {
+                biased;
+                chunk = body.data() => chunk.map(|chunk| (chunk, (body, deadline, false))),
+                _ = &mut deadline => Some((Ok(shutdown_part()), (body, deadline, true))),
+            }
+        },
+    );
+
+    Response::from_parts(parts, axum::body::boxed(StreamBody::new(body)))
+}
+
+/// Last part of a multipart response, sent when the router shuts down
+fn shutdown_part() -> Bytes {
+    let response = graphql::Response::builder()
+        .error(
+            graphql::Error::builder()
+                .message("the router is shutting down")
+                .extension_code("SHUTTING_DOWN")
+                .build(),
+        )
+        .has_next(false)
+        .build();
+    let mut buf = Vec::from(&b"content-type: application/json\r\n\r\n"[..]);
+    serde_json::to_writer(&mut buf, &response)
+        .expect("a GraphQL response can always be serialized");
+    buf.extend_from_slice(b"\r\n--graphql--\r\n");
+    buf.into()
+}
+
 #[cfg(test)]
 mod tests {
     use std::net::SocketAddr;
@@ -7,4 +7,5 @@ pub(crate) mod utils;
 
 pub(crate) use axum_http_server_factory::make_axum_router;
 pub(crate) use axum_http_server_factory::AxumHttpServerFactory;
+pub(crate) use listeners::ConnectionInfo;
 pub(crate) use listeners::ListenAddrAndRouter;
@@ -54,6 +54,7 @@ use tower::Service;
 use tower::ServiceExt;
 
 pub(crate) use super::axum_http_server_factory::make_axum_router;
+use super::axum_http_server_factory::startup_health_router;
 use super::*;
 use crate::configuration::cors::Cors;
 use crate::configuration::HealthCheck;
@@ -63,6 +64,7 @@ use crate::configuration::Supergraph;
 use crate::graphql;
 use crate::http_server_factory::HttpServerFactory;
 use crate::http_server_factory::HttpServerHandle;
+use crate::http_server_factory::StartupHealth;
 use crate::json_ext::Path;
 use crate::plugin::test::MockSubgraph;
 use crate::query_planner::BridgeQueryPlanner;
@@ -1811,6 +1813,100 @@ async fn it_supports_server_restart() {
     );
 }
 
+#[test(tokio::test)]
+async fn it_ends_streams_opened_before_a_restart_on_shutdown() {
+    let configuration = Arc::new(
+        Configuration::fake_builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .shutdown_grace_period(Duration::from_millis(100))
+                    .build(),
+            )
+            .build()
+            .unwrap(),
+    );
+    let router_service = router_service::from_supergraph_mock_callback(|req| {
+        let body = stream::iter(vec![graphql::Response::builder()
+            .data(json!({
+                "test": "hello",
+            }))
+            .has_next(true)
+            .build()])
+        .chain(stream::pending())
+        .boxed();
+        Ok(SupergraphResponse::new_from_response(
+            http::Response::builder().status(200).body(body).unwrap(),
+            req.context,
+        ))
+    })
+    .await;
+    let server_factory = AxumHttpServerFactory::new();
+    let (service, mut handle) = tower_test::mock::spawn();
+    tokio::spawn(async move {
+        let mut router_service = router_service;
+        while let Some((request, responder)) = handle.next_request().await {
+            match router_service.ready().await.unwrap().call(request).await {
+                Ok(response) => responder.send_response(response),
+                Err(err) => responder.send_error(err),
+            }
+        }
+    });
+    let router_factory = TestRouterFactory {
+        inner: service.into_inner(),
+    };
+    let (all_connections_stopped_sender, mut all_connections_stopped) = mpsc::channel::<()>(1);
+    let server = server_factory
+        .create(
+            router_factory.clone(),
+            configuration.clone(),
+            None,
+            vec![],
+            MultiMap::new(),
+            EntitlementState::default(),
+            all_connections_stopped_sender,
+        )
+        .await
+        .expect("Failed to create server factory");
+
+    let url = format!("{}/", server.graphql_listen_address().as_ref().unwrap());
+    let mut response = reqwest::Client::new()
+        .post(&url)
+        .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+        .header(
+            ACCEPT,
+            HeaderValue::from_static(MULTIPART_DEFER_CONTENT_TYPE),
+        )
+        .body(json!({ "query": "query { test ... @defer { other } }" }).to_string())
+        .send()
+        .await
+        .unwrap();
+    response.chunk().await.unwrap().unwrap();
+
+    let server = server
+        .restart(
+            &server_factory,
+            router_factory,
+            configuration,
+            MultiMap::new(),
+            EntitlementState::default(),
+        )
+        .await
+        .unwrap();
+    server.shutdown().await.unwrap();
+
+    let last = tokio::time::timeout(Duration::from_secs(5), response.chunk())
+        .await
+        .expect("the stream should be ended by the shutdown")
+        .unwrap()
+        .unwrap();
+    assert!(std::str::from_utf8(&last)
+        .unwrap()
+        .contains("SHUTTING_DOWN"));
+    tokio::time::timeout(Duration::from_secs(5), all_connections_stopped.recv())
+        .await
+        .expect("the sessions should be closed by the shutdown");
+}
+
 /// A counter of how many GraphQL responses have been sent by an Apollo Router
 ///
 /// When `@defer` is used, it should increment multiple times for a single HTTP request.
@@ -2175,6 +2271,96 @@ async fn test_health_check() {
     )
 }
 
+#[tokio::test]
+async fn test_liveness_and_readiness_checks() {
+    let (server, client) = init(router_service::empty().await).await;
+    let address = server
+        .graphql_listen_address()
+        .as_ref()
+        .unwrap()
+        .to_string();
+
+    let response = client
+        .get(format!("{address}/health/live"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json!({"status": "UP" }),
+        response.json::<serde_json::Value>().await.unwrap()
+    );
+
+    let response = client
+        .get(format!("{address}/health/ready"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json!({
+            "status": "UP",
+            "details": {
+                "draining": false,
+                "unhealthy_subgraphs": [],
+                "waiting_for": [],
+                "warming_up": false
+            }
+        }),
+        response.json::<serde_json::Value>().await.unwrap()
+    )
+}
+
+#[tokio::test]
+async fn test_liveness_and_readiness_checks_while_starting() {
+    let startup_health = StartupHealth::default();
+    startup_health.set_waiting_for(vec!["schema", "entitlement"]);
+    let router = startup_health_router(startup_health.clone());
+    let get = |path: &str| http::Request::get(path).body(hyper::Body::empty()).unwrap();
+    let json = |response: http::Response<BoxBody>| async move {
+        serde_json::from_slice::<serde_json::Value>(
+            &hyper::body::to_bytes(response.into_body()).await.unwrap(),
+        )
+        .unwrap()
+    };
+
+    let response = router.clone().oneshot(get("/health/live")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json!({"status": "UP" }), json(response).await);
+
+    let response = router.clone().oneshot(get("/health/ready")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        json!({
+            "status": "DOWN",
+            "details": {
+                "draining": false,
+                "unhealthy_subgraphs": [],
+                "waiting_for": ["schema", "entitlement"],
+                "warming_up": false
+            }
+        }),
+        json(response).await
+    );
+
+    startup_health.set_waiting_for(Vec::new());
+    startup_health.set_warming_up(true);
+    let response = router.oneshot(get("/health/ready")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        json!({
+            "status": "DOWN",
+            "details": {
+                "draining": false,
+                "unhealthy_subgraphs": [],
+                "waiting_for": [],
+                "warming_up": true
+            }
+        }),
+        json(response).await
+    );
+}
+
 #[tokio::test]
 async fn test_health_check_custom_listener() {
     let conf = Configuration::fake_builder()
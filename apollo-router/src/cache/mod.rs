@@ -127,6 +127,11 @@ where
     pub(crate) async fn in_memory_keys(&self) -> Vec<K> {
         self.storage.in_memory_keys().await
     }
+
+    /// Number of entries in the in memory cache
+    pub(crate) async fn len(&self) -> usize {
+        self.storage.len().await
+    }
 }
 
 pub(crate) struct Entry<K: KeyType, V: ValueType> {
@@ -329,4 +329,40 @@ impl RedisCacheStorage {
         };
         tracing::trace!("insert result {:?}", r);
     }
+
+    /// Increments the counter stored at `key`, and returns its new value along with the value
+    /// of the counter stored at `previous_key`
+    pub(crate) async fn incr_window(
+        &self,
+        key: String,
+        previous_key: String,
+        expiration: Duration,
+    ) -> Result<(i64, i64), RedisError> {
+        tracing::trace!("incrementing in redis: {:?}", key);
+        let pipeline = self.inner.pipeline();
+
+        let _ = pipeline.incr::<(), _>(key.clone()).await;
+        let _ = pipeline
+            .pexpire::<(), _>(key, expiration.as_millis() as i64)
+            .await;
+        let _ = pipeline.get::<(), _>(previous_key).await;
+
+        let results: Vec<fred::types::RedisValue> = pipeline.all().await?;
+        let count = results
+            .first()
+            .and_then(|value| value.as_i64())
+            .unwrap_or_default();
+        let previous = results
+            .get(2)
+            .and_then(|value| value.as_i64())
+            .unwrap_or_default();
+
+        Ok((count, previous))
+    }
+
+    /// Decrements the counter stored at `key`
+    pub(crate) async fn decr(&self, key: String) -> Result<(), RedisError> {
+        tracing::trace!("decrementing in redis: {:?}", key);
+        self.inner.decr::<(), _>(key).await
+    }
 }
@@ -181,7 +181,6 @@ where
             .collect()
     }
 
-    #[cfg(test)]
     pub(crate) async fn len(&self) -> usize {
         self.inner.lock().await.len()
     }
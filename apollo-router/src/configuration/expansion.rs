@@ -10,6 +10,7 @@ use proteus::Parser;
 use proteus::TransformBuilder;
 use serde_json::Value;
 
+use super::effective::leaves;
 use super::ConfigurationError;
 use crate::executable::APOLLO_ROUTER_DEV_ENV;
 
@@ -20,7 +21,7 @@ pub(crate) struct Expansion {
     defaults: Vec<ConfigDefault>,
 }
 
-#[derive(buildstructor::Builder)]
+#[derive(Clone, buildstructor::Builder)]
 pub(crate) struct ConfigDefault {
     config_path: String,
     env_name: Option<String>,
@@ -29,6 +30,13 @@ pub(crate) struct ConfigDefault {
     default: Value,
 }
 
+/// Where the value set by a [`ConfigDefault`] comes from
+pub(crate) enum DefaultSource {
+    EnvironmentVariable(String),
+    Default,
+    DevelopmentMode,
+}
+
 impl Expansion {
     pub(crate) fn default() -> Result<Self, ConfigurationError> {
         // APOLLO_ROUTER_CONFIG_ENV_PREFIX and APOLLO_ROUTER_CONFIG_SUPPORTED_MODES are unsupported and may change in future.
@@ -102,43 +110,132 @@ fn dev_mode_defaults() -> Vec<ConfigDefault> {
 }
 
 impl Expansion {
-    fn context_fn(&self) -> impl Fn(&str) -> Result<Option<String>, ConfigurationError> + '_ {
-        move |key: &str| {
-            if !self
-                .supported_modes
-                .iter()
-                .any(|prefix| key.starts_with(prefix.as_str()))
-            {
-                return Err(ConfigurationError::UnknownExpansionMode {
+    /// The value of a variable, `None` if the environment variable or the file does not exist
+    fn lookup(&self, key: &str) -> Result<Option<String>, ConfigurationError> {
+        if !self
+            .supported_modes
+            .iter()
+            .any(|prefix| key.starts_with(prefix.as_str()))
+        {
+            return Err(ConfigurationError::UnknownExpansionMode {
+                key: key.to_string(),
+                supported_modes: self.supported_modes.join("|"),
+            });
+        }
+
+        if let Some(key) = key.strip_prefix("env.") {
+            let value = match self.prefix.as_ref() {
+                None => env::var(key),
+                Some(prefix) => env::var(format!("{prefix}_{key}")),
+            };
+            return match value {
+                Ok(value) => Ok(Some(value)),
+                Err(VarError::NotPresent) => Ok(None),
+                Err(cause) => Err(ConfigurationError::CannotExpandVariable {
                     key: key.to_string(),
-                    supported_modes: self.supported_modes.join("|"),
-                });
+                    cause: format!("{cause}"),
+                }),
+            };
+        }
+        if let Some(key) = key.strip_prefix("file.") {
+            if !std::path::Path::new(key).exists() {
+                return Ok(None);
             }
 
-            if let Some(key) = key.strip_prefix("env.") {
-                return match self.prefix.as_ref() {
-                    None => env::var(key),
-                    Some(prefix) => env::var(format!("{prefix}_{key}")),
-                }
-                .map(Some)
-                .map_err(|cause| ConfigurationError::CannotExpandVariable {
+            return fs::read_to_string(key).map(Some).map_err(|cause| {
+                ConfigurationError::CannotExpandVariable {
                     key: key.to_string(),
                     cause: format!("{cause}"),
-                });
-            }
-            if let Some(key) = key.strip_prefix("file.") {
-                if !std::path::Path::new(key).exists() {
-                    return Ok(None);
                 }
+            });
+        }
+        Err(ConfigurationError::InvalidExpansionModeConfig)
+    }
 
-                return fs::read_to_string(key).map(Some).map_err(|cause| {
-                    ConfigurationError::CannotExpandVariable {
-                        key: key.to_string(),
-                        cause: format!("{cause}"),
+    /// Expand the variables of a configuration value, `None` if it does not reference any
+    fn expand_str(&self, value: &str) -> Result<Option<Value>, ConfigurationError> {
+        let mut expanded = String::new();
+        let mut references = 0;
+        let mut unresolved = 0;
+        let mut hint = None;
+        let mut rest = value;
+        while let Some(start) = rest.find('$') {
+            let after = &rest[start + 1..];
+            if !after.starts_with('{') {
+                // like the shell, `$name` is a reference, but only `${mode.key}` can be resolved
+                let name = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .map_or(after, |len| &after[..len]);
+                if !name.is_empty() {
+                    return Err(ConfigurationError::UnknownExpansionMode {
+                        key: name.to_string(),
+                        supported_modes: self.supported_modes.join("|"),
+                    });
+                }
+                expanded.push_str(&rest[..start + 1]);
+                rest = after;
+                continue;
+            }
+            let end = match closing_brace(&after[1..]) {
+                Some(end) => start + 2 + end,
+                None => break,
+            };
+            let reference = Reference::parse(&rest[start + 2..end])?;
+            expanded.push_str(&rest[..start]);
+            match self.resolve(&reference)? {
+                Some(value) => {
+                    expanded.push_str(&value);
+                    references += 1;
+                    if let Some(type_hint) = reference.hint {
+                        hint = Some((reference.key.to_string(), type_hint));
                     }
-                });
+                }
+                None => {
+                    expanded.push_str(&rest[start..=end]);
+                    unresolved += 1;
+                }
             }
-            Err(ConfigurationError::InvalidExpansionModeConfig)
+            rest = &rest[end + 1..];
+        }
+        if references == 0 {
+            return Ok(None);
+        }
+        expanded.push_str(rest);
+
+        // the value is the variable alone
+        let whole =
+            references == 1 && unresolved == 0 && value.starts_with("${") && rest.is_empty();
+        match hint {
+            None => Ok(Some(coerce(&expanded))),
+            Some((key, hint)) if whole => hint.apply(&key, expanded).map(Some),
+            Some((key, _)) => Err(ConfigurationError::CannotExpandVariable {
+                key,
+                cause: "a type hint can only be used when the variable is the whole value"
+                    .to_string(),
+            }),
+        }
+    }
+
+    /// The value of a reference, `None` if it is kept as is
+    fn resolve(&self, reference: &Reference) -> Result<Option<String>, ConfigurationError> {
+        let missing = |cause: &str| ConfigurationError::CannotExpandVariable {
+            key: reference
+                .key
+                .strip_prefix("env.")
+                .unwrap_or(reference.key)
+                .to_string(),
+            cause: cause.to_string(),
+        };
+        match (self.lookup(reference.key)?, &reference.fallback) {
+            (Some(value), Fallback::None) => Ok(Some(value)),
+            // like shells, an empty value is handled as a missing one
+            (Some(value), _) if !value.is_empty() => Ok(Some(value)),
+            (_, Fallback::Default(default)) => Ok(Some(default.to_string())),
+            (_, Fallback::Required("")) => Err(missing("a value is required")),
+            (_, Fallback::Required(message)) => Err(missing(message)),
+            // a missing file without a fallback is not expanded
+            (None, Fallback::None) if reference.key.starts_with("file.") => Ok(None),
+            (None, Fallback::None) => Err(missing(&VarError::NotPresent.to_string())),
         }
     }
 
@@ -146,10 +243,43 @@ impl Expansion {
         &self,
         configuration: &serde_json::Value,
     ) -> Result<serde_json::Value, ConfigurationError> {
+        self.expand_with_pointers(configuration)
+            .map(|(configuration, _)| configuration)
+    }
+
+    /// Expand the configuration, along with the JSON pointers of the values that were expanded
+    pub(crate) fn expand_with_pointers(
+        &self,
+        configuration: &serde_json::Value,
+    ) -> Result<(serde_json::Value, Vec<String>), ConfigurationError> {
         let mut configuration = configuration.clone();
         self.defaults(&mut configuration)?;
-        self.visit(&mut configuration)?;
-        Ok(configuration)
+        let mut expanded = Vec::new();
+        self.visit(&mut configuration, String::new(), &mut expanded)?;
+        Ok((configuration, expanded))
+    }
+
+    /// The configuration values set by the defaults, as JSON pointers, with where they come from
+    pub(crate) fn default_sources(
+        &self,
+    ) -> Result<Vec<(String, DefaultSource)>, ConfigurationError> {
+        let mut sources = Vec::new();
+        for default in &self.defaults {
+            let expansion = Expansion::builder().default(default.clone()).build();
+            let mut value = Value::Object(Default::default());
+            expansion.defaults(&mut value)?;
+            for (pointer, _, _) in leaves(&value) {
+                let source = match &default.env_name {
+                    Some(env_name) if env::var(env_name).is_ok() => {
+                        DefaultSource::EnvironmentVariable(env_name.clone())
+                    }
+                    Some(_) => DefaultSource::Default,
+                    None => DefaultSource::DevelopmentMode,
+                };
+                sources.push((pointer, source));
+            }
+        }
+        Ok(sources)
     }
 
     fn defaults(&self, config: &mut Value) -> Result<(), ConfigurationError> {
@@ -201,36 +331,131 @@ impl Expansion {
         Ok(())
     }
 
-    fn visit(&self, value: &mut Value) -> Result<(), ConfigurationError> {
-        let mut expanded: Option<String> = None;
+    fn visit(
+        &self,
+        value: &mut Value,
+        pointer: String,
+        expanded_pointers: &mut Vec<String>,
+    ) -> Result<(), ConfigurationError> {
+        let mut expanded: Option<Value> = None;
         match value {
-            Value::String(value) => {
-                let new_value =
-                    shellexpand::env_with_context(value, self.context_fn()).map_err(|e| e.cause)?;
-                if &new_value != value {
-                    expanded = Some(new_value.to_string());
-                }
-            }
+            Value::String(value) => expanded = self.expand_str(value)?,
             Value::Array(a) => {
-                for v in a {
-                    self.visit(v)?
+                for (index, v) in a.iter_mut().enumerate() {
+                    self.visit(v, format!("{pointer}/{index}"), expanded_pointers)?
                 }
             }
             Value::Object(o) => {
-                for v in o.values_mut() {
-                    self.visit(v)?
+                for (key, v) in o.iter_mut() {
+                    let key = key.replace('~', "~0").replace('/', "~1");
+                    self.visit(v, format!("{pointer}/{key}"), expanded_pointers)?
                 }
             }
             _ => {}
         }
-        // The expansion may have resulted in a primitive, reparse and replace
         if let Some(expanded) = expanded {
-            *value = coerce(&expanded)
+            *value = expanded;
+            expanded_pointers.push(pointer);
         }
         Ok(())
     }
 }
 
+/// A `${mode.key|hint:-default}` or `${mode.key|hint:?message}` variable, the hint being optional
+struct Reference<'a> {
+    key: &'a str,
+    hint: Option<TypeHint>,
+    fallback: Fallback<'a>,
+}
+
+enum Fallback<'a> {
+    None,
+    /// `:-default`, used when the variable is not set or empty
+    Default(&'a str),
+    /// `:?message`, the error message when the variable is not set
+    Required(&'a str),
+}
+
+/// The type a variable is expanded to, instead of guessing it from its value
+#[derive(Clone, Copy)]
+enum TypeHint {
+    String,
+    Number,
+    Bool,
+}
+
+impl<'a> Reference<'a> {
+    fn parse(reference: &'a str) -> Result<Self, ConfigurationError> {
+        let (name, fallback) = match (reference.find(":-"), reference.find(":?")) {
+            (Some(default), required) if required.map_or(true, |required| default < required) => (
+                &reference[..default],
+                Fallback::Default(&reference[default + 2..]),
+            ),
+            (_, Some(required)) => (
+                &reference[..required],
+                Fallback::Required(&reference[required + 2..]),
+            ),
+            (None, None) => (reference, Fallback::None),
+        };
+        let (key, hint) = match name.split_once('|') {
+            Some((key, hint)) => (key, Some(TypeHint::parse(key, hint)?)),
+            None => (name, None),
+        };
+        Ok(Reference {
+            key,
+            hint,
+            fallback,
+        })
+    }
+}
+
+impl TypeHint {
+    fn parse(key: &str, hint: &str) -> Result<Self, ConfigurationError> {
+        match hint {
+            "string" => Ok(TypeHint::String),
+            "number" => Ok(TypeHint::Number),
+            "bool" => Ok(TypeHint::Bool),
+            _ => Err(ConfigurationError::CannotExpandVariable {
+                key: key.to_string(),
+                cause: format!("unknown type '{hint}', expected one of string|number|bool"),
+            }),
+        }
+    }
+
+    fn apply(self, key: &str, expanded: String) -> Result<Value, ConfigurationError> {
+        let invalid = |expected: &str| ConfigurationError::CannotExpandVariable {
+            key: key.to_string(),
+            cause: format!("expected a {expected}"),
+        };
+        match self {
+            TypeHint::String => Ok(Value::String(expanded)),
+            TypeHint::Number => match Value::from_str(expanded.trim()) {
+                Ok(Value::Number(number)) => Ok(Value::Number(number)),
+                _ => Err(invalid("number")),
+            },
+            TypeHint::Bool => match expanded.trim() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err(invalid("boolean")),
+            },
+        }
+    }
+}
+
+/// The position of the `}` closing a reference, skipping the braces nested in its default value
+fn closing_brace(reference: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (position, c) in reference.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(position),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
 pub(crate) fn coerce(expanded: &str) -> Value {
     match serde_yaml::from_str(expanded) {
         Ok(Value::Bool(b)) => Value::Bool(b),
@@ -392,6 +617,154 @@ mod test {
         })
     }
 
+    #[test]
+    fn test_fallbacks() {
+        std::env::set_var("TEST_FALLBACK_SET_VAR", "set");
+        std::env::set_var("TEST_FALLBACK_EMPTY_VAR", "");
+
+        let expansion = Expansion::builder().supported_mode("env").build();
+        let value = json!({
+            "set": "${env.TEST_FALLBACK_SET_VAR:-default}",
+            "unset": "${env.TEST_FALLBACK_UNSET_VAR:-default}",
+            "empty": "${env.TEST_FALLBACK_EMPTY_VAR:-default}",
+            "interpolated": "http://${env.TEST_FALLBACK_UNSET_VAR:-localhost}:${env.TEST_FALLBACK_UNSET_VAR:-4000}/",
+            "required": "${env.TEST_FALLBACK_SET_VAR:?must be set}",
+            "not_closed": "${env.TEST_FALLBACK_SET_VAR",
+        });
+        assert_eq!(
+            expansion.expand(&value).expect("expansion must succeed"),
+            json!({
+                "set": "set",
+                "unset": "default",
+                "empty": "default",
+                "interpolated": "http://localhost:4000/",
+                "required": "set",
+                "not_closed": "${env.TEST_FALLBACK_SET_VAR",
+            })
+        );
+
+        let error = expansion
+            .expand(&json!({ "required": "${env.TEST_FALLBACK_UNSET_VAR:?must be set}" }))
+            .expect_err("the variable is required");
+        assert_eq!(
+            error.to_string(),
+            "could not expand variable: TEST_FALLBACK_UNSET_VAR, must be set"
+        );
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let expansion = Expansion::builder().supported_mode("file").build();
+        let value = json!({
+            "missing": "${file.does/not/exist}",
+            "interpolated": "Bearer ${file.does/not/exist}",
+            "fallback": "${file.does/not/exist:-default}",
+        });
+        assert_eq!(
+            expansion.expand(&value).expect("expansion must succeed"),
+            json!({
+                "missing": "${file.does/not/exist}",
+                "interpolated": "Bearer ${file.does/not/exist}",
+                "fallback": "default",
+            })
+        );
+
+        let error = expansion
+            .expand(&json!({ "required": "${file.does/not/exist:?must exist}" }))
+            .expect_err("the file is required");
+        assert_eq!(
+            error.to_string(),
+            "could not expand variable: file.does/not/exist, must exist"
+        );
+    }
+
+    #[test]
+    fn test_braces_in_fallbacks() {
+        let expansion = Expansion::builder().supported_mode("env").build();
+        let value = json!({
+            "object": "${env.TEST_BRACES_UNSET_VAR:-{\"a\": {\"b\": 1}}}",
+            "interpolated": "${env.TEST_BRACES_UNSET_VAR:-{}}/path",
+        });
+        assert_eq!(
+            expansion.expand(&value).expect("expansion must succeed"),
+            json!({
+                "object": "{\"a\": {\"b\": 1}}",
+                "interpolated": "{}/path",
+            })
+        );
+    }
+
+    #[test]
+    fn test_unbraced_variables() {
+        std::env::set_var("TEST_UNBRACED_VAR", "set");
+
+        let expansion = Expansion::builder().supported_mode("env").build();
+        let error = expansion
+            .expand(&json!({ "unbraced": "$TEST_UNBRACED_VAR" }))
+            .expect_err("only braced variables can be expanded");
+        assert_eq!(
+            error.to_string(),
+            "could not expand variable: TEST_UNBRACED_VAR. Variables must be prefixed with one of 'env' followed by '.' e.g. 'env.'"
+        );
+
+        // a `$` that does not start a name is kept
+        let value = json!({ "literal": "a $ b $-", "interpolated": "$ ${env.TEST_UNBRACED_VAR}" });
+        assert_eq!(
+            expansion.expand(&value).expect("expansion must succeed"),
+            json!({ "literal": "a $ b $-", "interpolated": "$ set" })
+        );
+    }
+
+    #[test]
+    fn test_expanded_pointers() {
+        std::env::set_var("TEST_POINTERS_VAR", "secret");
+
+        let expansion = Expansion::builder().supported_mode("env").build();
+        let value = json!({
+            "headers": [{ "value": "Bearer ${env.TEST_POINTERS_VAR}" }],
+            "a/b": { "c": "${env.TEST_POINTERS_VAR}" },
+            "plain": "value",
+        });
+        let (_, pointers) = expansion
+            .expand_with_pointers(&value)
+            .expect("expansion must succeed");
+        assert_eq!(pointers, vec!["/headers/0/value", "/a~1b/c"]);
+    }
+
+    #[test]
+    fn test_type_hints() {
+        std::env::set_var("TEST_HINT_NUMBER_VAR", "8080");
+        std::env::set_var("TEST_HINT_BOOL_VAR", "true");
+
+        let expansion = Expansion::builder().supported_mode("env").build();
+        let value = json!({
+            "string": "${env.TEST_HINT_NUMBER_VAR|string}",
+            "number": "${env.TEST_HINT_NUMBER_VAR|number}",
+            "bool": "${env.TEST_HINT_BOOL_VAR|bool}",
+            "default": "${env.TEST_HINT_UNSET_VAR|number:-4000}",
+            "guessed": "${env.TEST_HINT_BOOL_VAR}",
+        });
+        assert_eq!(
+            expansion.expand(&value).expect("expansion must succeed"),
+            json!({
+                "string": "8080",
+                "number": 8080,
+                "bool": true,
+                "default": 4000,
+                "guessed": true,
+            })
+        );
+
+        for invalid in [
+            "${env.TEST_HINT_BOOL_VAR|number}",
+            "${env.TEST_HINT_NUMBER_VAR|bool}",
+            "${env.TEST_HINT_NUMBER_VAR|integer}",
+            "port ${env.TEST_HINT_NUMBER_VAR|number}",
+        ] {
+            assert!(expansion.expand(&json!({ "value": invalid })).is_err());
+        }
+    }
+
     #[test]
     fn test_dev_mode() {
         let expansion = Expansion::builder().defaults(dev_mode_defaults()).build();
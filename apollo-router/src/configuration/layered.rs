@@ -0,0 +1,145 @@
+//! Configuration made of several files, merged in order
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde_json::Map;
+use serde_json::Value;
+
+use super::ConfigurationError;
+
+/// The configuration files to merge, in order. Directories are replaced by the `.yaml` and `.yml`
+/// files they contain, sorted by name.
+pub(crate) fn configuration_files(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            entries.retain(|entry| {
+                entry.is_file()
+                    && matches!(
+                        entry.extension().and_then(|extension| extension.to_str()),
+                        Some("yaml" | "yml")
+                    )
+            });
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// Merge YAML configuration documents, each one overriding the previous ones: objects are merged
+/// key by key, any other value, lists included, replaces the previous one.
+pub(crate) fn merge_yaml_configurations(
+    documents: &[String],
+) -> Result<String, ConfigurationError> {
+    if let [document] = documents {
+        // keep the document as is, so errors point to its lines
+        return Ok(document.clone());
+    }
+
+    let mut merged = Value::Object(Map::new());
+    for document in documents {
+        if document.trim().is_empty() {
+            continue;
+        }
+        let value: Value = serde_yaml::from_str(document).map_err(|e| {
+            ConfigurationError::InvalidConfiguration {
+                message: "failed to parse yaml",
+                error: e.to_string(),
+            }
+        })?;
+        merge(&mut merged, value);
+    }
+    serde_yaml::to_string(&merged).map_err(|e| ConfigurationError::InvalidConfiguration {
+        message: "failed to merge the configuration files",
+        error: e.to_string(),
+    })
+}
+
+/// Apply the values of `overlay` on `base`, objects are merged recursively
+pub(super) fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_merges_configurations_in_order() {
+        let merged = merge_yaml_configurations(&[
+            r#"
+supergraph:
+  listen: 0.0.0.0:4000
+  introspection: true
+headers:
+  all:
+    request:
+      - propagate:
+          named: x-base
+cors:
+  origins:
+    - https://base.example.com
+"#
+            .to_string(),
+            String::new(),
+            r#"
+supergraph:
+  introspection: false
+cors:
+  origins:
+    - https://team.example.com
+"#
+            .to_string(),
+        ])
+        .unwrap();
+
+        let merged: Value = serde_yaml::from_str(&merged).unwrap();
+        assert_eq!(
+            merged,
+            serde_json::json!({
+                "supergraph": { "listen": "0.0.0.0:4000", "introspection": false },
+                "headers": { "all": { "request": [{ "propagate": { "named": "x-base" } }] } },
+                "cors": { "origins": ["https://team.example.com"] }
+            })
+        );
+    }
+
+    #[test]
+    fn it_lists_the_files_of_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["20-team.yml", "10-base.yaml", "README.md"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        let base = PathBuf::from("/etc/router/router.yaml");
+
+        let files = configuration_files(&[base.clone(), dir.path().to_path_buf()]).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                base,
+                dir.path().join("10-base.yaml"),
+                dir.path().join("20-team.yml")
+            ]
+        );
+    }
+}
@@ -2,8 +2,10 @@
 
 //! Logic for loading configuration in to an object model
 pub(crate) mod cors;
+mod effective;
 mod expansion;
 mod experimental;
+mod layered;
 mod schema;
 pub(crate) mod subgraph;
 #[cfg(test)]
@@ -20,6 +22,7 @@ use std::net::SocketAddr;
 use std::num::NonZeroUsize;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use derivative::Derivative;
 use displaydoc::Display;
@@ -45,8 +48,12 @@ use serde_json::Value;
 use thiserror::Error;
 
 use self::cors::Cors;
+pub(crate) use self::effective::generate_effective_configuration;
+pub(crate) use self::effective::redact;
 use self::expansion::Expansion;
 pub(crate) use self::experimental::print_all_experimental_conf;
+pub(crate) use self::layered::configuration_files;
+pub(crate) use self::layered::merge_yaml_configurations;
 pub(crate) use self::schema::generate_config_schema;
 pub(crate) use self::schema::generate_upgrade;
 use self::subgraph::SubgraphConfiguration;
@@ -105,6 +112,11 @@ pub struct Configuration {
     #[serde(skip)]
     pub(crate) validated_yaml: Option<Value>,
 
+    /// JSON pointers of the values expanded from environment variables or files, redacted
+    /// whenever the configuration is displayed.
+    #[serde(skip)]
+    pub(crate) expanded_pointers: Vec<String>,
+
     /// Configuration options pertaining to the http server component.
     #[serde(default)]
     pub(crate) server: Server,
@@ -113,6 +125,14 @@ pub struct Configuration {
     #[serde(default)]
     pub(crate) health_check: HealthCheck,
 
+    /// Admin API configuration
+    #[serde(default)]
+    pub(crate) experimental_admin: Admin,
+
+    /// Rollback of reloads that make the router fail more often
+    #[serde(default)]
+    pub(crate) experimental_reload_rollback: ReloadRollback,
+
     /// Sandbox configuration
     #[serde(default)]
     pub(crate) sandbox: Sandbox,
@@ -158,6 +178,8 @@ impl<'de> serde::Deserialize<'de> for Configuration {
         struct AdHocConfiguration {
             server: Server,
             health_check: HealthCheck,
+            experimental_admin: Admin,
+            experimental_reload_rollback: ReloadRollback,
             sandbox: Sandbox,
             homepage: Homepage,
             supergraph: Supergraph,
@@ -173,6 +195,8 @@ impl<'de> serde::Deserialize<'de> for Configuration {
         Configuration::builder()
             .server(ad_hoc.server)
             .health_check(ad_hoc.health_check)
+            .experimental_admin(ad_hoc.experimental_admin)
+            .experimental_reload_rollback(ad_hoc.experimental_reload_rollback)
             .sandbox(ad_hoc.sandbox)
             .homepage(ad_hoc.homepage)
             .supergraph(ad_hoc.supergraph)
@@ -205,6 +229,8 @@ impl Configuration {
         server: Option<Server>,
         supergraph: Option<Supergraph>,
         health_check: Option<HealthCheck>,
+        experimental_admin: Option<Admin>,
+        experimental_reload_rollback: Option<ReloadRollback>,
         sandbox: Option<Sandbox>,
         homepage: Option<Homepage>,
         cors: Option<Cors>,
@@ -215,9 +241,12 @@ impl Configuration {
     ) -> Result<Self, ConfigurationError> {
         let conf = Self {
             validated_yaml: Default::default(),
+            expanded_pointers: Default::default(),
             server: server.unwrap_or_default(),
             supergraph: supergraph.unwrap_or_default(),
             health_check: health_check.unwrap_or_default(),
+            experimental_admin: experimental_admin.unwrap_or_default(),
+            experimental_reload_rollback: experimental_reload_rollback.unwrap_or_default(),
             sandbox: sandbox.unwrap_or_default(),
             homepage: homepage.unwrap_or_default(),
             cors: cors.unwrap_or_default(),
@@ -273,6 +302,8 @@ impl Configuration {
         server: Option<Server>,
         supergraph: Option<Supergraph>,
         health_check: Option<HealthCheck>,
+        experimental_admin: Option<Admin>,
+        experimental_reload_rollback: Option<ReloadRollback>,
         sandbox: Option<Sandbox>,
         homepage: Option<Homepage>,
         cors: Option<Cors>,
@@ -283,9 +314,12 @@ impl Configuration {
     ) -> Result<Self, ConfigurationError> {
         let configuration = Self {
             validated_yaml: Default::default(),
+            expanded_pointers: Default::default(),
             server: server.unwrap_or_default(),
             supergraph: supergraph.unwrap_or_else(|| Supergraph::fake_builder().build()),
             health_check: health_check.unwrap_or_else(|| HealthCheck::fake_builder().build()),
+            experimental_admin: experimental_admin.unwrap_or_else(|| Admin::fake_builder().build()),
+            experimental_reload_rollback: experimental_reload_rollback.unwrap_or_default(),
             sandbox: sandbox.unwrap_or_else(|| Sandbox::fake_builder().build()),
             homepage: homepage.unwrap_or_else(|| Homepage::fake_builder().build()),
             cors: cors.unwrap_or_default(),
@@ -352,6 +386,36 @@ impl Configuration {
                 },
             );
         }
+        if self.experimental_admin.enabled {
+            if self.experimental_admin.token.is_none() {
+                return Err(ConfigurationError::InvalidConfiguration {
+                    message: "invalid 'experimental_admin' configuration",
+                    error: "a token is required to enable the admin API".to_string(),
+                });
+            }
+            if self.experimental_admin.listen == self.supergraph.listen
+                || (self.health_check.enabled
+                    && self.experimental_admin.listen == self.health_check.listen)
+            {
+                return Err(ConfigurationError::InvalidConfiguration {
+                    message: "invalid 'experimental_admin' configuration",
+                    error: format!(
+                        "the admin API cannot listen on {}, it must use its own listener",
+                        self.experimental_admin.listen
+                    ),
+                });
+            }
+        }
+        let rollback = &self.experimental_reload_rollback;
+        if rollback.enabled && !(0.0..=1.0).contains(&rollback.max_error_rate_increase) {
+            return Err(ConfigurationError::InvalidConfiguration {
+                message: "invalid 'experimental_reload_rollback' configuration",
+                error: format!(
+                    "max_error_rate_increase must be between 0 and 1, got {}",
+                    rollback.max_error_rate_increase
+                ),
+            });
+        }
 
         Ok(self)
     }
@@ -463,6 +527,9 @@ pub(crate) struct Supergraph {
 
     /// Query planning options
     pub(crate) query_planning: QueryPlanning,
+
+    /// Handling of breaking changes in new schemas
+    pub(crate) experimental_breaking_changes: BreakingChanges,
 }
 
 fn default_defer_support() -> bool {
@@ -478,6 +545,7 @@ impl Supergraph {
         introspection: Option<bool>,
         defer_support: Option<bool>,
         query_planning: Option<QueryPlanning>,
+        experimental_breaking_changes: Option<BreakingChanges>,
     ) -> Self {
         Self {
             listen: listen.unwrap_or_else(default_graphql_listen),
@@ -485,6 +553,7 @@ impl Supergraph {
             introspection: introspection.unwrap_or_else(default_graphql_introspection),
             defer_support: defer_support.unwrap_or_else(default_defer_support),
             query_planning: query_planning.unwrap_or_default(),
+            experimental_breaking_changes: experimental_breaking_changes.unwrap_or_default(),
         }
     }
 }
@@ -499,6 +568,7 @@ impl Supergraph {
         introspection: Option<bool>,
         defer_support: Option<bool>,
         query_planning: Option<QueryPlanning>,
+        experimental_breaking_changes: Option<BreakingChanges>,
     ) -> Self {
         Self {
             listen: listen.unwrap_or_else(test_listen),
@@ -506,6 +576,7 @@ impl Supergraph {
             introspection: introspection.unwrap_or_else(default_graphql_introspection),
             defer_support: defer_support.unwrap_or_else(default_defer_support),
             query_planning: query_planning.unwrap_or_default(),
+            experimental_breaking_changes: experimental_breaking_changes.unwrap_or_default(),
         }
     }
 }
@@ -596,6 +667,32 @@ pub(crate) struct QueryPlanning {
     pub(crate) warmed_up_queries: usize,
 }
 
+/// Breaking changes configuration
+///
+/// The changes between the current and the new API schema are always logged. Breaking changes are
+/// removed types, fields, arguments and enum values, fields that became nullable, and input fields
+/// and arguments that became required.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct BreakingChanges {
+    /// Refuse new schemas with breaking changes affecting operations the router recently
+    /// executed. The router keeps running with the current schema.
+    /// Defaults to false
+    pub(crate) reject: bool,
+    /// How many of the most recently executed operations are checked
+    /// Defaults to 1000
+    pub(crate) recent_operations: usize,
+}
+
+impl Default for BreakingChanges {
+    fn default() -> Self {
+        Self {
+            reject: false,
+            recent_operations: 1000,
+        }
+    }
+}
+
 /// Cache configuration
 #[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields, default)]
@@ -879,6 +976,9 @@ pub(crate) struct HealthCheck {
 
     /// Set to false to disable the health check endpoint
     pub(crate) enabled: bool,
+
+    /// Subgraphs that must be available for the router to be reported as ready
+    pub(crate) experimental_critical_subgraphs: Vec<String>,
 }
 
 fn default_health_check_listen() -> ListenAddr {
@@ -892,10 +992,15 @@ fn default_health_check() -> bool {
 #[buildstructor::buildstructor]
 impl HealthCheck {
     #[builder]
-    pub(crate) fn new(listen: Option<ListenAddr>, enabled: Option<bool>) -> Self {
+    pub(crate) fn new(
+        listen: Option<ListenAddr>,
+        enabled: Option<bool>,
+        experimental_critical_subgraphs: Option<Vec<String>>,
+    ) -> Self {
         Self {
             listen: listen.unwrap_or_else(default_health_check_listen),
             enabled: enabled.unwrap_or_else(default_health_check),
+            experimental_critical_subgraphs: experimental_critical_subgraphs.unwrap_or_default(),
         }
     }
 }
@@ -904,10 +1009,15 @@ impl HealthCheck {
 #[buildstructor::buildstructor]
 impl HealthCheck {
     #[builder]
-    pub(crate) fn fake_new(listen: Option<ListenAddr>, enabled: Option<bool>) -> Self {
+    pub(crate) fn fake_new(
+        listen: Option<ListenAddr>,
+        enabled: Option<bool>,
+        experimental_critical_subgraphs: Option<Vec<String>>,
+    ) -> Self {
         Self {
             listen: listen.unwrap_or_else(test_listen),
             enabled: enabled.unwrap_or_else(default_health_check),
+            experimental_critical_subgraphs: experimental_critical_subgraphs.unwrap_or_default(),
         }
     }
 }
@@ -918,6 +1028,117 @@ impl Default for HealthCheck {
     }
 }
 
+/// Configuration options pertaining to the admin API.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub(crate) struct Admin {
+    /// The socket address and port to listen on
+    /// Defaults to 127.0.0.1:8089
+    pub(crate) listen: ListenAddr,
+
+    /// Set to true to enable the admin API
+    pub(crate) enabled: bool,
+
+    /// Token expected in the `authorization: Bearer <token>` header of admin API requests.
+    /// Required when the admin API is enabled
+    pub(crate) token: Option<String>,
+}
+
+fn default_admin_listen() -> ListenAddr {
+    SocketAddr::from_str("127.0.0.1:8089").unwrap().into()
+}
+
+#[buildstructor::buildstructor]
+impl Admin {
+    #[builder]
+    pub(crate) fn new(
+        listen: Option<ListenAddr>,
+        enabled: Option<bool>,
+        token: Option<String>,
+    ) -> Self {
+        Self {
+            listen: listen.unwrap_or_else(default_admin_listen),
+            enabled: enabled.unwrap_or_default(),
+            token,
+        }
+    }
+}
+
+#[cfg(test)]
+#[buildstructor::buildstructor]
+impl Admin {
+    #[builder]
+    pub(crate) fn fake_new(
+        listen: Option<ListenAddr>,
+        enabled: Option<bool>,
+        token: Option<String>,
+    ) -> Self {
+        Self {
+            listen: listen.unwrap_or_else(test_listen),
+            enabled: enabled.unwrap_or_default(),
+            token,
+        }
+    }
+}
+
+impl Default for Admin {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// Configuration options pertaining to the rollback of reloads.
+///
+/// After a reload, the error rate of the new router is compared to the error rate of the previous
+/// one. If it increased too much, the previous configuration and schema are applied again.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub(crate) struct ReloadRollback {
+    /// Set to true to roll back reloads that increase the error rate
+    pub(crate) enabled: bool,
+
+    /// How long the error rate is watched after a reload
+    /// default: 60s
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub(crate) window: Duration,
+
+    /// Requests handled by the new router before its error rate is considered
+    /// default: 100
+    pub(crate) min_requests: u64,
+
+    /// Increase of the error rate, between 0 and 1, that triggers a rollback. A request fails if
+    /// the router answers with a 5xx status or if one of its subgraph requests failed
+    /// default: 0.2
+    pub(crate) max_error_rate_increase: f64,
+}
+
+#[buildstructor::buildstructor]
+impl ReloadRollback {
+    #[builder]
+    pub(crate) fn new(
+        enabled: Option<bool>,
+        window: Option<Duration>,
+        min_requests: Option<u64>,
+        max_error_rate_increase: Option<f64>,
+    ) -> Self {
+        Self {
+            enabled: enabled.unwrap_or_default(),
+            window: window.unwrap_or_else(|| Duration::from_secs(60)),
+            min_requests: min_requests.unwrap_or(100),
+            max_error_rate_increase: max_error_rate_increase.unwrap_or(0.2),
+        }
+    }
+}
+
+impl Default for ReloadRollback {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
 /// Configuration options pertaining to the http server component.
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -926,16 +1147,55 @@ pub(crate) struct Server {
     /// Experimental limitation of query depth
     /// default: 4096
     pub(crate) experimental_parser_recursion_limit: usize,
+
+    /// Experimental limitation of the request body size, in bytes
+    /// default: no limit
+    pub(crate) experimental_http_max_request_bytes: Option<usize>,
+
+    /// Experimental limitation of the total size of the request headers, in bytes
+    /// default: no limit
+    pub(crate) experimental_http_max_header_bytes: Option<usize>,
+
+    /// Experimental limitation of the size of the serialized request variables, in bytes
+    /// default: no limit
+    pub(crate) experimental_max_variables_bytes: Option<usize>,
+
+    /// Experimental delay between marking the router as down on the health check and no longer
+    /// accepting new connections, when shutting down
+    /// default: 0s
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub(crate) experimental_shutdown_drain_delay: Duration,
+
+    /// Experimental maximum time given to in flight requests to finish when shutting down,
+    /// remaining `@defer` responses are then ended with an error
+    /// default: 30s
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub(crate) experimental_shutdown_grace_period: Duration,
 }
 
 #[buildstructor::buildstructor]
 impl Server {
     #[builder]
     #[allow(clippy::too_many_arguments)] // Used through a builder, not directly
-    pub(crate) fn new(parser_recursion_limit: Option<usize>) -> Self {
+    pub(crate) fn new(
+        parser_recursion_limit: Option<usize>,
+        http_max_request_bytes: Option<usize>,
+        http_max_header_bytes: Option<usize>,
+        max_variables_bytes: Option<usize>,
+        shutdown_drain_delay: Option<Duration>,
+        shutdown_grace_period: Option<Duration>,
+    ) -> Self {
         Self {
             experimental_parser_recursion_limit: parser_recursion_limit
                 .unwrap_or_else(default_parser_recursion_limit),
+            experimental_http_max_request_bytes: http_max_request_bytes,
+            experimental_http_max_header_bytes: http_max_header_bytes,
+            experimental_max_variables_bytes: max_variables_bytes,
+            experimental_shutdown_drain_delay: shutdown_drain_delay.unwrap_or_default(),
+            experimental_shutdown_grace_period: shutdown_grace_period
+                .unwrap_or_else(default_shutdown_grace_period),
         }
     }
 }
@@ -1016,3 +1276,7 @@ fn default_parser_recursion_limit() -> usize {
     // https://docs.rs/apollo-parser/0.2.8/src/apollo_parser/parser/mod.rs.html#368
     4096
 }
+
+fn default_shutdown_grace_period() -> Duration {
+    Duration::from_secs(30)
+}
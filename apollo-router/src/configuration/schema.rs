@@ -108,7 +108,7 @@ pub(crate) fn validate_yaml_configuration(
         }
     }
     log_used_experimental_conf(&yaml);
-    let expanded_yaml = expansion.expand(&yaml)?;
+    let (expanded_yaml, expanded_pointers) = expansion.expand_with_pointers(&yaml)?;
     let parsed_yaml = super::yaml::parse(raw_yaml)?;
     if let Err(errors_it) = schema.validate(&expanded_yaml) {
         // Validation failed, translate the errors into something nice for the user
@@ -262,6 +262,7 @@ pub(crate) fn validate_yaml_configuration(
         });
     }
     config.validated_yaml = Some(expanded_yaml);
+    config.expanded_pointers = expanded_pointers;
     Ok(config)
 }
 
@@ -155,6 +155,38 @@ fn bad_graphql_path_configuration_with_wildcard_as_prefix() {
     assert_eq!(error.to_string(), String::from("invalid 'server.graphql_path' configuration: '/*/test' is invalid, if you need to set a path like '/*/graphql' then specify it as a path parameter with a name, for example '/:my_project_key/graphql'"));
 }
 
+#[test]
+fn bad_admin_configuration() {
+    let error = Configuration::fake_builder()
+        .experimental_admin(Admin::fake_builder().enabled(true).build())
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "invalid 'experimental_admin' configuration: a token is required to enable the admin API"
+    );
+
+    let error = Configuration::fake_builder()
+        .supergraph(
+            Supergraph::fake_builder()
+                .listen(SocketAddr::from_str("127.0.0.1:4000").unwrap())
+                .build(),
+        )
+        .experimental_admin(
+            Admin::fake_builder()
+                .enabled(true)
+                .token("secret")
+                .listen(SocketAddr::from_str("127.0.0.1:4000").unwrap())
+                .build(),
+        )
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "invalid 'experimental_admin' configuration: the admin API cannot listen on 127.0.0.1:4000, it must use its own listener"
+    );
+}
+
 #[test]
 fn unknown_fields() {
     let error = validate_yaml_configuration(
@@ -4,9 +4,11 @@ use std::env;
 use std::ffi::OsStr;
 use std::fmt;
 use std::fmt::Debug;
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::anyhow;
@@ -17,6 +19,9 @@ use clap::CommandFactory;
 use clap::Parser;
 use clap::Subcommand;
 use directories::ProjectDirs;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
 #[cfg(any(feature = "dhat-heap", feature = "dhat-ad-hoc"))]
 use once_cell::sync::OnceCell;
 use url::ParseError;
@@ -24,13 +29,20 @@ use url::Url;
 
 use crate::configuration;
 use crate::configuration::generate_config_schema;
+use crate::configuration::generate_effective_configuration;
 use crate::configuration::generate_upgrade;
+use crate::configuration::Configuration;
 use crate::configuration::ConfigurationError;
+use crate::files;
+use crate::last_known_good::LastKnownGood;
 use crate::plugins::telemetry::reload::init_telemetry;
+use crate::query_planner::offline;
+use crate::query_planner::offline::OperationDocument;
 use crate::router::ConfigurationSource;
 use crate::router::RouterHttpServer;
 use crate::router::SchemaSource;
 use crate::router::ShutdownSource;
+use crate::router_factory;
 use crate::EntitlementSource;
 
 // Note: the dhat-heap and dhat-ad-hoc features should not be both enabled. We name our functions
@@ -101,6 +113,54 @@ extern "C" fn drop_ad_hoc_profiler() {
 enum Commands {
     /// Configuration subcommands.
     Config(ConfigSubcommandArgs),
+
+    /// Print the query plans of operations without starting the router.
+    Plan {
+        /// The location of the supergraph schema to plan with.
+        #[clap(
+            short,
+            long = "supergraph",
+            value_parser,
+            env = "APOLLO_ROUTER_SUPERGRAPH_PATH"
+        )]
+        supergraph_path: PathBuf,
+
+        /// The location of a config, for the query planner settings.
+        #[clap(short, long = "config", value_parser)]
+        config_path: Option<PathBuf>,
+
+        /// The location of a previous supergraph schema to compare the plans with.
+        #[clap(long = "compare", value_parser)]
+        compare_path: Option<PathBuf>,
+
+        /// Print the plans as JSON.
+        #[clap(action = ArgAction::SetTrue, long)]
+        json: bool,
+
+        /// The `.graphql` files containing the operations, or directories to search for them.
+        #[clap(value_parser, required = true)]
+        operations: Vec<PathBuf>,
+    },
+
+    /// Validate operations against a supergraph schema without starting the router.
+    ValidateOperations {
+        /// The location of the supergraph schema to validate against.
+        #[clap(
+            short,
+            long = "supergraph",
+            value_parser,
+            env = "APOLLO_ROUTER_SUPERGRAPH_PATH"
+        )]
+        supergraph_path: PathBuf,
+
+        /// The location of a config, for the limits operations are checked against.
+        #[clap(short, long = "config", value_parser)]
+        config_path: Option<PathBuf>,
+
+        /// The `.graphql` files containing the operations, or directories to search for them.
+        #[clap(value_parser, required = true)]
+        operations: Vec<PathBuf>,
+    },
 }
 
 #[derive(Args, Debug)]
@@ -127,6 +187,40 @@ enum ConfigSubcommand {
     },
     /// List all the available experimental configurations with related GitHub discussion
     Experimental,
+
+    /// Print the configuration the router would run with, secrets masked.
+    Show {
+        /// The location of the config to show.
+        #[clap(value_parser, env = "APOLLO_ROUTER_CONFIG_PATH")]
+        config_path: PathBuf,
+
+        /// Print where each value comes from.
+        #[clap(action = ArgAction::SetTrue, long)]
+        annotate: bool,
+    },
+
+    /// Validate a configuration without starting the router.
+    Validate {
+        /// The locations of the config to validate. Files and directories are merged in order.
+        #[clap(
+            short,
+            long = "config",
+            value_parser,
+            action = ArgAction::Append,
+            required = true,
+            env = "APOLLO_ROUTER_CONFIG_PATH"
+        )]
+        config_path: Vec<PathBuf>,
+
+        /// The location of a supergraph schema to build the query planner with.
+        #[clap(
+            short,
+            long = "supergraph",
+            value_parser,
+            env = "APOLLO_ROUTER_SUPERGRAPH_PATH"
+        )]
+        supergraph_path: Option<PathBuf>,
+    },
 }
 
 /// Options for the router
@@ -152,14 +246,16 @@ pub struct Opt {
     )]
     hot_reload: bool,
 
-    /// Configuration location relative to the project directory.
+    /// Configuration locations relative to the project directory. Files and directories are
+    /// merged in order.
     #[clap(
         short,
         long = "config",
         value_parser,
+        action = ArgAction::Append,
         env = "APOLLO_ROUTER_CONFIG_PATH"
     )]
-    config_path: Option<PathBuf>,
+    config_path: Vec<PathBuf>,
 
     /// Enable development mode.
     #[clap(
@@ -225,6 +321,36 @@ pub struct Opt {
     #[clap(long, default_value = "30s", value_parser = humantime::parse_duration, env)]
     apollo_uplink_timeout: Duration,
 
+    /// Headers sent when fetching the supergraph schema or the configuration from a URL, as
+    /// `name: value`.
+    #[clap(
+        long = "url-header",
+        env = "APOLLO_ROUTER_URL_HEADERS",
+        action = ArgAction::Append,
+        value_delimiter = '\n'
+    )]
+    url_headers: Vec<String>,
+
+    /// The time between polls of the supergraph schema or configuration URL.
+    #[clap(long, default_value = "10s", value_parser = humantime::parse_duration, env = "APOLLO_ROUTER_URL_POLL_INTERVAL")]
+    url_poll_interval: Duration,
+
+    /// The timeout for an http call to the supergraph schema or configuration URL.
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration, env = "APOLLO_ROUTER_URL_TIMEOUT")]
+    url_timeout: Duration,
+
+    /// Directory where the last supergraph schema and entitlement the router ran with are kept.
+    #[clap(
+        long = "last-known-good-dir",
+        env = "APOLLO_ROUTER_LAST_KNOWN_GOOD_DIR"
+    )]
+    last_known_good_dir: Option<PathBuf>,
+
+    /// How long to wait for the supergraph schema and entitlement at startup before using the
+    /// last known good ones.
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration, env = "APOLLO_ROUTER_LAST_KNOWN_GOOD_DEADLINE")]
+    last_known_good_deadline: Duration,
+
     /// Display version and exit.
     #[clap(action = ArgAction::SetTrue, long, short = 'V')]
     pub(crate) version: bool,
@@ -387,6 +513,46 @@ impl Executable {
                 configuration::print_all_experimental_conf();
                 Ok(())
             }
+            Some(Commands::Config(ConfigSubcommandArgs {
+                command:
+                    ConfigSubcommand::Show {
+                        config_path,
+                        annotate,
+                    },
+            })) => {
+                let config_string = std::fs::read_to_string(config_path)?;
+                let output = generate_effective_configuration(&config_string, *annotate)?;
+                print!("{output}");
+                Ok(())
+            }
+            Some(Commands::Config(ConfigSubcommandArgs {
+                command:
+                    ConfigSubcommand::Validate {
+                        config_path,
+                        supergraph_path,
+                    },
+            })) => validate_configuration(config_path, supergraph_path.as_ref()).await,
+            Some(Commands::Plan {
+                supergraph_path,
+                config_path,
+                compare_path,
+                json,
+                operations,
+            }) => {
+                plan_operations(
+                    supergraph_path,
+                    config_path.as_ref(),
+                    compare_path.as_ref(),
+                    operations,
+                    *json,
+                )
+                .await
+            }
+            Some(Commands::ValidateOperations {
+                supergraph_path,
+                config_path,
+                operations,
+            }) => validate_operations(supergraph_path, config_path.as_ref(), operations).await,
             None => Self::inner_start(shutdown, schema, config, entitlement, opt).await,
         };
 
@@ -422,29 +588,54 @@ impl Executable {
         // Enable hot reload when dev mode is enabled
         opt.hot_reload = opt.hot_reload || opt.dev;
 
-        let configuration = match (config, opt.config_path.as_ref()) {
-            (Some(_), Some(_)) => {
+        let configuration = match config {
+            Some(_) if !opt.config_path.is_empty() => {
                 return Err(anyhow!(
                     "--config and APOLLO_ROUTER_CONFIG_PATH cannot be used when a custom configuration source is in use"
                 ));
             }
-            (Some(config), None) => config,
-            _ => match opt.config_path.as_ref().map(|path| {
-                let path = if path.is_relative() {
-                    current_directory.join(path)
-                } else {
-                    path.to_path_buf()
-                };
-
-                ConfigurationSource::File {
-                    path,
-                    watch: opt.hot_reload,
-                    delay: None,
+            Some(config) => config,
+            None if opt.config_path.iter().any(|path| as_url(path).is_some()) => {
+                match opt.config_path.as_slice() {
+                    [path] => ConfigurationSource::Url {
+                        url: as_url(path).expect("checked above"),
+                        headers: parse_headers(&opt.url_headers)?,
+                        poll_interval: opt.url_poll_interval,
+                        timeout: opt.url_timeout,
+                    },
+                    _ => {
+                        return Err(anyhow!(
+                        "a configuration URL cannot be merged with other configuration locations"
+                    ))
+                    }
                 }
-            }) {
-                Some(configuration) => configuration,
-                None => Default::default(),
-            },
+            }
+            None => {
+                let mut paths: Vec<PathBuf> = opt
+                    .config_path
+                    .iter()
+                    .map(|path| {
+                        if path.is_relative() {
+                            current_directory.join(path)
+                        } else {
+                            path.to_path_buf()
+                        }
+                    })
+                    .collect();
+
+                match paths.len() {
+                    0 => Default::default(),
+                    1 if !paths[0].is_dir() => ConfigurationSource::File {
+                        path: paths.remove(0),
+                        watch: opt.hot_reload,
+                        delay: None,
+                    },
+                    _ => ConfigurationSource::Files {
+                        paths,
+                        watch: opt.hot_reload,
+                    },
+                }
+            }
         };
 
         let apollo_telemetry_msg = if opt.anonymous_telemetry_disabled {
@@ -461,6 +652,17 @@ impl Executable {
                 ))
             }
             (Some(source), None, _) => source,
+            (_, Some(supergraph_path), _) if as_url(supergraph_path).is_some() => {
+                tracing::info!("{apollo_router_msg}");
+                tracing::info!("{apollo_telemetry_msg}");
+
+                SchemaSource::Url {
+                    url: as_url(supergraph_path).expect("checked above"),
+                    headers: parse_headers(&opt.url_headers)?,
+                    poll_interval: opt.url_poll_interval,
+                    timeout: opt.url_timeout,
+                }
+            }
             (_, Some(supergraph_path), _) => {
                 tracing::info!("{apollo_router_msg}");
                 tracing::info!("{apollo_telemetry_msg}");
@@ -564,6 +766,12 @@ impl Executable {
             .schema(schema)
             .entitlement(entitlement)
             .shutdown(shutdown.unwrap_or(ShutdownSource::CtrlC))
+            .and_last_known_good(opt.last_known_good_dir.as_ref().map(|directory| {
+                LastKnownGood::new(
+                    current_directory.join(directory),
+                    opt.last_known_good_deadline,
+                )
+            }))
             .start();
         if let Err(err) = router.await {
             tracing::error!("{}", err);
@@ -573,6 +781,167 @@ impl Executable {
     }
 }
 
+async fn validate_configuration(
+    config_paths: &[PathBuf],
+    supergraph_path: Option<&PathBuf>,
+) -> Result<()> {
+    let description = config_paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let documents = configuration::configuration_files(config_paths)
+        .and_then(|files| {
+            files
+                .iter()
+                .map(std::fs::read_to_string)
+                .collect::<std::io::Result<Vec<_>>>()
+        })
+        .map_err(|e| anyhow!("could not read the configuration at {description}: {e}"))?;
+    let configuration: Configuration = configuration::merge_yaml_configurations(&documents)
+        .and_then(|config_string| config_string.parse())
+        .map_err(|e| anyhow!("the configuration at {description} is invalid: {e}"))?;
+    let schema = supergraph_path
+        .map(|path| {
+            std::fs::read_to_string(path).map_err(|e| {
+                anyhow!(
+                    "could not read the supergraph schema at {}: {e}",
+                    path.display()
+                )
+            })
+        })
+        .transpose()?;
+
+    router_factory::validate_configuration(Arc::new(configuration), schema)
+        .await
+        .map_err(|e| anyhow!("the configuration at {description} is invalid: {e}"))?;
+
+    println!("the configuration at {description} is valid");
+    Ok(())
+}
+
+async fn plan_operations(
+    supergraph_path: &PathBuf,
+    config_path: Option<&PathBuf>,
+    compare_path: Option<&PathBuf>,
+    operations: &[PathBuf],
+    json: bool,
+) -> Result<()> {
+    let configuration = read_configuration(config_path)?;
+    let schema = read_supergraph(supergraph_path)?;
+    let previous_schema = compare_path.map(read_supergraph).transpose()?;
+    let documents = read_operations(operations)?;
+
+    let report = offline::plan_operations(
+        Arc::new(configuration),
+        schema,
+        previous_schema,
+        documents,
+        json,
+    )
+    .await
+    .map_err(|e| anyhow!("could not create the query planner: {e}"))?;
+    print!("{}", report.output);
+    if report.failures > 0 {
+        return Err(anyhow!(
+            "{} operations could not be planned",
+            report.failures
+        ));
+    }
+    Ok(())
+}
+
+async fn validate_operations(
+    supergraph_path: &PathBuf,
+    config_path: Option<&PathBuf>,
+    operations: &[PathBuf],
+) -> Result<()> {
+    let configuration = read_configuration(config_path)?;
+    let schema = read_supergraph(supergraph_path)?;
+    let documents = read_operations(operations)?;
+
+    let report = offline::validate_operations(Arc::new(configuration), schema, documents)
+        .await
+        .map_err(|e| anyhow!("could not create the query planner: {e}"))?;
+    print!("{}", report.output);
+    if report.failures > 0 {
+        return Err(anyhow!("{} documents are invalid", report.failures));
+    }
+    Ok(())
+}
+
+/// The configuration at `config_path`, or the default one
+fn read_configuration(config_path: Option<&PathBuf>) -> Result<Configuration> {
+    let config_path = match config_path {
+        Some(config_path) => config_path,
+        None => return Ok(Configuration::default()),
+    };
+    let config_string = std::fs::read_to_string(config_path).map_err(|e| {
+        anyhow!(
+            "could not read the configuration at {}: {e}",
+            config_path.display()
+        )
+    })?;
+    config_string.parse().map_err(|e| {
+        anyhow!(
+            "the configuration at {} is invalid: {e}",
+            config_path.display()
+        )
+    })
+}
+
+fn read_supergraph(path: &PathBuf) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| {
+        anyhow!(
+            "could not read the supergraph schema at {}: {e}",
+            path.display()
+        )
+    })
+}
+
+/// The `.graphql` files at the given paths, searching directories
+fn read_operations(paths: &[PathBuf]) -> Result<Vec<OperationDocument>> {
+    let documents = files::graphql_files(paths)?
+        .into_iter()
+        .map(|path| {
+            let document = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow!("could not read {}: {e}", path.display()))?;
+            Ok(OperationDocument {
+                source: path.display().to_string(),
+                document,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if documents.is_empty() {
+        return Err(anyhow!("no .graphql files found"));
+    }
+    Ok(documents)
+}
+
+/// The URL of a supergraph schema or configuration location, if it is not a path
+fn as_url(location: &Path) -> Option<Url> {
+    location
+        .to_str()
+        .and_then(|location| Url::parse(location).ok())
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Parse `name: value` headers
+fn parse_headers(headers: &[String]) -> Result<HeaderMap> {
+    headers
+        .iter()
+        .map(|header| {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| anyhow!("invalid header '{header}', expected 'name: value'"))?;
+            Ok::<_, anyhow::Error>((
+                HeaderName::from_bytes(name.trim().as_bytes())?,
+                HeaderValue::from_str(value.trim())?,
+            ))
+        })
+        .collect()
+}
+
 fn setup_panic_handler() {
     // Redirect panics to the logs.
     let backtrace_env = std::env::var("RUST_BACKTRACE");
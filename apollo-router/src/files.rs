@@ -19,12 +19,13 @@ const DEFAULT_WATCH_DURATION: Duration = Duration::from_secs(3);
 #[cfg(test)]
 const DEFAULT_WATCH_DURATION: Duration = Duration::from_millis(100);
 
-/// Creates a stream events whenever the file at the path has changes. The stream never terminates
-/// and must be dropped to finish watching.
+/// Creates a stream events whenever the file at the path has changes, or when a file of the
+/// directory at the path is created, removed or changed. The stream never terminates and must be
+/// dropped to finish watching.
 ///
 /// # Arguments
 ///
-/// * `path`: The file to watch
+/// * `path`: The file or directory to watch
 ///
 /// returns: impl Stream<Item=()>
 ///
@@ -37,6 +38,7 @@ fn watch_with_duration(path: &Path, duration: Duration) -> impl Stream<Item = ()
     // supplied path (file), we are going to watch the parent (directory) of the path.
     let config_file_path = PathBuf::from(path);
     let watched_path = config_file_path.clone();
+    let watch_directory = config_file_path.is_dir();
 
     let (mut watch_sender, watch_receiver) = mpsc::channel(1);
     // We can't use the recommended watcher, because there's just too much variation across
@@ -52,12 +54,22 @@ fn watch_with_duration(path: &Path, duration: Duration) -> impl Stream<Item = ()
             Ok(event) => {
                 // The two kinds of events of interest to use are writes to the metadata of a
                 // watched file and changes to the data of a watched file
-                if matches!(
+                let modified = matches!(
                     event.kind,
                     EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime))
                         | EventKind::Modify(ModifyKind::Data(DataChange::Any))
-                ) && event.paths.contains(&watched_path)
-                {
+                );
+                let changed = if watch_directory {
+                    // files can also be added to or removed from a watched directory
+                    (modified || matches!(event.kind, EventKind::Create(_) | EventKind::Remove(_)))
+                        && event
+                            .paths
+                            .iter()
+                            .any(|path| path.parent() == Some(watched_path.as_path()))
+                } else {
+                    modified && event.paths.contains(&watched_path)
+                };
+                if changed {
                     loop {
                         match watch_sender.try_send(()) {
                             Ok(_) => break,
@@ -99,6 +111,31 @@ fn watch_with_duration(path: &Path, duration: Duration) -> impl Stream<Item = ()
         .boxed()
 }
 
+/// The `.graphql` files at the given paths, searching directories recursively. Paths to files
+/// are kept whatever their extension.
+pub(crate) fn graphql_files(paths: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            entries.sort();
+            let (directories, entries): (Vec<_>, Vec<_>) =
+                entries.into_iter().partition(|entry| entry.is_dir());
+            files.extend(
+                entries
+                    .into_iter()
+                    .filter(|entry| entry.extension().map_or(false, |ext| ext == "graphql")),
+            );
+            files.extend(graphql_files(&directories)?);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use std::env::temp_dir;
@@ -126,6 +163,41 @@ pub(crate) mod tests {
         assert!(futures::poll!(watch.next()).is_ready())
     }
 
+    #[test(tokio::test)]
+    async fn directory_watch() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut watch = watch_with_duration(dir.path(), Duration::from_millis(100));
+        assert!(futures::poll!(watch.next()).is_ready());
+
+        std::fs::write(dir.path().join("router.yaml"), "supergraph: {}").unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(futures::poll!(watch.next()).is_ready());
+        std::fs::remove_file(dir.path().join("router.yaml")).unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(futures::poll!(watch.next()).is_ready());
+    }
+
+    #[test]
+    fn it_finds_graphql_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        for file in ["b.graphql", "a.graphql", "notes.md", "nested/c.graphql"] {
+            std::fs::write(dir.path().join(file), "{ me }").unwrap();
+        }
+        let single = dir.path().join("notes.md");
+
+        let files = graphql_files(&[dir.path().to_path_buf(), single.clone()]).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.path().join("a.graphql"),
+                dir.path().join("b.graphql"),
+                dir.path().join("nested/c.graphql"),
+                single,
+            ]
+        );
+    }
+
     pub(crate) fn create_temp_file() -> (PathBuf, File) {
         let path = temp_dir().join(format!("{}", uuid::Uuid::new_v4()));
         let file = std::fs::File::create(&path).unwrap();
@@ -1,14 +1,20 @@
 // With regards to ELv2 licensing, this entire file is license key functionality
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use derivative::Derivative;
 use futures::channel::oneshot;
+use futures::future::BoxFuture;
 use futures::prelude::*;
 use itertools::Itertools;
 use multimap::MultiMap;
 use tokio::sync::mpsc;
+use tokio::sync::watch;
 
 use super::router::ApolloRouterError;
 use crate::configuration::Configuration;
@@ -37,6 +43,16 @@ pub(crate) trait HttpServerFactory {
     ) -> Self::Future
     where
         RF: RouterFactory;
+
+    /// Serve the liveness and readiness checks while the router starts, until its server is
+    /// created with the listener returned by [`StartupHealthHandle::stop`]
+    fn create_startup_health(
+        &self,
+        _configuration: &Configuration,
+        _startup_health: StartupHealth,
+    ) -> BoxFuture<'static, Result<Option<StartupHealthHandle>, ApolloRouterError>> {
+        future::ready(Ok(None)).boxed()
+    }
 }
 
 type MainAndExtraListeners = (Listener, Vec<(ListenAddr, Listener)>);
@@ -66,6 +82,168 @@ pub(crate) struct HttpServerHandle {
 
     /// copied into every client session, to track if there are still running sessions when shutting down
     all_connections_stopped_sender: mpsc::Sender<()>,
+
+    /// Used to drain the client sessions when shutting down
+    shutdown_state: ShutdownState,
+}
+
+/// Draining state of the server, shared with the client sessions.
+///
+/// When shutting down, the server is first reported as down on the health check, then stops
+/// accepting new connections. Once the grace period has elapsed, the remaining sessions are closed.
+/// The sessions of the servers replaced by a reload are drained and closed with the current one.
+#[derive(Clone, Debug)]
+pub(crate) struct ShutdownState {
+    draining: Arc<AtomicBool>,
+    deadline: Arc<watch::Sender<bool>>,
+    drain_delay: Duration,
+    grace_period: Duration,
+    previous: Arc<Mutex<Vec<ShutdownState>>>,
+}
+
+impl ShutdownState {
+    pub(crate) fn new(drain_delay: Duration, grace_period: Duration) -> Self {
+        Self {
+            draining: Default::default(),
+            deadline: Arc::new(watch::channel(false).0),
+            drain_delay,
+            grace_period,
+            previous: Default::default(),
+        }
+    }
+
+    pub(crate) fn from_configuration(configuration: &Configuration) -> Self {
+        Self::new(
+            configuration.server.experimental_shutdown_drain_delay,
+            configuration.server.experimental_shutdown_grace_period,
+        )
+    }
+
+    /// Whether the server is shutting down
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Keep the state of a server replaced by a reload, so its remaining sessions are drained and
+    /// closed when this one shuts down
+    fn carry_over(&self, previous: ShutdownState) {
+        let mut states = self.previous.lock().expect("lock poisoned");
+        states.extend(previous.previous.lock().expect("lock poisoned").drain(..));
+        states.push(previous);
+        // once all the sessions of a server have ended, nothing waits for its deadline anymore
+        states.retain(|state| Arc::strong_count(&state.deadline) > 1);
+    }
+
+    /// Report the server as down, then wait for the drain delay
+    async fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        for previous in self.previous.lock().expect("lock poisoned").iter() {
+            previous.draining.store(true, Ordering::SeqCst);
+        }
+        if !self.drain_delay.is_zero() {
+            tracing::info!("draining for {:?} before shutting down", self.drain_delay);
+            tokio::time::sleep(self.drain_delay).await;
+        }
+    }
+
+    /// Start counting the grace period given to the remaining sessions
+    fn start_deadline(&self) {
+        for previous in self.previous.lock().expect("lock poisoned").iter() {
+            previous.start_deadline();
+        }
+        let deadline = self.deadline.clone();
+        let grace_period = self.grace_period;
+        tokio::task::spawn(async move {
+            tokio::time::sleep(grace_period).await;
+            deadline.send_replace(true);
+        });
+    }
+
+    /// Resolves when the grace period has elapsed. It never resolves if the server is not
+    /// shutting down
+    pub(crate) fn deadline_reached(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.deadline.subscribe();
+        async move {
+            while !*receiver.borrow_and_update() {
+                if receiver.changed().await.is_err() {
+                    futures::future::pending::<()>().await;
+                }
+            }
+        }
+    }
+}
+
+/// What the router is waiting for before it can serve requests, reported by the readiness check
+/// while it starts.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StartupHealth(Arc<Mutex<StartupStatus>>);
+
+#[derive(Debug, Default)]
+struct StartupStatus {
+    waiting_for: Vec<&'static str>,
+    warming_up: bool,
+}
+
+impl StartupHealth {
+    /// The inputs the router did not receive yet: configuration, schema or entitlement
+    pub(crate) fn set_waiting_for(&self, waiting_for: Vec<&'static str>) {
+        self.0.lock().expect("lock poisoned").waiting_for = waiting_for;
+    }
+
+    /// Whether the router is being created and its query planner warmed up
+    pub(crate) fn set_warming_up(&self, warming_up: bool) {
+        self.0.lock().expect("lock poisoned").warming_up = warming_up;
+    }
+
+    pub(crate) fn waiting_for(&self) -> Vec<&'static str> {
+        self.0.lock().expect("lock poisoned").waiting_for.clone()
+    }
+
+    pub(crate) fn warming_up(&self) -> bool {
+        self.0.lock().expect("lock poisoned").warming_up
+    }
+}
+
+/// A handle on the server answering the health checks while the router starts
+pub(crate) struct StartupHealthHandle {
+    listen_address: ListenAddr,
+    shutdown_sender: oneshot::Sender<()>,
+    server_future: BoxFuture<'static, Result<Listener, ApolloRouterError>>,
+}
+
+impl StartupHealthHandle {
+    pub(crate) fn new(
+        listen_address: ListenAddr,
+        shutdown_sender: oneshot::Sender<()>,
+        server_future: BoxFuture<'static, Result<Listener, ApolloRouterError>>,
+    ) -> Self {
+        Self {
+            listen_address,
+            shutdown_sender,
+            server_future,
+        }
+    }
+
+    /// The configured listen address of the health checks
+    pub(crate) fn listen_address(&self) -> &ListenAddr {
+        &self.listen_address
+    }
+
+    /// Stop answering the health checks, and return the listener so the router's server can
+    /// take it over without refusing connections
+    pub(crate) async fn stop(self) -> Result<(ListenAddr, Listener), ApolloRouterError> {
+        if let Err(_err) = self.shutdown_sender.send(()) {
+            tracing::error!("Failed to notify http thread of shutdown")
+        };
+        let listener = self.server_future.await?;
+        Ok((self.listen_address, listener))
+    }
+}
+
+impl Default for ShutdownState {
+    fn default() -> Self {
+        Self::new(Duration::ZERO, Duration::from_secs(30))
+    }
 }
 
 impl HttpServerHandle {
@@ -81,6 +259,7 @@ impl HttpServerHandle {
         graphql_listen_address: Option<ListenAddr>,
         listen_addresses: Vec<ListenAddr>,
         all_connections_stopped_sender: mpsc::Sender<()>,
+        shutdown_state: ShutdownState,
     ) -> Self {
         Self {
             shutdown_sender,
@@ -88,13 +267,17 @@ impl HttpServerHandle {
             graphql_listen_address,
             listen_addresses,
             all_connections_stopped_sender,
+            shutdown_state,
         }
     }
 
     pub(crate) async fn shutdown(self) -> Result<(), ApolloRouterError> {
+        // give load balancers time to see the router as down before refusing connections
+        self.shutdown_state.start_draining().await;
         if let Err(_err) = self.shutdown_sender.send(()) {
             tracing::error!("Failed to notify http thread of shutdown")
         };
+        self.shutdown_state.start_deadline();
         let _listener = self.server_future.await?;
         #[cfg(unix)]
         // listen_addresses includes the main graphql_address
@@ -150,6 +333,8 @@ impl HttpServerHandle {
                 .map(std::string::ToString::to_string)
                 .join(" - ")
         );
+        // the sessions of the previous server keep running, they are closed with the new one
+        handle.shutdown_state.carry_over(self.shutdown_state);
 
         Ok(handle)
     }
@@ -262,6 +447,7 @@ mod tests {
             Some(SocketAddr::from_str("127.0.0.1:0").unwrap().into()),
             Default::default(),
             all_connections_stopped_sender,
+            Default::default(),
         )
         .shutdown()
         .await
@@ -272,6 +458,36 @@ mod tests {
             .expect("Should have been send notification to shutdown");
     }
 
+    #[test(tokio::test)]
+    async fn it_reaches_the_shutdown_deadline() {
+        let shutdown_state = ShutdownState::new(Duration::ZERO, Duration::from_millis(10));
+        let deadline = shutdown_state.deadline_reached();
+        assert!(!shutdown_state.is_draining());
+
+        shutdown_state.start_draining().await;
+        assert!(shutdown_state.is_draining());
+        shutdown_state.start_deadline();
+        tokio::time::timeout(Duration::from_secs(1), deadline)
+            .await
+            .expect("the deadline should be reached");
+    }
+
+    #[test(tokio::test)]
+    async fn it_reaches_the_shutdown_deadline_of_previous_servers() {
+        let previous = ShutdownState::new(Duration::ZERO, Duration::from_millis(10));
+        let deadline = previous.deadline_reached();
+        let session = previous.clone();
+        let shutdown_state = ShutdownState::new(Duration::ZERO, Duration::from_millis(10));
+        shutdown_state.carry_over(previous);
+
+        shutdown_state.start_draining().await;
+        assert!(session.is_draining());
+        shutdown_state.start_deadline();
+        tokio::time::timeout(Duration::from_secs(1), deadline)
+            .await
+            .expect("the deadline of the previous server should be reached");
+    }
+
     #[test(tokio::test)]
     #[cfg(unix)]
     // TODO [igni]: add a check with extra endpoints
@@ -288,6 +504,7 @@ mod tests {
             Some(ListenAddr::UnixSocket(sock)),
             Default::default(),
             all_connections_stopped_sender,
+            Default::default(),
         )
         .shutdown()
         .await
@@ -47,6 +47,7 @@ mod json_ext;
 #[macro_use]
 pub mod plugin;
 
+mod admin;
 pub(crate) mod axum_factory;
 mod cache;
 mod configuration;
@@ -58,12 +59,14 @@ pub mod graphql;
 mod http_ext;
 mod http_server_factory;
 mod introspection;
+mod last_known_good;
 pub mod layers;
 mod orbiter;
 mod plugins;
 mod query_planner;
 mod request;
 mod response;
+mod rollback;
 mod router;
 mod router_factory;
 pub mod services;
@@ -72,12 +75,14 @@ mod state_machine;
 mod test_harness;
 pub mod tracer;
 mod uplink;
+mod urls;
 
 pub use crate::configuration::Configuration;
 pub use crate::configuration::ListenAddr;
 pub use crate::context::Context;
 pub use crate::executable::main;
 pub use crate::executable::Executable;
+pub use crate::last_known_good::LastKnownGood;
 pub use crate::router::ApolloRouterError;
 pub use crate::router::ConfigurationSource;
 pub use crate::router::EntitlementSource;
@@ -102,6 +102,7 @@ where
         schema: String,
         previous_router: Option<&'a Self::RouterFactory>,
         extra_plugins: Option<Vec<(String, Box<dyn DynPlugin>)>>,
+        rolling_back: bool,
     ) -> Result<Self::RouterFactory, BoxError> {
         self.delegate
             .create(
@@ -109,6 +110,7 @@ where
                 schema.clone(),
                 previous_router,
                 extra_plugins,
+                rolling_back,
             )
             .await
             .map(|factory| {
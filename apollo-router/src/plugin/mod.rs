@@ -155,6 +155,21 @@ impl PluginFactory {
     }
 }
 
+tokio::task_local! {
+    static VALIDATING: ();
+}
+
+/// Create plugins in validation mode: they check their configuration as they would for a real
+/// start, but must not connect to external services or start background work.
+pub(crate) async fn validating<F: std::future::Future>(create: F) -> F::Output {
+    VALIDATING.scope((), create).await
+}
+
+/// Whether plugins are being created to validate a configuration, see [`validating`]
+pub(crate) fn is_validating() -> bool {
+    VALIDATING.try_with(|_| ()).is_ok()
+}
+
 // If we wanted to create a custom subset of plugins, this is where we would do it
 /// Get a copy of the registered plugin factories.
 pub(crate) fn plugins() -> impl Iterator<Item = &'static Lazy<PluginFactory>> {
@@ -50,6 +50,16 @@ impl JwksManager {
     pub(super) async fn new(list: Vec<JwksConfig>) -> Result<Self, BoxError> {
         use futures::FutureExt;
 
+        if crate::plugin::is_validating() {
+            // validating the configuration does not download the JWKS
+            let (_drop_signal, _) = oneshot::channel::<()>();
+            return Ok(JwksManager {
+                list,
+                jwks_map: Default::default(),
+                _drop_signal: Arc::new(_drop_signal),
+            });
+        }
+
         let downloads = list
             .iter()
             .cloned()
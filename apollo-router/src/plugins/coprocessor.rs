@@ -3,23 +3,42 @@
 
 use std::collections::HashMap;
 use std::ops::ControlFlow;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
 use std::time::Duration;
+use std::time::Instant;
 
+use apollo_parser::ast;
 use bytes::Bytes;
+use futures::future::ready;
+use futures::future::BoxFuture;
+use futures::stream::once;
+use futures::StreamExt;
 use http::header::HeaderName;
 use http::HeaderMap;
 use http::HeaderValue;
+use http::StatusCode;
+use http::Uri;
+use hyper::client::connect::Connected;
+use hyper::client::connect::Connection;
 use hyper::client::HttpConnector;
 use hyper::Body;
 use hyper_rustls::ConfigBuilderExt;
 use hyper_rustls::HttpsConnector;
+use hyper_rustls::MaybeHttpsStream;
+use rand::Rng;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
 use tower::timeout::TimeoutLayer;
-use tower::util::MapFutureLayer;
 use tower::BoxError;
 use tower::Service;
 use tower::ServiceBuilder;
@@ -27,10 +46,17 @@ use tower::ServiceExt;
 
 use crate::error::Error;
 use crate::layers::async_checkpoint::AsyncCheckpointLayer;
+use crate::layers::map_future_with_request_data::MapFutureWithRequestDataLayer;
 use crate::layers::ServiceBuilderExt;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::plugins::traffic_shaping::circuit_breaker::CircuitBreakerState;
+use crate::plugins::traffic_shaping::circuit_breaker::Target;
+use crate::plugins::traffic_shaping::CircuitBreakerConfig;
+use crate::query_planner::fetch::OperationKind;
+use crate::query_planner::PlanNode;
 use crate::register_plugin;
+use crate::services::execution;
 use crate::services::external::Control;
 use crate::services::external::Externalizable;
 use crate::services::external::PipelineStep;
@@ -38,17 +64,102 @@ use crate::services::external::DEFAULT_EXTERNALIZATION_TIMEOUT;
 use crate::services::external::EXTERNALIZABLE_VERSION;
 use crate::services::router;
 use crate::services::subgraph;
+use crate::services::supergraph;
 use crate::tracer::TraceId;
 
 pub(crate) const EXTERNAL_SPAN_NAME: &str = "external_plugin";
 
-type HTTPClientService = tower::timeout::Timeout<hyper::Client<HttpsConnector<HttpConnector>>>;
+/// Requests to a coprocessor listening on a Unix domain socket are sent to this URL, the
+/// connector ignores the host
+#[cfg(unix)]
+const UNIX_SOCKET_REQUEST_URL: &str = "http://localhost/";
+
+type HTTPClientService =
+    CoprocessorClient<tower::timeout::Timeout<hyper::Client<CoprocessorConnector>>>;
 
 #[async_trait::async_trait]
 impl Plugin for CoprocessorPlugin<HTTPClientService> {
     type Config = Conf;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let mut config = init.config;
+        config.validate()?;
+        let (connector, url) = CoprocessorConnector::new(&config.url)?;
+        config.url = url;
+
+        let mut builder = hyper::Client::builder();
+        builder
+            .http2_only(config.client.http2_only)
+            .pool_idle_timeout(config.client.pool_idle_timeout);
+        if let Some(max_idle) = config.client.pool_max_idle_per_host {
+            builder.pool_max_idle_per_host(max_idle);
+        }
+
+        let http_client = CoprocessorClient::new(
+            ServiceBuilder::new()
+                .layer(TimeoutLayer::new(config.timeout))
+                .service(builder.build(connector)),
+            config.retry.clone(),
+            config.circuit_breaker.as_ref(),
+        );
+
+        CoprocessorPlugin::new(http_client, config, init.supergraph_sdl)
+    }
+
+    fn router_service(&self, service: router::BoxService) -> router::BoxService {
+        self.router_service(service)
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        self.supergraph_service(service)
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        self.execution_service(service)
+    }
+
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        self.subgraph_service(name, service)
+    }
+}
+
+// This macro allows us to use it in our plugin registry!
+// register_plugin takes a group name, and a plugin name.
+//
+// In order to keep the plugin names consistent,
+// we use using the `Reverse domain name notation`
+register_plugin!(
+    "apollo",
+    "coprocessor",
+    CoprocessorPlugin<HTTPClientService>
+);
+
+// -------------------------------------------------------------------------------------------------------
+
+/// Connects to the coprocessor over TCP, with or without TLS, or over a Unix domain socket
+#[derive(Clone)]
+enum CoprocessorConnector {
+    Http(HttpsConnector<HttpConnector>),
+    #[cfg(unix)]
+    Unix(Arc<PathBuf>),
+}
+
+impl CoprocessorConnector {
+    /// The connector for the coprocessor URL, and the URL to send requests to
+    fn new(url: &str) -> Result<(Self, String), BoxError> {
+        if let Some(path) = url.strip_prefix("unix://") {
+            #[cfg(unix)]
+            return Ok((
+                CoprocessorConnector::Unix(Arc::new(PathBuf::from(path))),
+                UNIX_SOCKET_REQUEST_URL.to_string(),
+            ));
+            #[cfg(not(unix))]
+            return Err(format!(
+                "invalid coprocessor url '{url}': Unix domain sockets are not supported on this platform ({path})"
+            )
+            .into());
+        }
+
         let mut http_connector = HttpConnector::new();
         http_connector.set_nodelay(true);
         http_connector.set_keepalive(Some(std::time::Duration::from_secs(60)));
@@ -66,32 +177,197 @@ impl Plugin for CoprocessorPlugin<HTTPClientService> {
             .enable_http2()
             .wrap_connector(http_connector);
 
-        let http_client = ServiceBuilder::new()
-            .layer(TimeoutLayer::new(init.config.timeout))
-            .service(hyper::Client::builder().build(connector));
+        Ok((CoprocessorConnector::Http(connector), url.to_string()))
+    }
+}
 
-        CoprocessorPlugin::new(http_client, init.config, init.supergraph_sdl)
+impl Service<Uri> for CoprocessorConnector {
+    type Response = CoprocessorStream;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self {
+            CoprocessorConnector::Http(connector) => connector.poll_ready(cx),
+            #[cfg(unix)]
+            CoprocessorConnector::Unix(_) => Poll::Ready(Ok(())),
+        }
     }
 
-    fn router_service(&self, service: router::BoxService) -> router::BoxService {
-        self.router_service(service)
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        match self {
+            CoprocessorConnector::Http(connector) => {
+                let connecting = connector.call(uri);
+                Box::pin(async move { Ok(CoprocessorStream::Http(connecting.await?)) })
+            }
+            #[cfg(unix)]
+            CoprocessorConnector::Unix(path) => {
+                let path = path.clone();
+                Box::pin(async move {
+                    Ok(CoprocessorStream::Unix(
+                        tokio::net::UnixStream::connect(path.as_path()).await?,
+                    ))
+                })
+            }
+        }
     }
+}
 
-    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
-        self.subgraph_service(name, service)
+/// A connection to the coprocessor
+enum CoprocessorStream {
+    Http(MaybeHttpsStream<tokio::net::TcpStream>),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+impl Connection for CoprocessorStream {
+    fn connected(&self) -> Connected {
+        match self {
+            CoprocessorStream::Http(stream) => stream.connected(),
+            #[cfg(unix)]
+            CoprocessorStream::Unix(_) => Connected::new(),
+        }
     }
 }
 
-// This macro allows us to use it in our plugin registry!
-// register_plugin takes a group name, and a plugin name.
-//
-// In order to keep the plugin names consistent,
-// we use using the `Reverse domain name notation`
-register_plugin!(
-    "apollo",
-    "coprocessor",
-    CoprocessorPlugin<HTTPClientService>
-);
+impl AsyncRead for CoprocessorStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            CoprocessorStream::Http(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            CoprocessorStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for CoprocessorStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            CoprocessorStream::Http(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            CoprocessorStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            CoprocessorStream::Http(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            CoprocessorStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            CoprocessorStream::Http(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            CoprocessorStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Retries failed coprocessor calls, and stops calling the coprocessor while it keeps failing
+#[derive(Clone)]
+pub(super) struct CoprocessorClient<C> {
+    inner: C,
+    retry: RetryConf,
+    circuit_breaker: Option<Arc<CircuitBreakerState>>,
+}
+
+impl<C> CoprocessorClient<C> {
+    pub(super) fn new(
+        inner: C,
+        retry: RetryConf,
+        circuit_breaker: Option<&CircuitBreakerConfig>,
+    ) -> Self {
+        Self {
+            inner,
+            retry,
+            circuit_breaker: circuit_breaker.map(|conf| {
+                Arc::new(CircuitBreakerState::new(
+                    Target::Coprocessor,
+                    conf.error_rate_threshold,
+                    conf.latency_threshold,
+                    conf.minimum_requests,
+                    conf.window,
+                    conf.open_duration,
+                    conf.half_open_requests,
+                ))
+            }),
+        }
+    }
+}
+
+impl<C> Service<hyper::Request<Body>> for CoprocessorClient<C>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    C::Future: Send,
+{
+    type Response = hyper::Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+        let mut inner = self.inner.clone();
+        let retry = self.retry.clone();
+        let circuit_breaker = self.circuit_breaker.clone();
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let mut backoff = retry.min_backoff;
+            let mut retries = 0;
+            loop {
+                if let Some(circuit_breaker) = &circuit_breaker {
+                    if !circuit_breaker.try_acquire() {
+                        return Err("the coprocessor circuit breaker is open".into());
+                    }
+                }
+
+                let mut request = hyper::Request::builder()
+                    .method(parts.method.clone())
+                    .uri(parts.uri.clone())
+                    .version(parts.version)
+                    .body(Body::from(body.clone()))?;
+                *request.headers_mut() = parts.headers.clone();
+
+                let start = Instant::now();
+                let result = inner.ready().await?.call(request).await;
+                // a coprocessor that is overloaded or restarting may answer with a server error
+                let failed = match &result {
+                    Ok(response) => response.status().is_server_error(),
+                    Err(_) => true,
+                };
+                if let Some(circuit_breaker) = &circuit_breaker {
+                    circuit_breaker.record(!failed, start.elapsed());
+                }
+                if !failed || retries >= retry.attempts {
+                    return result;
+                }
+
+                retries += 1;
+                tracing::info!(monotonic_counter.apollo_router_coprocessor_retry_total = 1u64);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(retry.max_backoff);
+            }
+        })
+    }
+}
 
 // -------------------------------------------------------------------------------------------------------
 
@@ -107,7 +383,7 @@ where
         + Send
         + Sync
         + 'static,
-    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + Sync + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
 {
     http_client: C,
     configuration: Conf,
@@ -121,7 +397,7 @@ where
         + Send
         + Sync
         + 'static,
-    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + Sync + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
 {
     fn new(http_client: C, configuration: Conf, sdl: Arc<String>) -> Result<Self, BoxError> {
         Ok(Self {
@@ -140,6 +416,24 @@ where
         )
     }
 
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        self.configuration.supergraph.as_service(
+            self.http_client.clone(),
+            service,
+            self.configuration.url.clone(),
+            self.sdl.clone(),
+        )
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        self.configuration.execution.as_service(
+            self.http_client.clone(),
+            service,
+            self.configuration.url.clone(),
+            self.sdl.clone(),
+        )
+    }
+
     fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
         self.configuration.subgraph.all.as_service(
             self.http_client.clone(),
@@ -165,6 +459,10 @@ pub(super) struct RouterRequestConf {
     pub(super) path: bool,
     /// Send the method
     pub(super) method: bool,
+    /// When to call the coprocessor
+    pub(super) condition: Condition,
+    /// What happens when calling the coprocessor fails
+    pub(super) on_error: OnError,
 }
 
 /// What information is passed to a router request/response stage
@@ -181,7 +479,73 @@ pub(super) struct RouterResponseConf {
     pub(super) sdl: bool,
     /// Send the HTTP status
     pub(super) status_code: bool,
+    /// When to call the coprocessor
+    pub(super) condition: Condition,
+    /// What happens when calling the coprocessor fails
+    pub(super) on_error: OnError,
+}
+/// What information is passed to a supergraph request stage
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct SupergraphRequestConf {
+    /// Send the headers
+    pub(super) headers: bool,
+    /// Send the context
+    pub(super) context: bool,
+    /// Send the body
+    pub(super) body: bool,
+    /// Send the SDL
+    pub(super) sdl: bool,
+    /// Send the method
+    pub(super) method: bool,
+    /// When to call the coprocessor
+    pub(super) condition: Condition,
+    /// What happens when calling the coprocessor fails
+    pub(super) on_error: OnError,
+}
+
+/// What information is passed to a supergraph or execution response stage
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct SupergraphResponseConf {
+    /// Send the headers
+    pub(super) headers: bool,
+    /// Send the context
+    pub(super) context: bool,
+    /// Send the body
+    pub(super) body: bool,
+    /// Send the SDL
+    pub(super) sdl: bool,
+    /// Send the HTTP status
+    pub(super) status_code: bool,
+    /// When to call the coprocessor
+    pub(super) condition: Condition,
+    /// What happens when calling the coprocessor fails
+    pub(super) on_error: OnError,
 }
+
+/// What information is passed to an execution request stage
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct ExecutionRequestConf {
+    /// Send the headers
+    pub(super) headers: bool,
+    /// Send the context
+    pub(super) context: bool,
+    /// Send the body
+    pub(super) body: bool,
+    /// Send the SDL
+    pub(super) sdl: bool,
+    /// Send the method
+    pub(super) method: bool,
+    /// Send the query plan
+    pub(super) query_plan: bool,
+    /// When to call the coprocessor
+    pub(super) condition: Condition,
+    /// What happens when calling the coprocessor fails
+    pub(super) on_error: OnError,
+}
+
 /// What information is passed to a subgraph request/response stage
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
@@ -198,6 +562,10 @@ pub(super) struct SubgraphRequestConf {
     pub(super) method: bool,
     /// Send the service name
     pub(super) service_name: bool,
+    /// When to call the coprocessor
+    pub(super) condition: Condition,
+    /// What happens when calling the coprocessor fails
+    pub(super) on_error: OnError,
 }
 
 /// What information is passed to a subgraph request/response stage
@@ -214,31 +582,367 @@ pub(super) struct SubgraphResponseConf {
     pub(super) service_name: bool,
     /// Send the http status
     pub(super) status_code: bool,
+    /// When to call the coprocessor
+    pub(super) condition: Condition,
+    /// What happens when calling the coprocessor fails
+    pub(super) on_error: OnError,
+}
+
+/// A stage is enabled when it sends at least one piece of information to the coprocessor, its
+/// condition and failure policy alone do not enable it
+macro_rules! implement_is_enabled {
+    ($conf:ty => $($field:ident),+ $(,)?) => {
+        impl $conf {
+            pub(super) fn is_enabled(&self) -> bool {
+                $(self.$field)||+
+            }
+        }
+    };
+}
+
+implement_is_enabled!(RouterRequestConf => headers, context, body, sdl, path, method);
+implement_is_enabled!(RouterResponseConf => headers, context, body, sdl, status_code);
+implement_is_enabled!(SupergraphRequestConf => headers, context, body, sdl, method);
+implement_is_enabled!(SupergraphResponseConf => headers, context, body, sdl, status_code);
+implement_is_enabled!(ExecutionRequestConf => headers, context, body, sdl, method, query_plan);
+implement_is_enabled!(SubgraphRequestConf => headers, context, body, uri, method, service_name);
+implement_is_enabled!(SubgraphResponseConf => headers, context, body, service_name, status_code);
+
+/// When a stage calls the coprocessor. Every condition that is set must match, the coprocessor
+/// is called for all requests by default
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct Condition {
+    /// Client request headers that must be present, with these exact values
+    pub(super) headers: HashMap<String, String>,
+    /// The kind of operation, not available in the router stage
+    pub(super) operation_kind: Option<OperationKind>,
+    /// Context entries that must be present
+    pub(super) context_keys: Vec<String>,
+    /// Only call the coprocessor for these subgraphs, all of them if empty. Only available in
+    /// the subgraph stage
+    pub(super) subgraphs: Vec<String>,
+    /// The ratio of matching requests sent to the coprocessor, between 0 and 1
+    pub(super) sampling: Option<f64>,
+}
+
+impl Condition {
+    fn validate(&self, stage: &str, in_router: bool, in_subgraph: bool) -> Result<(), BoxError> {
+        if in_router && self.operation_kind.is_some() {
+            return Err(format!(
+                "{stage}: the operation kind is not known in the router stage, use the supergraph stage instead"
+            )
+            .into());
+        }
+        if !in_subgraph && !self.subgraphs.is_empty() {
+            return Err(
+                format!("{stage}: subgraphs can only be used in the subgraph stage").into(),
+            );
+        }
+        if let Some(sampling) = self.sampling {
+            if !(0.0..=1.0).contains(&sampling) {
+                return Err(format!("{stage}: sampling must be between 0 and 1").into());
+            }
+        }
+        Ok(())
+    }
+
+    fn applies_to(&self, service_name: &str) -> bool {
+        self.subgraphs.is_empty() || self.subgraphs.iter().any(|name| name == service_name)
+    }
+
+    /// Whether the coprocessor should be called for a request
+    fn matches(
+        &self,
+        headers: &HeaderMap,
+        operation_kind: impl FnOnce() -> Option<OperationKind>,
+        context: &crate::Context,
+    ) -> bool {
+        self.matches_request(headers, operation_kind) && self.matches_context(context)
+    }
+
+    /// Whether the client request headers and the operation kind match. The operation kind is
+    /// only computed if the condition needs it
+    fn matches_request(
+        &self,
+        headers: &HeaderMap,
+        operation_kind: impl FnOnce() -> Option<OperationKind>,
+    ) -> bool {
+        self.headers.iter().all(|(name, expected)| {
+            headers
+                .get_all(name.as_str())
+                .iter()
+                .any(|value| value.as_bytes() == expected.as_bytes())
+        }) && self
+            .operation_kind
+            .map_or(true, |expected| operation_kind() == Some(expected))
+    }
+
+    /// Whether the context entries are present, then whether this request is sampled
+    fn matches_context(&self, context: &crate::Context) -> bool {
+        self.context_keys
+            .iter()
+            .all(|key| context.contains_key(key.as_str()))
+            && self
+                .sampling
+                .map_or(true, |ratio| rand::thread_rng().gen_bool(ratio))
+    }
+}
+
+/// What happens when calling the coprocessor fails: it can't be reached, times out or returns an
+/// invalid response
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct OnError {
+    /// Stop the client request, or continue as if the stage was not configured
+    pub(super) mode: FailureMode,
+    /// The message of the GraphQL error returned to the client when failing closed. Without a
+    /// message or a status code, the request fails with the coprocessor error
+    pub(super) message: Option<String>,
+    /// The HTTP status code returned to the client when failing closed, 500 by default
+    pub(super) status_code: Option<u16>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum FailureMode {
+    /// Stop the client request
+    #[default]
+    FailClosed,
+    /// Continue with the request or response unchanged
+    FailOpen,
+}
+
+/// How a stage continues after a failed coprocessor call
+enum Failure {
+    /// Continue with the request or response unchanged
+    Open,
+    /// Answer the client with this error
+    Closed(StatusCode, Error),
+}
+
+impl OnError {
+    fn validate(&self, stage: &str) -> Result<(), BoxError> {
+        if let Some(status_code) = self.status_code {
+            StatusCode::from_u16(status_code)
+                .map_err(|_| format!("{stage}: invalid status code {status_code}"))?;
+        }
+        Ok(())
+    }
+
+    /// How to continue after the coprocessor call of a stage failed, or the error to fail with
+    fn handle(&self, error: BoxError, step: &PipelineStep) -> Result<Failure, BoxError> {
+        match self.mode {
+            FailureMode::FailOpen => {
+                tracing::warn!(
+                    "external extensibility: {} stage failed, continuing: {error}",
+                    step.to_string().to_lowercase()
+                );
+                Ok(Failure::Open)
+            }
+            FailureMode::FailClosed if self.message.is_none() && self.status_code.is_none() => {
+                Err(error)
+            }
+            FailureMode::FailClosed => {
+                tracing::error!(
+                    "external extensibility: {} stage error: {error}",
+                    step.to_string().to_lowercase()
+                );
+                let status = self
+                    .status_code
+                    .and_then(|code| StatusCode::from_u16(code).ok())
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                let error = Error::builder()
+                    .message(
+                        self.message
+                            .clone()
+                            .unwrap_or_else(|| format!("coprocessor error: {error}")),
+                    )
+                    .extension_code("COPROCESSOR_ERROR")
+                    .build();
+                Ok(Failure::Closed(status, error))
+            }
+        }
+    }
+}
+
+/// The kind of the operation that will be executed for a GraphQL request
+fn operation_kind(request: &crate::graphql::Request) -> Option<OperationKind> {
+    let document = apollo_parser::Parser::new(request.query.as_deref()?).parse();
+    document
+        .document()
+        .definitions()
+        .filter_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation),
+            _ => None,
+        })
+        .find(|operation| match request.operation_name.as_deref() {
+            Some(name) => operation.name().map_or(false, |n| &*n.text() == name),
+            None => true,
+        })
+        .map(|operation| match operation.operation_type() {
+            Some(kind) if kind.mutation_token().is_some() => OperationKind::Mutation,
+            Some(kind) if kind.subscription_token().is_some() => OperationKind::Subscription,
+            _ => OperationKind::Query,
+        })
 }
 
 /// Configures the externalization plugin
 #[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct Conf {
-    /// The url you'd like to offload processing to
+    /// The url you'd like to offload processing to, `unix:///path/to/socket` for a Unix domain socket
     url: String,
     /// The timeout for external requests
     #[serde(deserialize_with = "humantime_serde::deserialize")]
     #[schemars(with = "String", default = "default_timeout")]
     #[serde(default = "default_timeout")]
     timeout: Duration,
+    /// The HTTP client configuration
+    #[serde(default)]
+    client: ClientConf,
+    /// Retries of failed coprocessor calls
+    #[serde(default)]
+    retry: RetryConf,
+    /// Stop calling the coprocessor while it keeps failing. Disabled by default
+    circuit_breaker: Option<CircuitBreakerConfig>,
     /// The router stage request/response configuration
     #[serde(default)]
     router: RouterStage,
+    /// The supergraph stage request/response configuration
+    #[serde(default)]
+    supergraph: SupergraphStage,
+    /// The execution stage request/response configuration
+    #[serde(default)]
+    execution: ExecutionStage,
     /// The subgraph stage request/response configuration
     #[serde(default)]
     subgraph: SubgraphStages,
 }
 
+impl Conf {
+    fn validate(&self) -> Result<(), BoxError> {
+        let stages = [
+            (
+                "router.request",
+                &self.router.request.condition,
+                &self.router.request.on_error,
+            ),
+            (
+                "router.response",
+                &self.router.response.condition,
+                &self.router.response.on_error,
+            ),
+            (
+                "supergraph.request",
+                &self.supergraph.request.condition,
+                &self.supergraph.request.on_error,
+            ),
+            (
+                "supergraph.response",
+                &self.supergraph.response.condition,
+                &self.supergraph.response.on_error,
+            ),
+            (
+                "execution.request",
+                &self.execution.request.condition,
+                &self.execution.request.on_error,
+            ),
+            (
+                "execution.response",
+                &self.execution.response.condition,
+                &self.execution.response.on_error,
+            ),
+            (
+                "subgraph.all.request",
+                &self.subgraph.all.request.condition,
+                &self.subgraph.all.request.on_error,
+            ),
+            (
+                "subgraph.all.response",
+                &self.subgraph.all.response.condition,
+                &self.subgraph.all.response.on_error,
+            ),
+        ];
+        for (stage, condition, on_error) in stages {
+            condition.validate(
+                stage,
+                stage.starts_with("router."),
+                stage.starts_with("subgraph."),
+            )?;
+            on_error.validate(stage)?;
+        }
+        Ok(())
+    }
+}
+
 fn default_timeout() -> Duration {
     DEFAULT_EXTERNALIZATION_TIMEOUT
 }
 
+/// How connections to the coprocessor are made and reused
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+struct ClientConf {
+    /// Use HTTP/2 only. For `http://` and `unix://` URLs, HTTP/2 is used without negotiation
+    /// (h2c with prior knowledge)
+    http2_only: bool,
+    /// How long an idle connection is kept open for reuse
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String", default = "default_pool_idle_timeout")]
+    pool_idle_timeout: Duration,
+    /// The maximum number of idle connections kept open for reuse, not limited by default
+    pool_max_idle_per_host: Option<usize>,
+}
+
+impl Default for ClientConf {
+    fn default() -> Self {
+        Self {
+            http2_only: false,
+            pool_idle_timeout: default_pool_idle_timeout(),
+            pool_max_idle_per_host: None,
+        }
+    }
+}
+
+fn default_pool_idle_timeout() -> Duration {
+    Duration::from_secs(90)
+}
+
+/// Retries of coprocessor calls that fail to connect, time out or return a server error
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(super) struct RetryConf {
+    /// How many times a failed call is retried, calls are not retried by default
+    pub(super) attempts: u32,
+    /// The delay before the first retry, doubled for each following retry
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String", default = "default_min_backoff")]
+    pub(super) min_backoff: Duration,
+    /// The maximum delay between two retries
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String", default = "default_max_backoff")]
+    pub(super) max_backoff: Duration,
+}
+
+impl Default for RetryConf {
+    fn default() -> Self {
+        Self {
+            attempts: 0,
+            min_backoff: default_min_backoff(),
+            max_backoff: default_max_backoff(),
+        }
+    }
+}
+
+fn default_min_backoff() -> Duration {
+    Duration::from_millis(100)
+}
+
+fn default_max_backoff() -> Duration {
+    Duration::from_secs(1)
+}
+
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
 #[serde(default)]
 pub(super) struct RouterStage {
@@ -264,7 +968,7 @@ impl RouterStage {
             + 'static,
         <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
     {
-        let request_layer = (self.request != Default::default()).then_some({
+        let request_layer = self.request.is_enabled().then_some({
             let request_config = self.request.clone();
             let coprocessor_url = coprocessor_url.clone();
             let http_client = http_client.clone();
@@ -277,6 +981,13 @@ impl RouterStage {
                 let sdl = sdl.clone();
 
                 async move {
+                    if !request_config.condition.matches(
+                        request.router_request.headers(),
+                        || None,
+                        &request.context,
+                    ) {
+                        return Ok(ControlFlow::Continue(request));
+                    }
                     process_router_request_stage(
                         http_client,
                         coprocessor_url,
@@ -295,33 +1006,44 @@ impl RouterStage {
             })
         });
 
-        let response_layer = (self.response != Default::default()).then_some({
+        let response_layer = self.response.is_enabled().then_some({
             let response_config = self.response.clone();
-            MapFutureLayer::new(move |fut| {
-                let sdl = sdl.clone();
-                let coprocessor_url = coprocessor_url.clone();
-                let http_client = http_client.clone();
-                let response_config = response_config.clone();
-
-                async move {
-                    let response: router::Response = fut.await?;
-
-                    process_router_response_stage(
-                        http_client,
-                        coprocessor_url,
-                        sdl,
-                        response,
-                        response_config,
-                    )
-                    .await
-                    .map_err(|error| {
-                        tracing::error!(
-                            "external extensibility: router response stage error: {error}"
-                        );
-                        error
-                    })
-                }
-            })
+            let condition = self.response.condition.clone();
+            MapFutureWithRequestDataLayer::new(
+                move |request: &router::Request| {
+                    condition.matches_request(request.router_request.headers(), || None)
+                },
+                move |matches: bool, fut| {
+                    let sdl = sdl.clone();
+                    let coprocessor_url = coprocessor_url.clone();
+                    let http_client = http_client.clone();
+                    let response_config = response_config.clone();
+
+                    async move {
+                        let response: router::Response = fut.await?;
+                        if !(matches
+                            && response_config.condition.matches_context(&response.context))
+                        {
+                            return Ok(response);
+                        }
+
+                        process_router_response_stage(
+                            http_client,
+                            coprocessor_url,
+                            sdl,
+                            response,
+                            response_config,
+                        )
+                        .await
+                        .map_err(|error| {
+                            tracing::error!(
+                                "external extensibility: router response stage error: {error}"
+                            );
+                            error
+                        })
+                    }
+                },
+            )
         });
 
         fn external_service_span() -> impl Fn(&router::Request) -> tracing::Span + Clone {
@@ -346,6 +1068,326 @@ impl RouterStage {
 
 // -----------------------------------------------------------------------------------------
 
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct SupergraphStage {
+    /// The request configuration
+    pub(super) request: SupergraphRequestConf,
+    /// The response configuration
+    pub(super) response: SupergraphResponseConf,
+}
+
+impl SupergraphStage {
+    pub(crate) fn as_service<C>(
+        &self,
+        http_client: C,
+        service: supergraph::BoxService,
+        coprocessor_url: String,
+        sdl: Arc<String>,
+    ) -> supergraph::BoxService
+    where
+        C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    {
+        graphql_stage_service(
+            http_client,
+            service,
+            coprocessor_url,
+            sdl,
+            self.request
+                .is_enabled()
+                .then(|| self.request.clone().into()),
+            self.response.is_enabled().then(|| self.response.clone()),
+        )
+    }
+}
+
+impl From<SupergraphRequestConf> for GraphQLRequestConf {
+    fn from(conf: SupergraphRequestConf) -> Self {
+        Self {
+            headers: conf.headers,
+            context: conf.context,
+            body: conf.body,
+            sdl: conf.sdl,
+            method: conf.method,
+            query_plan: false,
+            condition: conf.condition,
+            on_error: conf.on_error,
+        }
+    }
+}
+
+impl GraphQLStageRequest for supergraph::Request {
+    const STAGE: &'static str = "supergraph";
+    const SERVICE: &'static str = "supergraph::Request";
+    const REQUEST_STEP: PipelineStep = PipelineStep::SupergraphRequest;
+    const RESPONSE_STEP: PipelineStep = PipelineStep::SupergraphResponse;
+
+    fn supergraph_request(&self) -> &http::Request<crate::graphql::Request> {
+        &self.supergraph_request
+    }
+
+    fn supergraph_request_mut(&mut self) -> &mut http::Request<crate::graphql::Request> {
+        &mut self.supergraph_request
+    }
+
+    fn context(&self) -> &crate::Context {
+        &self.context
+    }
+
+    fn context_mut(&mut self) -> &mut crate::Context {
+        &mut self.context
+    }
+
+    fn operation_kind(&self) -> Option<OperationKind> {
+        operation_kind(self.supergraph_request.body())
+    }
+
+    fn query_plan(&self) -> Option<&PlanNode> {
+        None
+    }
+}
+
+// -----------------------------------------------------------------------------------------
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct ExecutionStage {
+    /// The request configuration
+    pub(super) request: ExecutionRequestConf,
+    /// The response configuration
+    pub(super) response: SupergraphResponseConf,
+}
+
+impl ExecutionStage {
+    pub(crate) fn as_service<C>(
+        &self,
+        http_client: C,
+        service: execution::BoxService,
+        coprocessor_url: String,
+        sdl: Arc<String>,
+    ) -> execution::BoxService
+    where
+        C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    {
+        graphql_stage_service(
+            http_client,
+            service,
+            coprocessor_url,
+            sdl,
+            self.request
+                .is_enabled()
+                .then(|| self.request.clone().into()),
+            self.response.is_enabled().then(|| self.response.clone()),
+        )
+    }
+}
+
+impl From<ExecutionRequestConf> for GraphQLRequestConf {
+    fn from(conf: ExecutionRequestConf) -> Self {
+        Self {
+            headers: conf.headers,
+            context: conf.context,
+            body: conf.body,
+            sdl: conf.sdl,
+            method: conf.method,
+            query_plan: conf.query_plan,
+            condition: conf.condition,
+            on_error: conf.on_error,
+        }
+    }
+}
+
+impl GraphQLStageRequest for execution::Request {
+    const STAGE: &'static str = "execution";
+    const SERVICE: &'static str = "execution::Request";
+    const REQUEST_STEP: PipelineStep = PipelineStep::ExecutionRequest;
+    const RESPONSE_STEP: PipelineStep = PipelineStep::ExecutionResponse;
+
+    fn supergraph_request(&self) -> &http::Request<crate::graphql::Request> {
+        &self.supergraph_request
+    }
+
+    fn supergraph_request_mut(&mut self) -> &mut http::Request<crate::graphql::Request> {
+        &mut self.supergraph_request
+    }
+
+    fn context(&self) -> &crate::Context {
+        &self.context
+    }
+
+    fn context_mut(&mut self) -> &mut crate::Context {
+        &mut self.context
+    }
+
+    fn operation_kind(&self) -> Option<OperationKind> {
+        self.query_plan
+            .query
+            .operation_kind(self.supergraph_request.body().operation_name.as_deref())
+    }
+
+    fn query_plan(&self) -> Option<&PlanNode> {
+        Some(&self.query_plan.root)
+    }
+}
+
+// -----------------------------------------------------------------------------------------
+
+/// What a supergraph or execution request stage sends to the coprocessor, and when
+#[derive(Clone)]
+struct GraphQLRequestConf {
+    headers: bool,
+    context: bool,
+    body: bool,
+    sdl: bool,
+    method: bool,
+    query_plan: bool,
+    condition: Condition,
+    on_error: OnError,
+}
+
+/// A request going through the supergraph or execution stage
+trait GraphQLStageRequest: Send + 'static {
+    /// The stage name used in error logs
+    const STAGE: &'static str;
+    /// The service name recorded on the stage span
+    const SERVICE: &'static str;
+    const REQUEST_STEP: PipelineStep;
+    const RESPONSE_STEP: PipelineStep;
+
+    fn supergraph_request(&self) -> &http::Request<crate::graphql::Request>;
+    fn supergraph_request_mut(&mut self) -> &mut http::Request<crate::graphql::Request>;
+    fn context(&self) -> &crate::Context;
+    fn context_mut(&mut self) -> &mut crate::Context;
+    /// The kind of the operation that will be executed
+    fn operation_kind(&self) -> Option<OperationKind>;
+    /// The query plan, if it was already computed at this stage
+    fn query_plan(&self) -> Option<&PlanNode>;
+}
+
+/// Wraps a supergraph or execution service with the coprocessor request and response stages
+fn graphql_stage_service<C, R>(
+    http_client: C,
+    service: tower::util::BoxService<R, supergraph::Response, BoxError>,
+    coprocessor_url: String,
+    sdl: Arc<String>,
+    request_config: Option<GraphQLRequestConf>,
+    response_config: Option<SupergraphResponseConf>,
+) -> tower::util::BoxService<R, supergraph::Response, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    R: GraphQLStageRequest,
+{
+    let request_layer = request_config.map(|request_config| {
+        let coprocessor_url = coprocessor_url.clone();
+        let http_client = http_client.clone();
+        let sdl = sdl.clone();
+
+        AsyncCheckpointLayer::new(move |request: R| {
+            let request_config = request_config.clone();
+            let coprocessor_url = coprocessor_url.clone();
+            let http_client = http_client.clone();
+            let sdl = sdl.clone();
+
+            async move {
+                if !request_config.condition.matches(
+                    request.supergraph_request().headers(),
+                    || request.operation_kind(),
+                    request.context(),
+                ) {
+                    return Ok(ControlFlow::Continue(request));
+                }
+                process_graphql_request_stage(
+                    http_client,
+                    coprocessor_url,
+                    sdl,
+                    request,
+                    request_config,
+                )
+                .await
+                .map_err(|error| {
+                    tracing::error!(
+                        "external extensibility: {} request stage error: {error}",
+                        R::STAGE
+                    );
+                    error
+                })
+            }
+        })
+    });
+
+    let response_layer = response_config.map(|response_config| {
+        let condition = response_config.condition.clone();
+        MapFutureWithRequestDataLayer::new(
+            move |request: &R| {
+                condition.matches_request(request.supergraph_request().headers(), || {
+                    request.operation_kind()
+                })
+            },
+            move |matches: bool, fut| {
+                let sdl = sdl.clone();
+                let coprocessor_url = coprocessor_url.clone();
+                let http_client = http_client.clone();
+                let response_config = response_config.clone();
+
+                async move {
+                    let response: supergraph::Response = fut.await?;
+                    if !(matches && response_config.condition.matches_context(&response.context)) {
+                        return Ok(response);
+                    }
+
+                    process_supergraph_response_stage(
+                        http_client,
+                        coprocessor_url,
+                        sdl,
+                        response,
+                        response_config,
+                        R::RESPONSE_STEP,
+                    )
+                    .await
+                    .map_err(|error| {
+                        tracing::error!(
+                            "external extensibility: {} response stage error: {error}",
+                            R::STAGE
+                        );
+                        error
+                    })
+                }
+            },
+        )
+    });
+
+    ServiceBuilder::new()
+        .instrument(|_request: &R| {
+            tracing::info_span!(
+                EXTERNAL_SPAN_NAME,
+                "external service" = R::SERVICE,
+                "otel.kind" = "INTERNAL"
+            )
+        })
+        .option_layer(request_layer)
+        .option_layer(response_layer)
+        .buffered()
+        .service(service)
+        .boxed()
+}
+
+// -----------------------------------------------------------------------------------------
+
 /// What information is passed to a subgraph request/response stage
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
@@ -380,7 +1422,9 @@ impl SubgraphStage {
             + 'static,
         <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
     {
-        let request_layer = (self.request != Default::default()).then_some({
+        let request_layer = (self.request.is_enabled()
+            && self.request.condition.applies_to(&service_name))
+        .then_some({
             let request_config = self.request.clone();
             let http_client = http_client.clone();
             let coprocessor_url = coprocessor_url.clone();
@@ -392,6 +1436,13 @@ impl SubgraphStage {
                 let request_config = request_config.clone();
 
                 async move {
+                    if !request_config.condition.matches(
+                        request.supergraph_request.headers(),
+                        || Some(request.operation_kind),
+                        &request.context,
+                    ) {
+                        return Ok(ControlFlow::Continue(request));
+                    }
                     process_subgraph_request_stage(
                         http_client,
                         coprocessor_url,
@@ -410,34 +1461,49 @@ impl SubgraphStage {
             })
         });
 
-        let response_layer = (self.response != Default::default()).then_some({
+        let response_layer = (self.response.is_enabled()
+            && self.response.condition.applies_to(&service_name))
+        .then_some({
             let response_config = self.response.clone();
 
-            MapFutureLayer::new(move |fut| {
-                let http_client = http_client.clone();
-                let coprocessor_url = coprocessor_url.clone();
-                let response_config = response_config.clone();
-                let service_name = service_name.clone();
-
-                async move {
-                    let response: subgraph::Response = fut.await?;
-
-                    process_subgraph_response_stage(
-                        http_client,
-                        coprocessor_url,
-                        service_name,
-                        response,
-                        response_config,
-                    )
-                    .await
-                    .map_err(|error| {
-                        tracing::error!(
-                            "external extensibility: subgraph response stage error: {error}"
-                        );
-                        error
+            let condition = self.response.condition.clone();
+            MapFutureWithRequestDataLayer::new(
+                move |request: &subgraph::Request| {
+                    condition.matches_request(request.supergraph_request.headers(), || {
+                        Some(request.operation_kind)
                     })
-                }
-            })
+                },
+                move |matches: bool, fut| {
+                    let http_client = http_client.clone();
+                    let coprocessor_url = coprocessor_url.clone();
+                    let response_config = response_config.clone();
+                    let service_name = service_name.clone();
+
+                    async move {
+                        let response: subgraph::Response = fut.await?;
+                        if !(matches
+                            && response_config.condition.matches_context(&response.context))
+                        {
+                            return Ok(response);
+                        }
+
+                        process_subgraph_response_stage(
+                            http_client,
+                            coprocessor_url,
+                            service_name,
+                            response,
+                            response_config,
+                        )
+                        .await
+                        .map_err(|error| {
+                            tracing::error!(
+                                "external extensibility: subgraph response stage error: {error}"
+                            );
+                            error
+                        })
+                    }
+                },
+            )
         });
 
         fn external_service_span() -> impl Fn(&subgraph::Request) -> tracing::Span + Clone {
@@ -513,84 +1579,138 @@ where
         method: Some(parts.method.to_string()),
         service_name: None,
         status_code: None,
+        query_plan: None,
     };
 
-    tracing::debug!(?payload, "externalized output");
     request.context.enter_active_request().await;
-    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    let co_processor_result = call_coprocessor(
+        http_client,
+        &coprocessor_url,
+        payload,
+        &PipelineStep::RouterRequest,
+    )
+    .await;
     request.context.leave_active_request().await;
-    tracing::debug!(?co_processor_result, "co-processor returned");
-    let co_processor_output = co_processor_result?;
-
-    validate_coprocessor_output(&co_processor_output, PipelineStep::RouterRequest)?;
-    // unwrap is safe here because validate_coprocessor_output made sure control is available
-    let control = co_processor_output.control.expect("validated above; qed");
 
     // Thirdly, we need to interpret the control flow which may have been
     // updated by our co-processor and decide if we should proceed or stop.
-
-    if matches!(control, Control::Break(_)) {
-        // Ensure the code is a valid http status code
-        let code = control.get_http_status()?;
-
-        let graphql_response: crate::graphql::Response =
-            serde_json::from_value(co_processor_output.body.unwrap_or(serde_json::Value::Null))
-                .unwrap_or_else(|error| {
-                    crate::graphql::Response::builder()
-                        .errors(vec![Error::builder()
-                            .message(format!(
-                                "couldn't deserialize coprocessor output body: {error}"
-                            ))
-                            .extension_code("EXERNAL_DESERIALIZATION_ERROR")
-                            .build()])
-                        .build()
-                });
-
-        let res = router::Response::builder()
-            .errors(graphql_response.errors)
-            .extensions(graphql_response.extensions)
-            .status_code(code)
-            .context(request.context);
-
-        let mut res = match (graphql_response.label, graphql_response.data) {
-            (Some(label), Some(data)) => res.label(label).data(data).build()?,
-            (Some(label), None) => res.label(label).build()?,
-            (None, Some(data)) => res.data(data).build()?,
-            (None, None) => res.build()?,
-        };
-        if let Some(headers) = co_processor_output.headers {
-            *res.response.headers_mut() = internalize_header_map(headers)?;
+    // The replaced "bits" are decoded before the request is touched, so that
+    // a malformed output is handled like a failed call.
+    let co_processor_result = co_processor_result.and_then(|co_processor_output| {
+        // unwrap is safe here because validate_coprocessor_output made sure control is available
+        let control = co_processor_output
+            .control
+            .clone()
+            .expect("validated above; qed");
+        if matches!(control, Control::Break(_)) {
+            return router_break_response(control, co_processor_output, request.context.clone())
+                .map(ControlFlow::Break);
         }
 
-        if let Some(context) = co_processor_output.context {
-            res.context = context;
+        let body = co_processor_output
+            .body
+            .map(|body| serde_json::to_vec(&body))
+            .transpose()?;
+        let headers = co_processor_output
+            .headers
+            .map(internalize_header_map)
+            .transpose()?;
+        Ok(ControlFlow::Continue((
+            body,
+            headers,
+            co_processor_output.context,
+        )))
+    });
+    let (new_body, new_headers, new_context) = match co_processor_result {
+        Ok(ControlFlow::Break(response)) => return Ok(ControlFlow::Break(response)),
+        Ok(ControlFlow::Continue(output)) => output,
+        Err(error) => {
+            return match request_config
+                .on_error
+                .handle(error, &PipelineStep::RouterRequest)?
+            {
+                Failure::Open => {
+                    request.router_request = http::Request::from_parts(parts, Body::from(bytes));
+                    Ok(ControlFlow::Continue(request))
+                }
+                Failure::Closed(status, error) => Ok(ControlFlow::Break(
+                    router::Response::builder()
+                        .errors(vec![error])
+                        .status_code(status)
+                        .context(request.context)
+                        .build()?,
+                )),
+            };
         }
-
-        return Ok(ControlFlow::Break(res));
-    }
+    };
 
     // Finally, process our reply and act on the contents. Our processing logic is
     // that we replace "bits" of our incoming request with the updated bits if they
     // are present in our co_processor_output.
 
-    let new_body = match co_processor_output.body {
-        Some(bytes) => Body::from(serde_json::to_vec(&bytes)?),
+    let new_body = match new_body {
+        Some(new_body) => Body::from(new_body),
         None => Body::from(bytes),
     };
 
     request.router_request = http::Request::from_parts(parts, new_body);
 
-    if let Some(context) = co_processor_output.context {
+    if let Some(context) = new_context {
         request.context = context;
     }
 
-    if let Some(headers) = co_processor_output.headers {
-        *request.router_request.headers_mut() = internalize_header_map(headers)?;
+    if let Some(headers) = new_headers {
+        *request.router_request.headers_mut() = headers;
     }
 
     Ok(ControlFlow::Continue(request))
 }
 
+/// The response to send when a router request stage breaks
+fn router_break_response(
+    control: Control,
+    co_processor_output: Externalizable<serde_json::Value>,
+    context: crate::Context,
+) -> Result<router::Response, BoxError> {
+    // Ensure the code is a valid http status code
+    let code = control.get_http_status()?;
+
+    let graphql_response: crate::graphql::Response =
+        serde_json::from_value(co_processor_output.body.unwrap_or(serde_json::Value::Null))
+            .unwrap_or_else(|error| {
+                crate::graphql::Response::builder()
+                    .errors(vec![Error::builder()
+                        .message(format!(
+                            "couldn't deserialize coprocessor output body: {error}"
+                        ))
+                        .extension_code("EXERNAL_DESERIALIZATION_ERROR")
+                        .build()])
+                    .build()
+            });
+
+    let res = router::Response::builder()
+        .errors(graphql_response.errors)
+        .extensions(graphql_response.extensions)
+        .status_code(code)
+        .context(context);
+
+    let mut res = match (graphql_response.label, graphql_response.data) {
+        (Some(label), Some(data)) => res.label(label).data(data).build()?,
+        (Some(label), None) => res.label(label).build()?,
+        (None, Some(data)) => res.data(data).build()?,
+        (None, None) => res.build()?,
+    };
+    if let Some(headers) = co_processor_output.headers {
+        *res.response.headers_mut() = internalize_header_map(headers)?;
+    }
+
+    if let Some(context) = co_processor_output.context {
+        res.context = context;
+    }
+
+    Ok(res)
+}
+
 async fn process_router_response_stage<C>(
     http_client: C,
     coprocessor_url: String,
@@ -638,43 +1758,487 @@ where
         path: None,
         method: None,
         service_name: None,
+        query_plan: None,
     };
 
     // Second, call our co-processor and get a reply.
-    tracing::debug!(?payload, "externalized output");
     response.context.enter_active_request().await;
-    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    let co_processor_result = call_coprocessor(
+        http_client,
+        &coprocessor_url,
+        payload,
+        &PipelineStep::RouterResponse,
+    )
+    .await;
     response.context.leave_active_request().await;
-    tracing::debug!(?co_processor_result, "co-processor returned");
-    let co_processor_output = co_processor_result?;
 
-    validate_coprocessor_output(&co_processor_output, PipelineStep::RouterResponse)?;
+    // The replaced "bits" are decoded before the response is touched, so that
+    // a malformed output is handled like a failed call.
+    let co_processor_result = co_processor_result.and_then(|co_processor_output| {
+        let body = co_processor_output
+            .body
+            .map(|body| serde_json::to_vec(&body))
+            .transpose()?;
+        let status = co_processor_output
+            .control
+            .map(|control| control.get_http_status())
+            .transpose()?;
+        let headers = co_processor_output
+            .headers
+            .map(internalize_header_map)
+            .transpose()?;
+        Ok((body, status, headers, co_processor_output.context))
+    });
+    let (new_body, new_status, new_headers, new_context) = match co_processor_result {
+        Ok(output) => output,
+        Err(error) => {
+            return match response_config
+                .on_error
+                .handle(error, &PipelineStep::RouterResponse)?
+            {
+                Failure::Open => {
+                    response.response = http::Response::from_parts(parts, Body::from(bytes));
+                    Ok(response)
+                }
+                Failure::Closed(status, error) => router::Response::builder()
+                    .errors(vec![error])
+                    .status_code(status)
+                    .context(response.context)
+                    .build(),
+            };
+        }
+    };
 
     // Third, process our reply and act on the contents. Our processing logic is
     // that we replace "bits" of our incoming response with the updated bits if they
     // are present in our co_processor_output. If they aren't present, just use the
     // bits that we sent to the co_processor.
 
-    let new_body = match co_processor_output.body {
-        Some(bytes) => Body::from(serde_json::to_vec(&bytes)?),
+    let new_body = match new_body {
+        Some(new_body) => Body::from(new_body),
         None => Body::from(bytes),
     };
 
     response.response = http::Response::from_parts(parts, new_body);
-    if let Some(control) = co_processor_output.control {
-        *response.response.status_mut() = control.get_http_status()?
+    if let Some(status) = new_status {
+        *response.response.status_mut() = status;
     }
 
-    if let Some(context) = co_processor_output.context {
+    if let Some(context) = new_context {
         response.context = context;
     }
 
+    if let Some(headers) = new_headers {
+        *response.response.headers_mut() = headers;
+    }
+
+    Ok(response)
+}
+// -----------------------------------------------------------------------------------------------------
+
+async fn process_graphql_request_stage<C, R>(
+    http_client: C,
+    coprocessor_url: String,
+    sdl: Arc<String>,
+    mut request: R,
+    request_config: GraphQLRequestConf,
+) -> Result<ControlFlow<supergraph::Response, R>, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    R: GraphQLStageRequest,
+{
+    // Call into our out of process processor with a body of our body
+    // First, extract the data we need from our request and prepare our
+    // external call. Use our configuration to figure out which data to send.
+    let (parts, body) = std::mem::replace(
+        request.supergraph_request_mut(),
+        http::Request::new(Default::default()),
+    )
+    .into_parts();
+
+    let headers_to_send = request_config
+        .headers
+        .then(|| externalize_header_map(&parts.headers))
+        .transpose()?;
+    let body_to_send = request_config
+        .body
+        .then(|| serde_json::to_value(&body))
+        .transpose()?;
+    let context_to_send = request_config.context.then(|| request.context().clone());
+    let sdl = request_config.sdl.then(|| sdl.clone().to_string());
+    let method = request_config.method.then(|| parts.method.to_string());
+    let query_plan = request
+        .query_plan()
+        .filter(|_| request_config.query_plan)
+        .map(serde_json::to_value)
+        .transpose()?;
+
+    let payload = Externalizable {
+        version: EXTERNALIZABLE_VERSION,
+        stage: R::REQUEST_STEP.to_string(),
+        control: Some(Control::default()),
+        id: TraceId::maybe_new().map(|id| id.to_string()),
+        headers: headers_to_send,
+        body: body_to_send,
+        context: context_to_send,
+        sdl,
+        uri: None,
+        path: None,
+        method,
+        service_name: None,
+        status_code: None,
+        query_plan,
+    };
+
+    let context = request.context();
+    context.enter_active_request().await;
+    let co_processor_result =
+        call_coprocessor(http_client, &coprocessor_url, payload, &R::REQUEST_STEP).await;
+    context.leave_active_request().await;
+
+    // Thirdly, we need to interpret the control flow which may have been
+    // updated by our co-processor and decide if we should proceed or stop.
+    // The replaced "bits" are decoded before the request is touched, so that
+    // a malformed output is handled like a failed call.
+    let co_processor_result = co_processor_result.and_then(|co_processor_output| {
+        // unwrap is safe here because validate_coprocessor_output made sure control is available
+        let control = co_processor_output
+            .control
+            .clone()
+            .expect("validated above; qed");
+        if matches!(control, Control::Break(_)) {
+            return supergraph_break_response(
+                control,
+                co_processor_output,
+                request.context().clone(),
+            )
+            .map(ControlFlow::Break);
+        }
+
+        let body = co_processor_output
+            .body
+            .map(serde_json::from_value::<crate::graphql::Request>)
+            .transpose()?;
+        let headers = co_processor_output
+            .headers
+            .map(internalize_header_map)
+            .transpose()?;
+        Ok(ControlFlow::Continue((
+            body,
+            headers,
+            co_processor_output.context,
+        )))
+    });
+    let (new_body, new_headers, new_context) = match co_processor_result {
+        Ok(ControlFlow::Break(response)) => return Ok(ControlFlow::Break(response)),
+        Ok(ControlFlow::Continue(output)) => output,
+        Err(error) => {
+            return match request_config.on_error.handle(error, &R::REQUEST_STEP)? {
+                Failure::Open => {
+                    *request.supergraph_request_mut() = http::Request::from_parts(parts, body);
+                    Ok(ControlFlow::Continue(request))
+                }
+                Failure::Closed(status, error) => Ok(ControlFlow::Break(
+                    supergraph::Response::builder()
+                        .errors(vec![error])
+                        .status_code(status)
+                        .context(request.context().clone())
+                        .build()?,
+                )),
+            };
+        }
+    };
+
+    // Finally, process our reply and act on the contents. Our processing logic is
+    // that we replace "bits" of our incoming request with the updated bits if they
+    // are present in our co_processor_output. At the execution stage the query plan
+    // was already computed, so only the variables and extensions of a new body have
+    // an effect.
+
+    *request.supergraph_request_mut() = http::Request::from_parts(parts, new_body.unwrap_or(body));
+
+    if let Some(context) = new_context {
+        *request.context_mut() = context;
+    }
+
+    if let Some(headers) = new_headers {
+        *request.supergraph_request_mut().headers_mut() = headers;
+    }
+
+    Ok(ControlFlow::Continue(request))
+}
+
+/// The response to send when a supergraph or execution request stage breaks
+fn supergraph_break_response(
+    control: Control,
+    co_processor_output: Externalizable<serde_json::Value>,
+    context: crate::Context,
+) -> Result<supergraph::Response, BoxError> {
+    // Ensure the code is a valid http status code
+    let code = control.get_http_status()?;
+
+    let graphql_response: crate::graphql::Response =
+        serde_json::from_value(co_processor_output.body.unwrap_or(serde_json::Value::Null))
+            .unwrap_or_else(|error| {
+                crate::graphql::Response::builder()
+                    .errors(vec![Error::builder()
+                        .message(format!(
+                            "couldn't deserialize coprocessor output body: {error}"
+                        ))
+                        .extension_code("EXERNAL_DESERIALIZATION_ERROR")
+                        .build()])
+                    .build()
+            });
+
+    let mut res = supergraph::Response::builder()
+        .errors(graphql_response.errors)
+        .extensions(graphql_response.extensions)
+        .and_label(graphql_response.label)
+        .and_data(graphql_response.data)
+        .status_code(code)
+        .context(context)
+        .build()?;
     if let Some(headers) = co_processor_output.headers {
-        *response.response.headers_mut() = internalize_header_map(headers)?;
+        *res.response.headers_mut() = internalize_header_map(headers)?;
+    }
+
+    if let Some(context) = co_processor_output.context {
+        res.context = context;
+    }
+
+    Ok(res)
+}
+
+/// Process the responses of the supergraph or execution stage: the first one with its headers
+/// and status, then each deferred response
+async fn process_supergraph_response_stage<C>(
+    http_client: C,
+    coprocessor_url: String,
+    sdl: Arc<String>,
+    mut response: supergraph::Response,
+    response_config: SupergraphResponseConf,
+    step: PipelineStep,
+) -> Result<supergraph::Response, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+{
+    // Call into our out of process processor with a body of our body
+    // First, extract the data we need from our response and prepare our
+    // external call. Use our configuration to figure out which data to send.
+    let (mut parts, stream) = response.response.into_parts();
+    let (first, deferred) = stream.into_future().await;
+    let first = match first {
+        Some(first) => first,
+        None => {
+            response.response = http::Response::from_parts(parts, deferred.boxed());
+            return Ok(response);
+        }
+    };
+
+    let headers_to_send = response_config
+        .headers
+        .then(|| externalize_header_map(&parts.headers))
+        .transpose()?;
+    let status_to_send = response_config.status_code.then(|| parts.status.as_u16());
+
+    response.context.enter_active_request().await;
+    let co_processor_result = call_supergraph_response_stage(
+        http_client.clone(),
+        &coprocessor_url,
+        &sdl,
+        &response_config,
+        &step,
+        headers_to_send,
+        status_to_send,
+        &first,
+        &response.context,
+    )
+    .await;
+    response.context.leave_active_request().await;
+    // The replaced "bits" are decoded before the response is touched, so that
+    // a malformed output is handled like a failed call.
+    let co_processor_result = co_processor_result.and_then(|co_processor_output| {
+        let body = co_processor_output
+            .body
+            .map(serde_json::from_value::<crate::graphql::Response>)
+            .transpose()?;
+        let status = co_processor_output
+            .control
+            .map(|control| control.get_http_status())
+            .transpose()?;
+        let headers = co_processor_output
+            .headers
+            .map(internalize_header_map)
+            .transpose()?;
+        Ok((body, status, headers, co_processor_output.context))
+    });
+    let co_processor_output = match co_processor_result {
+        Ok(output) => Some(output),
+        Err(error) => match response_config.on_error.handle(error, &step)? {
+            // the deferred responses are still sent to the coprocessor
+            Failure::Open => None,
+            Failure::Closed(status, error) => {
+                return supergraph::Response::builder()
+                    .errors(vec![error])
+                    .status_code(status)
+                    .context(response.context)
+                    .build();
+            }
+        },
+    };
+
+    // Third, process our reply and act on the contents. Our processing logic is
+    // that we replace "bits" of our incoming response with the updated bits if they
+    // are present in our co_processor_output. If they aren't present, just use the
+    // bits that we sent to the co_processor.
+
+    let mut first = first;
+    if let Some((new_body, new_status, new_headers, new_context)) = co_processor_output {
+        if let Some(new_body) = new_body {
+            first = new_body;
+        }
+
+        if let Some(status) = new_status {
+            parts.status = status;
+        }
+
+        if let Some(context) = new_context {
+            response.context = context;
+        }
+
+        if let Some(headers) = new_headers {
+            parts.headers = headers;
+        }
     }
 
+    // Deferred responses are sent to the coprocessor as they come. Their headers and status
+    // were already sent to the client, so only their body and the context can change.
+    let context = response.context.clone();
+    let deferred = deferred.then(move |deferred_response| {
+        let http_client = http_client.clone();
+        let coprocessor_url = coprocessor_url.clone();
+        let sdl = sdl.clone();
+        let response_config = response_config.clone();
+        let step = step.clone();
+        let context = context.clone();
+
+        async move {
+            let has_next = deferred_response.has_next;
+            let result = call_supergraph_response_stage(
+                http_client,
+                &coprocessor_url,
+                &sdl,
+                &response_config,
+                &step,
+                None,
+                None,
+                &deferred_response,
+                &context,
+            )
+            .await
+            .and_then(|co_processor_output| {
+                if let Some(new_context) = co_processor_output.context {
+                    for entry in new_context.iter() {
+                        context.insert_json_value(entry.key().clone(), entry.value().clone());
+                    }
+                }
+                co_processor_output
+                    .body
+                    .map(serde_json::from_value::<crate::graphql::Response>)
+                    .transpose()
+                    .map_err(BoxError::from)
+            });
+
+            let error = match result {
+                Ok(Some(response)) => return response,
+                Ok(None) => return deferred_response,
+                Err(error) => error,
+            };
+            let error = match response_config.on_error.handle(error, &step) {
+                Ok(Failure::Open) => return deferred_response,
+                Ok(Failure::Closed(_, error)) => error,
+                Err(error) => {
+                    tracing::error!(
+                        "external extensibility: {} stage error: {error}",
+                        step.to_string().to_lowercase()
+                    );
+                    Error::builder()
+                        .message(format!("coprocessor error: {error}"))
+                        .extension_code("EXTERNAL_DEFERRED_RESPONSE_ERROR")
+                        .build()
+                }
+            };
+            let mut response = crate::graphql::Response::builder()
+                .errors(vec![error])
+                .build();
+            response.has_next = has_next;
+            response
+        }
+    });
+
+    response.response =
+        http::Response::from_parts(parts, once(ready(first)).chain(deferred).boxed());
     Ok(response)
 }
+
+#[allow(clippy::too_many_arguments)]
+async fn call_supergraph_response_stage<C>(
+    http_client: C,
+    coprocessor_url: &str,
+    sdl: &Arc<String>,
+    response_config: &SupergraphResponseConf,
+    step: &PipelineStep,
+    headers: Option<HashMap<String, Vec<String>>>,
+    status_code: Option<u16>,
+    body: &crate::graphql::Response,
+    context: &crate::Context,
+) -> Result<Externalizable<serde_json::Value>, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+{
+    let body_to_send = response_config
+        .body
+        .then(|| serde_json::to_value(body))
+        .transpose()?;
+    let context_to_send = response_config.context.then(|| context.clone());
+    let sdl = response_config.sdl.then(|| sdl.to_string());
+
+    let payload = Externalizable {
+        version: EXTERNALIZABLE_VERSION,
+        stage: step.to_string(),
+        control: None,
+        id: TraceId::maybe_new().map(|id| id.to_string()),
+        headers,
+        body: body_to_send,
+        context: context_to_send,
+        status_code,
+        sdl,
+        uri: None,
+        path: None,
+        method: None,
+        service_name: None,
+        query_plan: None,
+    };
+
+    // Second, call our co-processor and get a reply.
+    call_coprocessor(http_client, coprocessor_url, payload, step).await
+}
+
 // -----------------------------------------------------------------------------------------------------
 
 async fn process_subgraph_request_stage<C>(
@@ -725,86 +2289,139 @@ where
         uri,
         method: Some(parts.method.to_string()),
         status_code: None,
+        query_plan: None,
     };
 
-    tracing::debug!(?payload, "externalized output");
     request.context.enter_active_request().await;
-    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    let co_processor_result = call_coprocessor(
+        http_client,
+        &coprocessor_url,
+        payload,
+        &PipelineStep::SubgraphRequest,
+    )
+    .await;
     request.context.leave_active_request().await;
-    tracing::debug!(?co_processor_result, "co-processor returned");
-    let co_processor_output = co_processor_result?;
-    validate_coprocessor_output(&co_processor_output, PipelineStep::SubgraphRequest)?;
-    // unwrap is safe here because validate_coprocessor_output made sure control is available
-    let control = co_processor_output.control.expect("validated above; qed");
 
     // Thirdly, we need to interpret the control flow which may have been
     // updated by our co-processor and decide if we should proceed or stop.
+    // The replaced "bits" are decoded before the request is touched, so that
+    // a malformed output is handled like a failed call.
+    let co_processor_result = co_processor_result.and_then(|co_processor_output| {
+        // unwrap is safe here because validate_coprocessor_output made sure control is available
+        let control = co_processor_output
+            .control
+            .clone()
+            .expect("validated above; qed");
+        if matches!(control, Control::Break(_)) {
+            return subgraph_break_response(control, co_processor_output, request.context.clone())
+                .map(ControlFlow::Break);
+        }
 
-    if matches!(control, Control::Break(_)) {
-        // Ensure the code is a valid http status code
-        let code = control.get_http_status()?;
-
-        let res = {
-            let graphql_response: crate::graphql::Response =
-                serde_json::from_value(co_processor_output.body.unwrap_or(serde_json::Value::Null))
-                    .unwrap_or_else(|error| {
+        let body = co_processor_output
+            .body
+            .map(serde_json::from_value::<crate::graphql::Request>)
+            .transpose()?;
+        let headers = co_processor_output
+            .headers
+            .map(internalize_header_map)
+            .transpose()?;
+        let uri = co_processor_output
+            .uri
+            .map(|uri| uri.parse::<Uri>())
+            .transpose()?;
+        Ok(ControlFlow::Continue((
+            body,
+            headers,
+            uri,
+            co_processor_output.context,
+        )))
+    });
+    let (new_body, new_headers, new_uri, new_context) = match co_processor_result {
+        Ok(ControlFlow::Break(response)) => return Ok(ControlFlow::Break(response)),
+        Ok(ControlFlow::Continue(output)) => output,
+        Err(error) => {
+            return match request_config
+                .on_error
+                .handle(error, &PipelineStep::SubgraphRequest)?
+            {
+                Failure::Open => {
+                    request.subgraph_request = http::Request::from_parts(parts, body);
+                    Ok(ControlFlow::Continue(request))
+                }
+                Failure::Closed(status, error) => Ok(ControlFlow::Break(subgraph::Response {
+                    response: http::Response::builder().status(status).body(
                         crate::graphql::Response::builder()
-                            .errors(vec![Error::builder()
-                                .message(format!(
-                                    "couldn't deserialize coprocessor output body: {error}"
-                                ))
-                                .extension_code("EXERNAL_DESERIALIZATION_ERROR")
-                                .build()])
-                            .build()
-                    });
-
-            let mut http_response = http::Response::builder()
-                .status(code)
-                .body(graphql_response)?;
-            if let Some(headers) = co_processor_output.headers {
-                *http_response.headers_mut() = internalize_header_map(headers)?;
-            }
-
-            let mut subgraph_response = subgraph::Response {
-                response: http_response,
-                context: request.context,
+                            .errors(vec![error])
+                            .build(),
+                    )?,
+                    context: request.context,
+                })),
             };
-
-            if let Some(context) = co_processor_output.context {
-                subgraph_response.context = context;
-            }
-
-            subgraph_response
-        };
-        return Ok(ControlFlow::Break(res));
-    }
+        }
+    };
 
     // Finally, process our reply and act on the contents. Our processing logic is
     // that we replace "bits" of our incoming request with the updated bits if they
     // are present in our co_processor_output.
 
-    let new_body: crate::graphql::Request = match co_processor_output.body {
-        Some(value) => serde_json::from_value(value)?,
-        None => body,
-    };
-
-    request.subgraph_request = http::Request::from_parts(parts, new_body);
+    request.subgraph_request = http::Request::from_parts(parts, new_body.unwrap_or(body));
 
-    if let Some(context) = co_processor_output.context {
+    if let Some(context) = new_context {
         request.context = context;
     }
 
-    if let Some(headers) = co_processor_output.headers {
-        *request.subgraph_request.headers_mut() = internalize_header_map(headers)?;
+    if let Some(headers) = new_headers {
+        *request.subgraph_request.headers_mut() = headers;
     }
 
-    if let Some(uri) = co_processor_output.uri {
-        *request.subgraph_request.uri_mut() = uri.parse()?;
+    if let Some(uri) = new_uri {
+        *request.subgraph_request.uri_mut() = uri;
     }
 
     Ok(ControlFlow::Continue(request))
 }
 
+/// The response to send when a subgraph request stage breaks
+fn subgraph_break_response(
+    control: Control,
+    co_processor_output: Externalizable<serde_json::Value>,
+    context: crate::Context,
+) -> Result<subgraph::Response, BoxError> {
+    // Ensure the code is a valid http status code
+    let code = control.get_http_status()?;
+
+    let graphql_response: crate::graphql::Response =
+        serde_json::from_value(co_processor_output.body.unwrap_or(serde_json::Value::Null))
+            .unwrap_or_else(|error| {
+                crate::graphql::Response::builder()
+                    .errors(vec![Error::builder()
+                        .message(format!(
+                            "couldn't deserialize coprocessor output body: {error}"
+                        ))
+                        .extension_code("EXERNAL_DESERIALIZATION_ERROR")
+                        .build()])
+                    .build()
+            });
+
+    let mut http_response = http::Response::builder()
+        .status(code)
+        .body(graphql_response)?;
+    if let Some(headers) = co_processor_output.headers {
+        *http_response.headers_mut() = internalize_header_map(headers)?;
+    }
+
+    let mut subgraph_response = subgraph::Response {
+        response: http_response,
+        context,
+    };
+
+    if let Some(context) = co_processor_output.context {
+        subgraph_response.context = context;
+    }
+
+    Ok(subgraph_response)
+}
+
 async fn process_subgraph_response_stage<C>(
     http_client: C,
     coprocessor_url: String,
@@ -855,39 +2472,76 @@ where
         path: None,
         method: None,
         service_name,
+        query_plan: None,
     };
 
-    tracing::debug!(?payload, "externalized output");
     response.context.enter_active_request().await;
-    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    let co_processor_result = call_coprocessor(
+        http_client,
+        &coprocessor_url,
+        payload,
+        &PipelineStep::SubgraphResponse,
+    )
+    .await;
     response.context.leave_active_request().await;
-    tracing::debug!(?co_processor_result, "co-processor returned");
-    let co_processor_output = co_processor_result?;
 
-    validate_coprocessor_output(&co_processor_output, PipelineStep::SubgraphResponse)?;
+    // The replaced "bits" are decoded before the response is touched, so that
+    // a malformed output is handled like a failed call.
+    let co_processor_result = co_processor_result.and_then(|co_processor_output| {
+        let body = co_processor_output
+            .body
+            .map(serde_json::from_value::<crate::graphql::Response>)
+            .transpose()?;
+        let status = co_processor_output
+            .control
+            .map(|control| control.get_http_status())
+            .transpose()?;
+        let headers = co_processor_output
+            .headers
+            .map(internalize_header_map)
+            .transpose()?;
+        Ok((body, status, headers, co_processor_output.context))
+    });
+    let (new_body, new_status, new_headers, new_context) = match co_processor_result {
+        Ok(output) => output,
+        Err(error) => {
+            return match response_config
+                .on_error
+                .handle(error, &PipelineStep::SubgraphResponse)?
+            {
+                Failure::Open => {
+                    response.response = http::Response::from_parts(parts, body);
+                    Ok(response)
+                }
+                Failure::Closed(status, error) => {
+                    response.response = http::Response::builder().status(status).body(
+                        crate::graphql::Response::builder()
+                            .errors(vec![error])
+                            .build(),
+                    )?;
+                    Ok(response)
+                }
+            };
+        }
+    };
 
     // Third, process our reply and act on the contents. Our processing logic is
     // that we replace "bits" of our incoming response with the updated bits if they
     // are present in our co_processor_output. If they aren't present, just use the
     // bits that we sent to the co_processor.
 
-    let new_body: crate::graphql::Response = match co_processor_output.body {
-        Some(value) => serde_json::from_value(value)?,
-        None => body,
-    };
-
-    response.response = http::Response::from_parts(parts, new_body);
+    response.response = http::Response::from_parts(parts, new_body.unwrap_or(body));
 
-    if let Some(control) = co_processor_output.control {
-        *response.response.status_mut() = control.get_http_status()?
+    if let Some(status) = new_status {
+        *response.response.status_mut() = status;
     }
 
-    if let Some(context) = co_processor_output.context {
+    if let Some(context) = new_context {
         response.context = context;
     }
 
-    if let Some(headers) = co_processor_output.headers {
-        *response.response.headers_mut() = internalize_header_map(headers)?;
+    if let Some(headers) = new_headers {
+        *response.response.headers_mut() = headers;
     }
 
     Ok(response)
@@ -895,6 +2549,44 @@ where
 
 // -----------------------------------------------------------------------------------------
 
+/// Call the coprocessor for a stage and check its output, recording how long the call took and
+/// whether it failed
+async fn call_coprocessor<C>(
+    http_client: C,
+    coprocessor_url: &str,
+    payload: Externalizable<serde_json::Value>,
+    step: &PipelineStep,
+) -> Result<Externalizable<serde_json::Value>, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+{
+    tracing::debug!(?payload, "externalized output");
+    let start = Instant::now();
+    let co_processor_result = payload
+        .call(http_client, coprocessor_url)
+        .await
+        .and_then(|output| validate_coprocessor_output(&output, step.clone()).map(|_| output));
+    tracing::debug!(?co_processor_result, "co-processor returned");
+
+    let stage = step.to_string();
+    tracing::info!(
+        histogram.apollo_router_coprocessor_duration_seconds = start.elapsed().as_secs_f64(),
+        stage = %stage,
+    );
+    if co_processor_result.is_err() {
+        tracing::info!(
+            monotonic_counter.apollo_router_coprocessor_failures_total = 1u64,
+            stage = %stage,
+        );
+    }
+    co_processor_result
+}
+
 fn validate_coprocessor_output(
     co_processor_output: &Externalizable<serde_json::Value>,
     expected_step: PipelineStep,
@@ -1,7 +1,10 @@
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
     use std::sync::Arc;
+    use std::time::Duration;
 
     use futures::future::BoxFuture;
     use http::header::ACCEPT;
@@ -15,12 +18,18 @@ mod tests {
     use mime::TEXT_HTML;
     use serde_json::json;
     use tower::BoxError;
+    use tower::Service;
     use tower::ServiceExt;
 
     use super::super::coprocessor::*;
+    use crate::plugin::test::MockExecutionService;
     use crate::plugin::test::MockHttpClientService;
     use crate::plugin::test::MockRouterService;
     use crate::plugin::test::MockSubgraphService;
+    use crate::plugin::test::MockSupergraphService;
+    use crate::plugins::traffic_shaping::CircuitBreakerConfig;
+    use crate::query_planner::fetch::OperationKind;
+    use crate::services::execution;
     use crate::services::external::Externalizable;
     use crate::services::external::PipelineStep;
     use crate::services::external::EXTERNALIZABLE_VERSION;
@@ -100,6 +109,8 @@ mod tests {
                 sdl: true,
                 path: false,
                 method: false,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
             response: Default::default(),
         };
@@ -159,6 +170,8 @@ mod tests {
                 sdl: true,
                 path: false,
                 method: false,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
             response: Default::default(),
         };
@@ -218,6 +231,8 @@ mod tests {
                 sdl: true,
                 path: false,
                 method: false,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
             response: Default::default(),
         };
@@ -276,6 +291,8 @@ mod tests {
                 uri: false,
                 method: false,
                 service_name: false,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
             response: Default::default(),
         };
@@ -338,6 +355,8 @@ mod tests {
                 uri: false,
                 method: false,
                 service_name: false,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
             response: Default::default(),
         };
@@ -467,6 +486,8 @@ mod tests {
                 uri: false,
                 method: false,
                 service_name: false,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
             response: Default::default(),
         };
@@ -535,6 +556,8 @@ mod tests {
                 body: true,
                 service_name: false,
                 status_code: false,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
         };
 
@@ -646,6 +669,8 @@ mod tests {
                 sdl: true,
                 path: true,
                 method: true,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
             response: Default::default(),
         };
@@ -764,6 +789,8 @@ mod tests {
                 sdl: true,
                 path: true,
                 method: true,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
             response: Default::default(),
         };
@@ -892,6 +919,8 @@ mod tests {
                 sdl: true,
                 path: true,
                 method: true,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
             response: Default::default(),
         };
@@ -978,6 +1007,8 @@ mod tests {
                 body: true,
                 sdl: true,
                 status_code: false,
+                condition: Default::default(),
+                on_error: Default::default(),
             },
             request: Default::default(),
         };
@@ -1099,6 +1130,319 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn external_plugin_supergraph_request() {
+        let supergraph_stage = SupergraphStage {
+            request: SupergraphRequestConf {
+                headers: false,
+                context: true,
+                body: true,
+                sdl: false,
+                method: false,
+                condition: Default::default(),
+                on_error: Default::default(),
+            },
+            response: Default::default(),
+        };
+
+        let mut mock_supergraph_service = MockSupergraphService::new();
+
+        mock_supergraph_service
+            .expect_call()
+            .returning(|req: supergraph::Request| {
+                // The variables and the context should have changed
+                assert_eq!(
+                    req.supergraph_request.body().variables.get("id"),
+                    Some(&serde_json_bytes::json!("2"))
+                );
+                assert!(req.context.get::<_, bool>("authorized").unwrap().unwrap());
+
+                Ok(supergraph::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .context(req.context)
+                    .build()
+                    .unwrap())
+            });
+
+        let mock_http_client = mock_with_callback(move |req: hyper::Request<Body>| {
+            Box::pin(async {
+                let deserialized_request: Externalizable<serde_json::Value> =
+                    serde_json::from_slice(&hyper::body::to_bytes(req.into_body()).await.unwrap())
+                        .unwrap();
+
+                assert_eq!(
+                    PipelineStep::SupergraphRequest.to_string(),
+                    deserialized_request.stage
+                );
+                assert_eq!(
+                    json!({ "id": "1" }),
+                    deserialized_request.body.unwrap()["variables"]
+                );
+
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r##"{
+                                "version": 1,
+                                "stage": "SupergraphRequest",
+                                "control": "continue",
+                                "body": {
+                                    "query": "query Me($id: ID) { me(id: $id) { name } }",
+                                    "variables": { "id": "2" }
+                                },
+                                "context": {
+                                    "entries": {
+                                        "authorized": true
+                                    }
+                                }
+                            }"##,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = supergraph_stage.as_service(
+            mock_http_client,
+            mock_supergraph_service.boxed(),
+            "http://test".to_string(),
+            Arc::new("".to_string()),
+        );
+
+        let request = supergraph::Request::fake_builder()
+            .query("query Me($id: ID) { me(id: $id) { name } }")
+            .variable("id", "1")
+            .build()
+            .unwrap();
+
+        let mut response = service.oneshot(request).await.unwrap();
+        assert_eq!(
+            serde_json_bytes::json!({ "test": 1234_u32 }),
+            response.next_response().await.unwrap().data.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn external_plugin_execution_request_controlflow_break() {
+        let execution_stage = ExecutionStage {
+            request: ExecutionRequestConf {
+                headers: false,
+                context: false,
+                body: false,
+                sdl: false,
+                method: false,
+                query_plan: true,
+                condition: Default::default(),
+                on_error: Default::default(),
+            },
+            response: Default::default(),
+        };
+
+        // This will never be called because the coprocessor breaks.
+        let mock_execution_service = MockExecutionService::new();
+
+        let mock_http_client = mock_with_callback(move |req: hyper::Request<Body>| {
+            Box::pin(async {
+                let deserialized_request: Externalizable<serde_json::Value> =
+                    serde_json::from_slice(&hyper::body::to_bytes(req.into_body()).await.unwrap())
+                        .unwrap();
+
+                assert_eq!(
+                    PipelineStep::ExecutionRequest.to_string(),
+                    deserialized_request.stage
+                );
+                assert!(deserialized_request.query_plan.is_some());
+
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r##"{
+                                "version": 1,
+                                "stage": "ExecutionRequest",
+                                "control": {
+                                    "break": 403
+                                },
+                                "body": {
+                                    "errors": [{ "message": "not authorized" }]
+                                }
+                            }"##,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = execution_stage.as_service(
+            mock_http_client,
+            mock_execution_service.boxed(),
+            "http://test".to_string(),
+            Arc::new("".to_string()),
+        );
+
+        let request = execution::Request::fake_builder().build();
+
+        let mut response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            "not authorized",
+            response.next_response().await.unwrap().errors[0]
+                .message
+                .as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn external_plugin_supergraph_response() {
+        let supergraph_stage = SupergraphStage {
+            request: Default::default(),
+            response: SupergraphResponseConf {
+                headers: true,
+                context: false,
+                body: true,
+                sdl: false,
+                status_code: false,
+                condition: Default::default(),
+                on_error: Default::default(),
+            },
+        };
+
+        let mut mock_supergraph_service = MockSupergraphService::new();
+
+        mock_supergraph_service
+            .expect_call()
+            .returning(|req: supergraph::Request| {
+                Ok(supergraph::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .context(req.context)
+                    .build()
+                    .unwrap())
+            });
+
+        let mock_http_client = mock_with_callback(move |res: hyper::Request<Body>| {
+            Box::pin(async {
+                let deserialized_response: Externalizable<serde_json::Value> =
+                    serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await.unwrap())
+                        .unwrap();
+
+                assert_eq!(
+                    PipelineStep::SupergraphResponse.to_string(),
+                    deserialized_response.stage
+                );
+                assert_eq!(
+                    json!({ "data": { "test": 1234_u32 } }),
+                    deserialized_response.body.unwrap()
+                );
+
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r##"{
+                                "version": 1,
+                                "stage": "SupergraphResponse",
+                                "headers": {
+                                    "x-audited": ["true"]
+                                },
+                                "body": {
+                                    "data": { "test": 42 }
+                                }
+                            }"##,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = supergraph_stage.as_service(
+            mock_http_client,
+            mock_supergraph_service.boxed(),
+            "http://test".to_string(),
+            Arc::new("".to_string()),
+        );
+
+        let request = supergraph::Request::fake_builder().build().unwrap();
+
+        let mut response = service.oneshot(request).await.unwrap();
+        assert_eq!(
+            response.response.headers().get("x-audited").unwrap(),
+            "true"
+        );
+        assert_eq!(
+            serde_json_bytes::json!({ "test": 42_u32 }),
+            response.next_response().await.unwrap().data.unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    async fn coprocessor_over_unix_socket(http2_only: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("coprocessor.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(
+                    hyper::server::conn::Http::new()
+                        .http2_only(http2_only)
+                        .serve_connection(
+                            stream,
+                            hyper::service::service_fn(|_: hyper::Request<Body>| async {
+                                Ok::<_, BoxError>(hyper::Response::new(Body::from(
+                                    r##"{
+                                        "version": 1,
+                                        "stage": "RouterRequest",
+                                        "control": { "break": 200 },
+                                        "body": { "data": { "fromCoprocessor": true } }
+                                    }"##,
+                                )))
+                            }),
+                        ),
+                );
+            }
+        });
+
+        let config = serde_json::json!({
+            "coprocessor": {
+                "url": format!("unix://{}", socket.display()),
+                "client": {
+                    "http2_only": http2_only,
+                    "pool_idle_timeout": "10s",
+                    "pool_max_idle_per_host": 2
+                },
+                "router": {
+                    "request": {
+                        "headers": true
+                    }
+                }
+            }
+        });
+        let router = crate::TestHarness::builder()
+            .configuration_json(config)
+            .unwrap()
+            .build_router()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let response = router
+            .oneshot(request.try_into().unwrap())
+            .await
+            .unwrap()
+            .response;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json!({ "data": { "fromCoprocessor": true } }),
+            serde_json::from_slice::<serde_json::Value>(
+                &hyper::body::to_bytes(response.into_body()).await.unwrap()
+            )
+            .unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn external_plugin_over_unix_socket() {
+        coprocessor_over_unix_socket(false).await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn external_plugin_over_unix_socket_with_h2c() {
+        coprocessor_over_unix_socket(true).await;
+    }
+
     #[test]
     fn it_externalizes_headers() {
         // Build our expected HashMap
@@ -1163,6 +1507,438 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[tokio::test]
+    async fn external_plugin_router_condition_on_operation_kind_is_rejected() {
+        let config = serde_json::json!({
+            "coprocessor": {
+                "url": "http://127.0.0.1:8081",
+                "router": {
+                    "request": {
+                        "body": true,
+                        "condition": {
+                            "operation_kind": "mutation"
+                        }
+                    }
+                }
+            }
+        });
+        assert!(crate::TestHarness::builder()
+            .configuration_json(config)
+            .unwrap()
+            .build_router()
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn external_plugin_supergraph_request_condition() {
+        let supergraph_stage = SupergraphStage {
+            request: SupergraphRequestConf {
+                body: true,
+                condition: Condition {
+                    operation_kind: Some(OperationKind::Mutation),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            response: Default::default(),
+        };
+
+        fn mock_supergraph_service() -> supergraph::BoxService {
+            let mut mock_supergraph_service = MockSupergraphService::new();
+            mock_supergraph_service
+                .expect_call()
+                .returning(|req: supergraph::Request| {
+                    Ok(supergraph::Response::builder()
+                        .data(json!({ "test": 1234_u32 }))
+                        .context(req.context)
+                        .build()
+                        .unwrap())
+                });
+            mock_supergraph_service.boxed()
+        }
+
+        // queries are not sent to the coprocessor
+        let mock_http_client = mock_with_callback(|_: hyper::Request<Body>| {
+            panic!("the coprocessor should not be called for queries")
+        });
+        let service = supergraph_stage.as_service(
+            mock_http_client,
+            mock_supergraph_service(),
+            "http://test".to_string(),
+            Arc::new("".to_string()),
+        );
+        let request = supergraph::Request::fake_builder()
+            .query("query Me { me { name } }")
+            .build()
+            .unwrap();
+        service.oneshot(request).await.unwrap();
+
+        // mutations are
+        let mock_http_client = mock_with_callback(|req: hyper::Request<Body>| {
+            Box::pin(async {
+                let deserialized_request: Externalizable<serde_json::Value> =
+                    serde_json::from_slice(&hyper::body::to_bytes(req.into_body()).await.unwrap())
+                        .unwrap();
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "version": 1,
+                            "stage": "SupergraphRequest",
+                            "control": "continue",
+                            "body": deserialized_request.body.unwrap(),
+                            "context": { "entries": { "audited": true } },
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap())
+            })
+        });
+        let service = supergraph_stage.as_service(
+            mock_http_client,
+            mock_supergraph_service(),
+            "http://test".to_string(),
+            Arc::new("".to_string()),
+        );
+        let request = supergraph::Request::fake_builder()
+            .query("query Me { me { name } } mutation Update { update }")
+            .operation_name("Update")
+            .build()
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        assert!(response.context.get::<_, bool>("audited").unwrap().unwrap());
+    }
+
+    #[tokio::test]
+    async fn external_plugin_subgraph_request_condition() {
+        let subgraph_stage = SubgraphStage {
+            request: SubgraphRequestConf {
+                body: true,
+                condition: Condition {
+                    headers: [("x-audit".to_string(), "true".to_string())].into(),
+                    subgraphs: vec!["accounts".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            response: Default::default(),
+        };
+
+        for service_name in ["products", "accounts"] {
+            let mut mock_subgraph_service = MockSubgraphService::new();
+            mock_subgraph_service
+                .expect_call()
+                .returning(|req: subgraph::Request| {
+                    Ok(subgraph::Response::builder()
+                        .data(json!({ "test": 1234_u32 }))
+                        .errors(Vec::new())
+                        .extensions(crate::json_ext::Object::new())
+                        .context(req.context)
+                        .build())
+                });
+            // not called for products, and for accounts without the header
+            let mock_http_client = mock_with_callback(|_: hyper::Request<Body>| {
+                panic!("the coprocessor should not be called")
+            });
+
+            let service = subgraph_stage.as_service(
+                mock_http_client,
+                mock_subgraph_service.boxed(),
+                "http://test".to_string(),
+                service_name.to_string(),
+            );
+            let request = subgraph::Request::fake_builder().build();
+            service.oneshot(request).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn external_plugin_stage_without_payload_is_disabled() {
+        let subgraph_stage = SubgraphStage {
+            request: SubgraphRequestConf {
+                condition: Condition {
+                    subgraphs: vec!["accounts".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            response: SubgraphResponseConf {
+                on_error: OnError {
+                    mode: FailureMode::FailOpen,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        };
+
+        let mut mock_subgraph_service = MockSubgraphService::new();
+        mock_subgraph_service
+            .expect_call()
+            .returning(|req: subgraph::Request| {
+                Ok(subgraph::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .errors(Vec::new())
+                    .extensions(crate::json_ext::Object::new())
+                    .context(req.context)
+                    .build())
+            });
+        let mock_http_client = mock_with_callback(|_: hyper::Request<Body>| {
+            panic!("the coprocessor should not be called")
+        });
+
+        let service = subgraph_stage.as_service(
+            mock_http_client,
+            mock_subgraph_service.boxed(),
+            "http://test".to_string(),
+            "accounts".to_string(),
+        );
+        let request = subgraph::Request::fake_builder().build();
+        service.oneshot(request).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn external_plugin_subgraph_request_fail_open() {
+        let subgraph_stage = SubgraphStage {
+            request: SubgraphRequestConf {
+                body: true,
+                on_error: OnError {
+                    mode: FailureMode::FailOpen,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            response: Default::default(),
+        };
+
+        let mut mock_subgraph_service = MockSubgraphService::new();
+        mock_subgraph_service
+            .expect_call()
+            .returning(|req: subgraph::Request| {
+                // the request is unchanged
+                assert_eq!(
+                    req.subgraph_request.body().query.as_deref(),
+                    Some("query { me { name } }")
+                );
+                Ok(subgraph::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .errors(Vec::new())
+                    .extensions(crate::json_ext::Object::new())
+                    .context(req.context)
+                    .build())
+            });
+
+        let mock_http_client = mock_with_callback(|_: hyper::Request<Body>| {
+            Box::pin(async { Ok(hyper::Response::builder().body(Body::from("{")).unwrap()) })
+        });
+
+        let service = subgraph_stage.as_service(
+            mock_http_client,
+            mock_subgraph_service.boxed(),
+            "http://test".to_string(),
+            "my_subgraph_service_name".to_string(),
+        );
+
+        let request = subgraph::Request::fake_builder()
+            .subgraph_request(
+                http::Request::builder()
+                    .body(
+                        crate::graphql::Request::builder()
+                            .query("query { me { name } }")
+                            .build(),
+                    )
+                    .unwrap(),
+            )
+            .build();
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(
+            serde_json_bytes::json!({ "test": 1234_u32 }),
+            response.response.into_body().data.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn external_plugin_subgraph_request_fail_open_on_invalid_output() {
+        let subgraph_stage = SubgraphStage {
+            request: SubgraphRequestConf {
+                body: true,
+                on_error: OnError {
+                    mode: FailureMode::FailOpen,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            response: Default::default(),
+        };
+
+        let mut mock_subgraph_service = MockSubgraphService::new();
+        mock_subgraph_service
+            .expect_call()
+            .returning(|req: subgraph::Request| {
+                // the request is unchanged
+                assert_eq!(
+                    req.subgraph_request.body().query.as_deref(),
+                    Some("query { me { name } }")
+                );
+                Ok(subgraph::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .errors(Vec::new())
+                    .extensions(crate::json_ext::Object::new())
+                    .context(req.context)
+                    .build())
+            });
+
+        // the output is valid, but its body is not a GraphQL request
+        let mock_http_client = mock_with_callback(|_: hyper::Request<Body>| {
+            Box::pin(async {
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r##"{
+                                "version": 1,
+                                "stage": "SubgraphRequest",
+                                "control": "continue",
+                                "body": 12
+                            }"##,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = subgraph_stage.as_service(
+            mock_http_client,
+            mock_subgraph_service.boxed(),
+            "http://test".to_string(),
+            "my_subgraph_service_name".to_string(),
+        );
+
+        let request = subgraph::Request::fake_builder()
+            .subgraph_request(
+                http::Request::builder()
+                    .body(
+                        crate::graphql::Request::builder()
+                            .query("query { me { name } }")
+                            .build(),
+                    )
+                    .unwrap(),
+            )
+            .build();
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(
+            serde_json_bytes::json!({ "test": 1234_u32 }),
+            response.response.into_body().data.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn external_plugin_supergraph_request_fail_closed() {
+        let supergraph_stage = SupergraphStage {
+            request: SupergraphRequestConf {
+                body: true,
+                on_error: OnError {
+                    mode: FailureMode::FailClosed,
+                    message: Some("the request could not be authorized".to_string()),
+                    status_code: Some(503),
+                },
+                ..Default::default()
+            },
+            response: Default::default(),
+        };
+
+        // the supergraph service is not called
+        let mock_supergraph_service = MockSupergraphService::new();
+
+        let mock_http_client = mock_with_callback(|_: hyper::Request<Body>| {
+            Box::pin(async {
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r##"{ "version": 2, "stage": "SupergraphRequest", "control": "continue" }"##,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = supergraph_stage.as_service(
+            mock_http_client,
+            mock_supergraph_service.boxed(),
+            "http://test".to_string(),
+            Arc::new("".to_string()),
+        );
+
+        let request = supergraph::Request::fake_builder()
+            .query("query { me { name } }")
+            .build()
+            .unwrap();
+        let mut response = service.oneshot(request).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.response.status());
+        let errors = response.next_response().await.unwrap().errors;
+        assert_eq!(errors[0].message, "the request could not be authorized");
+        assert_eq!(
+            errors[0].extensions.get("code").unwrap(),
+            &serde_json_bytes::json!("COPROCESSOR_ERROR")
+        );
+    }
+
+    #[tokio::test]
+    async fn coprocessor_client_retries_and_opens_the_circuit() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let inner_calls = calls.clone();
+        // fails twice then succeeds, then keeps failing
+        let inner = tower::service_fn(move |_: hyper::Request<Body>| {
+            let call = inner_calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let status = if call == 2 {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                Ok::<_, BoxError>(
+                    hyper::Response::builder()
+                        .status(status)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+            }
+        });
+        let circuit_breaker = CircuitBreakerConfig {
+            minimum_requests: Some(6),
+            ..Default::default()
+        };
+        let mut client = CoprocessorClient::new(
+            inner,
+            RetryConf {
+                attempts: 2,
+                min_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(2),
+            },
+            Some(&circuit_breaker),
+        );
+        let request = || {
+            hyper::Request::builder()
+                .uri("http://test")
+                .body(Body::from("{}"))
+                .unwrap()
+        };
+
+        let response = client.ready().await.unwrap().call(request()).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+
+        // all the retries fail
+        let response = client.ready().await.unwrap().call(request()).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        assert_eq!(6, calls.load(Ordering::SeqCst));
+
+        // 5 failures out of 6 calls open the circuit
+        let error = client
+            .ready()
+            .await
+            .unwrap()
+            .call(request())
+            .await
+            .unwrap_err();
+        assert_eq!("the coprocessor circuit breaker is open", error.to_string());
+        assert_eq!(6, calls.load(Ordering::SeqCst));
+    }
+
     #[allow(clippy::type_complexity)]
     fn mock_with_callback(
         callback: fn(
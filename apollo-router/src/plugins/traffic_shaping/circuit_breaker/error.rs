@@ -0,0 +1,23 @@
+//! Error types
+
+use std::error;
+use std::fmt;
+
+/// The circuit breaker is open and the request was not sent.
+#[derive(Debug, Default)]
+pub(crate) struct CircuitOpen;
+
+impl CircuitOpen {
+    /// Construct a new CircuitOpen error
+    pub(crate) fn new() -> Self {
+        CircuitOpen {}
+    }
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("circuit breaker is open, the subgraph is considered unavailable")
+    }
+}
+
+impl error::Error for CircuitOpen {}
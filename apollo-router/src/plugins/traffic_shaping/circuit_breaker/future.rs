@@ -0,0 +1,72 @@
+//! Future types
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Instant;
+
+use futures::ready;
+use pin_project_lite::pin_project;
+
+use super::error::CircuitOpen;
+use super::state::CircuitBreakerState;
+use crate::services::subgraph;
+
+pin_project! {
+    /// [`CircuitBreaker`] response future
+    ///
+    /// [`CircuitBreaker`]: super::CircuitBreaker
+    #[project = ResponseFutureProj]
+    pub(crate) enum ResponseFuture<F> {
+        Rejected,
+        Called {
+            #[pin]
+            response: F,
+            state: Arc<CircuitBreakerState>,
+            start: Instant,
+        },
+    }
+}
+
+impl<F> ResponseFuture<F> {
+    pub(crate) fn rejected() -> Self {
+        ResponseFuture::Rejected
+    }
+
+    pub(crate) fn called(response: F, state: Arc<CircuitBreakerState>) -> Self {
+        ResponseFuture::Called {
+            response,
+            state,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl<F, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<subgraph::Response, E>>,
+    E: Into<tower::BoxError>,
+{
+    type Output = Result<subgraph::Response, tower::BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Rejected => Poll::Ready(Err(CircuitOpen::new().into())),
+            ResponseFutureProj::Called {
+                response,
+                state,
+                start,
+            } => {
+                let result = ready!(response.poll(cx));
+                // Transport errors, timeouts and 5xx responses all count against the subgraph
+                let success =
+                    matches!(&result, Ok(res) if !res.response.status().is_server_error());
+                state.record(success, start.elapsed());
+
+                Poll::Ready(result.map_err(Into::into))
+            }
+        }
+    }
+}
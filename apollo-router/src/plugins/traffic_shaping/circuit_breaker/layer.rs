@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use tower::Layer;
+
+use super::state::CircuitBreakerState;
+use super::CircuitBreaker;
+
+/// Stops sending requests to the underlying service while it is failing.
+///
+/// Clones of the layer share the same breaker state.
+#[derive(Debug, Clone)]
+pub(crate) struct CircuitBreakerLayer {
+    state: Arc<CircuitBreakerState>,
+}
+
+impl CircuitBreakerLayer {
+    /// Create a new circuit breaker layer.
+    pub(crate) fn new(state: CircuitBreakerState) -> Self {
+        CircuitBreakerLayer {
+            state: Arc::new(state),
+        }
+    }
+
+    /// Whether requests are currently rejected by the breaker.
+    pub(crate) fn is_open(&self) -> bool {
+        self.state.rejects_requests()
+    }
+
+    /// Open the circuit until the open duration has elapsed.
+    pub(crate) fn trip(&self) {
+        self.state.trip()
+    }
+
+    /// Let probe requests through again, if the circuit is open.
+    pub(crate) fn half_open(&self) {
+        self.state.half_open()
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreaker<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        CircuitBreaker {
+            inner: service,
+            state: self.state.clone(),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Circuit breaker for subgraph requests. The state is also used around the coprocessor.
+//!
+//! Once the ratio of failed (or too slow) requests over a window crosses a threshold, the circuit
+//! opens and requests fail immediately without reaching the subgraph. After the open duration,
+//! the circuit becomes half-open and lets a few probe requests through: if they succeed the circuit
+//! closes again, otherwise it stays open for another period.
+
+pub(crate) mod error;
+pub(crate) mod future;
+mod layer;
+mod state;
+
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use tower::Service;
+
+use self::future::ResponseFuture;
+pub(crate) use self::layer::CircuitBreakerLayer;
+pub(crate) use self::state::CircuitBreakerState;
+pub(crate) use self::state::Target;
+use crate::services::subgraph;
+
+#[derive(Debug, Clone)]
+pub(crate) struct CircuitBreaker<S> {
+    inner: S,
+    state: Arc<CircuitBreakerState>,
+}
+
+impl<S> Service<subgraph::Request> for CircuitBreaker<S>
+where
+    S: Service<subgraph::Request, Response = subgraph::Response>,
+    S::Error: Into<tower::BoxError>,
+{
+    type Response = subgraph::Response;
+    type Error = tower::BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: subgraph::Request) -> Self::Future {
+        if self.state.try_acquire() {
+            ResponseFuture::called(self.inner.call(request), self.state.clone())
+        } else {
+            ResponseFuture::rejected()
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+const DEFAULT_ERROR_RATE_THRESHOLD: f64 = 0.5;
+const DEFAULT_MINIMUM_REQUESTS: u64 = 20;
+const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+const DEFAULT_OPEN_DURATION: Duration = Duration::from_secs(30);
+const DEFAULT_HALF_OPEN_REQUESTS: u32 = 1;
+
+/// What a circuit breaker protects, used in logs and metrics
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Target {
+    Subgraph(String),
+    Coprocessor,
+}
+
+impl Target {
+    /// The subgraph name, recorded as a structured field in logs
+    fn subgraph_name(&self) -> Option<&str> {
+        match self {
+            Target::Subgraph(name) => Some(name),
+            Target::Coprocessor => None,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Subgraph(name) => write!(f, "subgraph '{name}'"),
+            Target::Coprocessor => write!(f, "the coprocessor"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Status {
+    /// Requests go through, outcomes are recorded
+    Closed,
+    /// Requests are rejected until the open duration has elapsed
+    Open { since: Instant },
+    /// A limited number of probe requests go through to check if the subgraph recovered
+    HalfOpen { since: Instant, in_flight: u32 },
+}
+
+impl Status {
+    fn as_metric(&self) -> u64 {
+        match self {
+            Status::Closed => 0,
+            Status::HalfOpen { .. } => 1,
+            Status::Open { .. } => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    status: Status,
+    window_start: Instant,
+    requests: u64,
+    failures: u64,
+}
+
+/// Shared circuit breaker state for one subgraph, or the coprocessor.
+#[derive(Debug)]
+pub(crate) struct CircuitBreakerState {
+    target: Target,
+    error_rate_threshold: f64,
+    latency_threshold: Option<Duration>,
+    minimum_requests: u64,
+    window: Duration,
+    open_duration: Duration,
+    half_open_requests: u32,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreakerState {
+    pub(crate) fn new(
+        target: Target,
+        error_rate_threshold: Option<f64>,
+        latency_threshold: Option<Duration>,
+        minimum_requests: Option<u64>,
+        window: Option<Duration>,
+        open_duration: Option<Duration>,
+        half_open_requests: Option<u32>,
+    ) -> Self {
+        Self {
+            target,
+            error_rate_threshold: error_rate_threshold.unwrap_or(DEFAULT_ERROR_RATE_THRESHOLD),
+            latency_threshold,
+            minimum_requests: minimum_requests.unwrap_or(DEFAULT_MINIMUM_REQUESTS),
+            window: window.unwrap_or(DEFAULT_WINDOW),
+            open_duration: open_duration.unwrap_or(DEFAULT_OPEN_DURATION),
+            half_open_requests: half_open_requests
+                .unwrap_or(DEFAULT_HALF_OPEN_REQUESTS)
+                .max(1),
+            inner: Mutex::new(Inner {
+                status: Status::Closed,
+                window_start: Instant::now(),
+                requests: 0,
+                failures: 0,
+            }),
+        }
+    }
+
+    pub(crate) fn status(&self) -> Status {
+        self.inner.lock().expect("lock poisoned").status
+    }
+
+    /// Whether requests are rejected right now. Once the open duration has elapsed, the next
+    /// request goes through as a probe, even if no request came in to move the breaker to
+    /// half-open yet.
+    pub(crate) fn rejects_requests(&self) -> bool {
+        match self.status() {
+            Status::Open { since } => since.elapsed() < self.open_duration,
+            Status::Closed | Status::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Check whether a request may be sent to the subgraph or the coprocessor.
+    pub(crate) fn try_acquire(&self) -> bool {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        match inner.status {
+            Status::Closed => true,
+            Status::Open { since } => {
+                if since.elapsed() >= self.open_duration {
+                    self.transition(
+                        &mut inner,
+                        Status::HalfOpen {
+                            since: Instant::now(),
+                            in_flight: 1,
+                        },
+                    );
+                    true
+                } else {
+                    self.rejected();
+                    false
+                }
+            }
+            Status::HalfOpen { since, in_flight } => {
+                if since.elapsed() >= self.open_duration {
+                    // the probes never completed (the requests were cancelled), probe again
+                    inner.status = Status::HalfOpen {
+                        since: Instant::now(),
+                        in_flight: 1,
+                    };
+                    true
+                } else if in_flight < self.half_open_requests {
+                    inner.status = Status::HalfOpen {
+                        since,
+                        in_flight: in_flight + 1,
+                    };
+                    true
+                } else {
+                    self.rejected();
+                    false
+                }
+            }
+        }
+    }
+
+    /// Record the outcome of a request that was let through.
+    pub(crate) fn record(&self, success: bool, latency: Duration) {
+        let success = success
+            && self
+                .latency_threshold
+                .map(|threshold| latency <= threshold)
+                .unwrap_or(true);
+
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        match inner.status {
+            Status::HalfOpen { .. } => {
+                if success {
+                    self.transition(&mut inner, Status::Closed);
+                } else {
+                    self.transition(
+                        &mut inner,
+                        Status::Open {
+                            since: Instant::now(),
+                        },
+                    );
+                }
+            }
+            // a request that started before the circuit opened
+            Status::Open { .. } => {}
+            Status::Closed => {
+                if inner.window_start.elapsed() > self.window {
+                    inner.window_start = Instant::now();
+                    inner.requests = 0;
+                    inner.failures = 0;
+                }
+                inner.requests += 1;
+                if !success {
+                    inner.failures += 1;
+                }
+
+                if inner.requests >= self.minimum_requests
+                    && inner.failures as f64 / inner.requests as f64 >= self.error_rate_threshold
+                {
+                    self.transition(
+                        &mut inner,
+                        Status::Open {
+                            since: Instant::now(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Open the circuit without waiting for requests to fail, for example because the subgraph
+    /// failed its health probes.
+    pub(crate) fn trip(&self) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        if !matches!(inner.status, Status::Open { .. }) {
+            self.transition(
+                &mut inner,
+                Status::Open {
+                    since: Instant::now(),
+                },
+            );
+        }
+    }
+
+    /// Let probe requests through again without waiting for the open duration, for example
+    /// because the subgraph passes its health probes again.
+    pub(crate) fn half_open(&self) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        if matches!(inner.status, Status::Open { .. }) {
+            self.transition(
+                &mut inner,
+                Status::HalfOpen {
+                    since: Instant::now(),
+                    in_flight: 0,
+                },
+            );
+        }
+    }
+
+    fn transition(&self, inner: &mut Inner, status: Status) {
+        match status {
+            Status::Open { .. } => tracing::warn!(
+                subgraph = self.target.subgraph_name(),
+                "circuit breaker opened, requests to {} will fail for {:?}",
+                self.target,
+                self.open_duration
+            ),
+            Status::Closed => tracing::info!(
+                subgraph = self.target.subgraph_name(),
+                "circuit breaker closed, {} recovered",
+                self.target
+            ),
+            Status::HalfOpen { .. } => tracing::debug!(
+                subgraph = self.target.subgraph_name(),
+                "circuit breaker half-open, probing {}",
+                self.target
+            ),
+        }
+
+        inner.status = status;
+        inner.window_start = Instant::now();
+        inner.requests = 0;
+        inner.failures = 0;
+
+        match &self.target {
+            Target::Subgraph(name) => tracing::info!(
+                value.apollo_router_circuit_breaker_state = status.as_metric(),
+                subgraph = %name,
+            ),
+            Target::Coprocessor => tracing::info!(
+                value.apollo_router_coprocessor_circuit_breaker_state = status.as_metric(),
+            ),
+        }
+    }
+
+    fn rejected(&self) {
+        match &self.target {
+            Target::Subgraph(name) => tracing::info!(
+                monotonic_counter.apollo_router_circuit_breaker_rejected_total = 1u64,
+                subgraph = %name,
+            ),
+            Target::Coprocessor => tracing::info!(
+                monotonic_counter.apollo_router_coprocessor_circuit_breaker_rejected_total = 1u64,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state() -> CircuitBreakerState {
+        CircuitBreakerState::new(
+            Target::Subgraph("test".to_string()),
+            Some(0.5),
+            Some(Duration::from_millis(100)),
+            Some(4),
+            Some(Duration::from_secs(10)),
+            Some(Duration::from_millis(50)),
+            Some(1),
+        )
+    }
+
+    #[test]
+    fn it_opens_after_error_rate_threshold() {
+        let state = state();
+        for success in [true, false, true] {
+            assert!(state.try_acquire());
+            state.record(success, Duration::from_millis(1));
+        }
+        assert_eq!(state.status(), Status::Closed);
+
+        assert!(state.try_acquire());
+        state.record(false, Duration::from_millis(1));
+        assert!(matches!(state.status(), Status::Open { .. }));
+        assert!(!state.try_acquire());
+    }
+
+    #[test]
+    fn it_counts_slow_requests_as_failures() {
+        let state = state();
+        for _ in 0..4 {
+            assert!(state.try_acquire());
+            state.record(true, Duration::from_millis(200));
+        }
+        assert!(matches!(state.status(), Status::Open { .. }));
+    }
+
+    #[test]
+    fn it_half_opens_and_recovers() {
+        let state = state();
+        for _ in 0..4 {
+            state.record(false, Duration::from_millis(1));
+        }
+        assert!(!state.try_acquire());
+
+        std::thread::sleep(Duration::from_millis(60));
+        // a single probe goes through
+        assert!(state.try_acquire());
+        assert!(!state.try_acquire());
+        assert!(matches!(state.status(), Status::HalfOpen { .. }));
+
+        state.record(true, Duration::from_millis(1));
+        assert_eq!(state.status(), Status::Closed);
+        assert!(state.try_acquire());
+    }
+
+    #[test]
+    fn it_opens_when_tripped() {
+        let state = state();
+        state.trip();
+        assert!(matches!(state.status(), Status::Open { .. }));
+        assert!(!state.try_acquire());
+    }
+
+    #[test]
+    fn it_stops_rejecting_after_the_open_duration() {
+        let state = state();
+        state.trip();
+        assert!(state.rejects_requests());
+
+        // no request moved the breaker to half-open
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(matches!(state.status(), Status::Open { .. }));
+        assert!(!state.rejects_requests());
+    }
+
+    #[test]
+    fn it_half_opens_before_the_open_duration() {
+        let state = state();
+        state.trip();
+        state.half_open();
+        assert!(matches!(
+            state.status(),
+            Status::HalfOpen { in_flight: 0, .. }
+        ));
+
+        assert!(state.try_acquire());
+        state.record(true, Duration::from_millis(1));
+        assert_eq!(state.status(), Status::Closed);
+    }
+
+    #[test]
+    fn it_reopens_when_probe_fails() {
+        let state = state();
+        for _ in 0..4 {
+            state.record(false, Duration::from_millis(1));
+        }
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(state.try_acquire());
+        state.record(false, Duration::from_millis(1));
+        assert!(matches!(state.status(), Status::Open { .. }));
+        assert!(!state.try_acquire());
+    }
+}
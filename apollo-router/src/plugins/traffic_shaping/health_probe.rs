@@ -0,0 +1,308 @@
+//! Periodic health probes of subgraphs.
+//!
+//! Each probed endpoint gets a task sending either a `GET` request to a configured path, or a
+//! `{ __typename }` query. An endpoint becomes unhealthy after a number of consecutive failed
+//! probes, and healthy again after a number of consecutive successful ones.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use http::Uri;
+use rustls::RootCertStore;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::task::JoinHandle;
+use tower::BoxError;
+
+use super::Merge;
+use crate::error::ConfigurationError;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_UNHEALTHY_THRESHOLD: u32 = 3;
+const DEFAULT_HEALTHY_THRESHOLD: u32 = 2;
+
+/// Health probe configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HealthProbeConfig {
+    /// HTTP path requested with a GET on the subgraph, which must answer with a 2xx status code.
+    /// If not set, a `{ __typename }` query is sent to the subgraph URL instead
+    path: Option<String>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// time between two probes. The default value is 10 seconds
+    interval: Option<Duration>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// how long to wait for a probe response. The default value is 1 second
+    timeout: Option<Duration>,
+    /// number of consecutive failed probes after which the subgraph is unhealthy. The default
+    /// value is 3
+    unhealthy_threshold: Option<u32>,
+    /// number of consecutive successful probes after which an unhealthy subgraph is healthy
+    /// again. The default value is 2
+    healthy_threshold: Option<u32>,
+}
+
+impl Merge for HealthProbeConfig {
+    fn merge(&self, fallback: Option<&Self>) -> Self {
+        match fallback {
+            None => self.clone(),
+            Some(fallback) => HealthProbeConfig {
+                path: self.path.as_ref().or(fallback.path.as_ref()).cloned(),
+                interval: self.interval.or(fallback.interval),
+                timeout: self.timeout.or(fallback.timeout),
+                unhealthy_threshold: self.unhealthy_threshold.or(fallback.unhealthy_threshold),
+                healthy_threshold: self.healthy_threshold.or(fallback.healthy_threshold),
+            },
+        }
+    }
+}
+
+/// Probes one endpoint of a subgraph.
+#[derive(Debug)]
+pub(crate) struct HealthProbe {
+    subgraph_name: String,
+    endpoint: Uri,
+    url: url::Url,
+    graphql: bool,
+    interval: Duration,
+    timeout: Duration,
+    status: ProbeStatus,
+    tls_cert_store: Option<RootCertStore>,
+}
+
+impl HealthProbe {
+    /// `tls_cert_store` holds the certificate authorities configured for the subgraph, the native
+    /// roots are used if there are none.
+    pub(crate) fn new(
+        subgraph_name: String,
+        endpoint: Uri,
+        config: &HealthProbeConfig,
+        tls_cert_store: Option<RootCertStore>,
+    ) -> Result<Self, ConfigurationError> {
+        let mut url = url::Url::parse(&endpoint.to_string()).map_err(|e| {
+            ConfigurationError::InvalidConfiguration {
+                message: "bad configuration for traffic_shaping plugin",
+                error: format!(
+                    "cannot probe the endpoint '{endpoint}' of subgraph '{subgraph_name}': {e}"
+                ),
+            }
+        })?;
+        if let Some(path) = &config.path {
+            url.set_path(path);
+            url.set_query(None);
+        }
+
+        Ok(Self {
+            url,
+            graphql: config.path.is_none(),
+            interval: config.interval.unwrap_or(DEFAULT_INTERVAL),
+            timeout: config.timeout.unwrap_or(DEFAULT_TIMEOUT),
+            status: ProbeStatus::new(
+                config
+                    .unhealthy_threshold
+                    .unwrap_or(DEFAULT_UNHEALTHY_THRESHOLD),
+                config
+                    .healthy_threshold
+                    .unwrap_or(DEFAULT_HEALTHY_THRESHOLD),
+            ),
+            subgraph_name,
+            endpoint,
+            tls_cert_store,
+        })
+    }
+
+    /// Probe the endpoint until the returned task is aborted. `report` is called after each probe
+    /// with the endpoint and its current health.
+    pub(crate) fn spawn<F>(mut self, report: F) -> JoinHandle<()>
+    where
+        F: Fn(&Uri, bool) + Send + Sync + 'static,
+    {
+        tokio::spawn(async move {
+            let mut builder = reqwest::Client::builder().timeout(self.timeout);
+            // probes go through the same certificate authorities as the subgraph requests
+            if let Some(store) = self.tls_cert_store.take() {
+                builder = builder.use_preconfigured_tls(
+                    rustls::ClientConfig::builder()
+                        .with_safe_defaults()
+                        .with_root_certificates(store)
+                        .with_no_client_auth(),
+                );
+            }
+            let client = match builder.build() {
+                Ok(client) => client,
+                Err(e) => {
+                    tracing::error!(
+                        subgraph = %self.subgraph_name,
+                        "cannot create the health probe client: {}",
+                        e
+                    );
+                    return;
+                }
+            };
+
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+
+                let start = Instant::now();
+                let result = self.probe(&client).await;
+                let success = result.is_ok();
+                if let Err(e) = result {
+                    tracing::debug!(
+                        subgraph = %self.subgraph_name,
+                        endpoint = %self.endpoint,
+                        "health probe failed: {}",
+                        e
+                    );
+                }
+
+                if let Some(healthy) = self.status.record(success) {
+                    if healthy {
+                        tracing::info!(
+                            subgraph = %self.subgraph_name,
+                            "endpoint {} of subgraph '{}' is healthy again",
+                            self.endpoint,
+                            self.subgraph_name
+                        );
+                    } else {
+                        tracing::warn!(
+                            subgraph = %self.subgraph_name,
+                            "endpoint {} of subgraph '{}' failed {} consecutive health probes",
+                            self.endpoint,
+                            self.subgraph_name,
+                            self.status.unhealthy_threshold
+                        );
+                    }
+                }
+
+                tracing::info!(
+                    monotonic_counter.apollo_router_subgraph_health_probes_total = 1u64,
+                    subgraph = %self.subgraph_name,
+                    endpoint = %self.endpoint,
+                    success,
+                );
+                tracing::info!(
+                    histogram.apollo_router_subgraph_health_probe_duration =
+                        start.elapsed().as_secs_f64(),
+                    subgraph = %self.subgraph_name,
+                    endpoint = %self.endpoint,
+                );
+                tracing::info!(
+                    value.apollo_router_subgraph_healthy = self.status.healthy as u64,
+                    subgraph = %self.subgraph_name,
+                    endpoint = %self.endpoint,
+                );
+
+                report(&self.endpoint, self.status.healthy);
+            }
+        })
+    }
+
+    async fn probe(&self, client: &reqwest::Client) -> Result<(), BoxError> {
+        if !self.graphql {
+            let response = client.get(self.url.clone()).send().await?;
+            if !response.status().is_success() {
+                return Err(format!("unexpected status code {}", response.status()).into());
+            }
+            return Ok(());
+        }
+
+        let response = client
+            .post(self.url.clone())
+            .json(&serde_json::json!({ "query": "{ __typename }" }))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(format!("unexpected status code {}", response.status()).into());
+        }
+        let body: Value = response.json().await?;
+        match body.get("data").and_then(|data| data.get("__typename")) {
+            Some(Value::String(_)) => Ok(()),
+            _ => Err("the response does not contain `data.__typename`".into()),
+        }
+    }
+}
+
+/// Consecutive probe results of an endpoint
+#[derive(Debug)]
+struct ProbeStatus {
+    healthy: bool,
+    consecutive: u32,
+    unhealthy_threshold: u32,
+    healthy_threshold: u32,
+}
+
+impl ProbeStatus {
+    fn new(unhealthy_threshold: u32, healthy_threshold: u32) -> Self {
+        Self {
+            healthy: true,
+            consecutive: 0,
+            unhealthy_threshold: unhealthy_threshold.max(1),
+            healthy_threshold: healthy_threshold.max(1),
+        }
+    }
+
+    /// Returns the new health if it changed
+    fn record(&mut self, success: bool) -> Option<bool> {
+        if success == self.healthy {
+            self.consecutive = 0;
+            return None;
+        }
+
+        self.consecutive += 1;
+        let threshold = if self.healthy {
+            self.unhealthy_threshold
+        } else {
+            self.healthy_threshold
+        };
+        if self.consecutive >= threshold {
+            self.healthy = success;
+            self.consecutive = 0;
+            Some(success)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_applies_thresholds() {
+        let mut status = ProbeStatus::new(3, 2);
+        assert_eq!(status.record(false), None);
+        assert_eq!(status.record(false), None);
+        assert_eq!(status.record(true), None);
+        assert_eq!(status.record(false), None);
+        assert_eq!(status.record(false), None);
+        assert_eq!(status.record(false), Some(false));
+        assert!(!status.healthy);
+
+        assert_eq!(status.record(true), None);
+        assert_eq!(status.record(false), None);
+        assert_eq!(status.record(true), None);
+        assert_eq!(status.record(true), Some(true));
+        assert!(status.healthy);
+    }
+
+    #[test]
+    fn it_probes_a_configured_path() {
+        let config: HealthProbeConfig = serde_yaml::from_str("path: /healthz").unwrap();
+        let probe = HealthProbe::new(
+            "products".to_string(),
+            Uri::from_static("http://products:4001/graphql?x=1"),
+            &config,
+            None,
+        )
+        .unwrap();
+        assert!(!probe.graphql);
+        assert_eq!(probe.url.as_str(), "http://products:4001/healthz");
+    }
+}
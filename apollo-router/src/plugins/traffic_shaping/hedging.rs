@@ -0,0 +1,319 @@
+//! Hedge subgraph queries. Implemented as a tower Layer.
+//!
+//! If a query has not been answered after a delay, a second request is sent and the first
+//! successful response is used. Mutations are never hedged.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use tower::retry::budget::Budget;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceExt;
+
+use crate::query_planner::OperationKind;
+use crate::services::subgraph;
+
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+const LATENCY_SAMPLES: usize = 1000;
+const MIN_LATENCY_SAMPLES: usize = 100;
+const RECOMPUTE_EVERY: usize = 50;
+
+/// Recent latencies, used to compute the hedging delay from a percentile
+#[derive(Debug, Default)]
+struct LatencyWindow {
+    samples: VecDeque<Duration>,
+    recorded: usize,
+    percentile_value: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub(crate) struct HedgeState {
+    subgraph_name: String,
+    delay: Duration,
+    percentile: Option<f64>,
+    latencies: Mutex<LatencyWindow>,
+    budget: Budget,
+}
+
+impl HedgeState {
+    pub(crate) fn new(
+        subgraph_name: String,
+        delay: Option<Duration>,
+        percentile: Option<f64>,
+        ttl: Option<Duration>,
+        min_per_sec: Option<u32>,
+        hedge_percent: Option<f32>,
+    ) -> Self {
+        Self {
+            subgraph_name,
+            delay: delay.unwrap_or(DEFAULT_DELAY),
+            percentile: percentile.map(|p| p.clamp(0.0, 100.0)),
+            latencies: Mutex::new(LatencyWindow::default()),
+            budget: Budget::new(
+                ttl.unwrap_or_else(|| Duration::from_secs(10)),
+                min_per_sec.unwrap_or(10),
+                hedge_percent.unwrap_or(0.1),
+            ),
+        }
+    }
+
+    /// How long to wait for the first response before sending a hedged request
+    fn delay(&self) -> Duration {
+        if self.percentile.is_none() {
+            return self.delay;
+        }
+
+        // until enough latencies are recorded, use the configured delay
+        self.latencies
+            .lock()
+            .expect("lock poisoned")
+            .percentile_value
+            .unwrap_or(self.delay)
+    }
+
+    fn record(&self, latency: Duration) {
+        let percentile = match self.percentile {
+            Some(percentile) => percentile,
+            None => return,
+        };
+
+        let mut latencies = self.latencies.lock().expect("lock poisoned");
+        if latencies.samples.len() == LATENCY_SAMPLES {
+            latencies.samples.pop_front();
+        }
+        latencies.samples.push_back(latency);
+        latencies.recorded += 1;
+
+        if latencies.samples.len() >= MIN_LATENCY_SAMPLES
+            && latencies.recorded % RECOMPUTE_EVERY == 0
+        {
+            let mut sorted: Vec<Duration> = latencies.samples.iter().copied().collect();
+            sorted.sort_unstable();
+            let index = ((percentile / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+            latencies.percentile_value = Some(sorted[index]);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct HedgeLayer {
+    state: Arc<HedgeState>,
+}
+
+impl HedgeLayer {
+    pub(crate) fn new(state: HedgeState) -> Self {
+        Self {
+            state: Arc::new(state),
+        }
+    }
+}
+
+impl<S> Layer<S> for HedgeLayer {
+    type Service = HedgeService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        HedgeService {
+            service,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct HedgeService<S> {
+    service: S,
+    state: Arc<HedgeState>,
+}
+
+impl<S> HedgeService<S>
+where
+    S: Service<subgraph::Request, Response = subgraph::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    /// `service` was made ready in `poll_ready`, the hedged request readies a clone of it
+    async fn hedge(
+        mut service: S,
+        state: Arc<HedgeState>,
+        request: subgraph::Request,
+    ) -> Result<subgraph::Response, BoxError> {
+        state.budget.deposit();
+        let hedged_request = request.clone();
+        let hedge_service = service.clone();
+
+        let start = Instant::now();
+        let primary = service.call(request);
+        tokio::pin!(primary);
+
+        tokio::select! {
+            result = &mut primary => {
+                state.record(start.elapsed());
+                return result;
+            }
+            _ = tokio::time::sleep(state.delay()) => {}
+        }
+
+        if state.budget.withdraw().is_err() {
+            tracing::info!(
+                monotonic_counter.apollo_router_hedged_requests_total = 1u64,
+                status = "aborted",
+                subgraph = %state.subgraph_name,
+            );
+            let result = primary.await;
+            state.record(start.elapsed());
+            return result;
+        }
+
+        tracing::info!(
+            monotonic_counter.apollo_router_hedged_requests_total = 1u64,
+            subgraph = %state.subgraph_name,
+        );
+        let hedged = hedge_service.oneshot(hedged_request);
+        tokio::pin!(hedged);
+
+        // use the first successful response, the other request is cancelled
+        let result = tokio::select! {
+            result = &mut primary => match result {
+                Ok(response) => Ok(response),
+                Err(_) => hedged.await,
+            },
+            result = &mut hedged => match result {
+                Ok(response) => Ok(response),
+                Err(_) => primary.await,
+            },
+        };
+        state.record(start.elapsed());
+
+        result
+    }
+}
+
+impl<S> Service<subgraph::Request> for HedgeService<S>
+where
+    S: Service<subgraph::Request, Response = subgraph::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = subgraph::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: subgraph::Request) -> Self::Future {
+        let clone = self.service.clone();
+        let mut service = std::mem::replace(&mut self.service, clone);
+
+        if request.operation_kind != OperationKind::Query {
+            return Box::pin(service.call(request));
+        }
+
+        Box::pin(Self::hedge(service, self.state.clone(), request))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    fn slow_first_service(
+        calls: Arc<AtomicUsize>,
+    ) -> impl Service<
+        subgraph::Request,
+        Response = subgraph::Response,
+        Error = BoxError,
+        Future = BoxFuture<'static, Result<subgraph::Response, BoxError>>,
+    > + Clone
+           + Send
+           + 'static {
+        tower::service_fn(move |request: subgraph::Request| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                if call == 0 {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+                Ok(subgraph::Response::fake_builder()
+                    .context(request.context)
+                    .build())
+            }) as BoxFuture<'static, Result<subgraph::Response, BoxError>>
+        })
+    }
+
+    fn layer() -> HedgeLayer {
+        HedgeLayer::new(HedgeState::new(
+            "test".to_string(),
+            Some(Duration::from_millis(10)),
+            None,
+            None,
+            None,
+            None,
+        ))
+    }
+
+    #[tokio::test]
+    async fn it_hedges_slow_queries() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = layer().layer(slow_first_service(calls.clone()));
+
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            service.oneshot(subgraph::Request::fake_builder().build()),
+        )
+        .await
+        .expect("the hedged request should answer first")
+        .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_hedge_mutations() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = layer().layer(slow_first_service(calls.clone()));
+
+        assert!(tokio::time::timeout(
+            Duration::from_millis(100),
+            service.oneshot(
+                subgraph::Request::fake_builder()
+                    .operation_kind(OperationKind::Mutation)
+                    .build()
+            ),
+        )
+        .await
+        .is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn it_computes_the_delay_from_a_percentile() {
+        let state = HedgeState::new(
+            "test".to_string(),
+            Some(Duration::from_secs(1)),
+            Some(90.0),
+            None,
+            None,
+            None,
+        );
+        assert_eq!(state.delay(), Duration::from_secs(1));
+
+        for i in 1..=100 {
+            state.record(Duration::from_millis(i));
+        }
+        assert_eq!(state.delay(), Duration::from_millis(90));
+    }
+}
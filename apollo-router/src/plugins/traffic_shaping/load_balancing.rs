@@ -0,0 +1,520 @@
+//! Spread subgraph requests over multiple endpoints. Implemented as a tower Layer.
+//!
+//! Endpoints that fail repeatedly are ejected for a while (passive outlier detection). When all
+//! the primary endpoints are ejected, requests fail over to the secondary endpoints.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use http::Uri;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+
+use crate::error::ConfigurationError;
+use crate::services::subgraph;
+
+const DEFAULT_CONSECUTIVE_FAILURES: u32 = 5;
+const DEFAULT_EJECTION_DURATION: Duration = Duration::from_secs(30);
+
+/// Load balancing configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LoadBalancingConfig {
+    /// Load balancing strategy
+    #[serde(default)]
+    strategy: Strategy,
+    /// URLs of the subgraph endpoints
+    endpoints: Vec<url::Url>,
+    /// URLs of the endpoints used when all the primary endpoints are ejected
+    #[serde(default)]
+    failover_endpoints: Vec<url::Url>,
+    /// Outlier detection configuration
+    outlier_detection: Option<OutlierDetectionConfig>,
+}
+
+/// Load balancing strategy
+#[derive(PartialEq, Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum Strategy {
+    /// Send requests to each endpoint in turn
+    #[default]
+    RoundRobin,
+    /// Send requests to the endpoint with the least requests in flight
+    LeastRequests,
+    /// Send requests with the same header value to the same endpoint
+    ConsistentHash {
+        /// Name of the client request header used as the hash key
+        header: String,
+    },
+}
+
+/// Outlier detection configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct OutlierDetectionConfig {
+    /// number of consecutive failed requests after which an endpoint is ejected. The default
+    /// value is 5
+    consecutive_failures: Option<u32>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// how long an endpoint stays ejected. The default value is 30 seconds
+    ejection_duration: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    uri: Uri,
+    outstanding: AtomicUsize,
+    consecutive_failures: AtomicU32,
+    ejected_until: Mutex<Option<Instant>>,
+    /// Result of the active health probes, if enabled
+    probe_healthy: AtomicBool,
+}
+
+impl Endpoint {
+    fn new(url: &url::Url) -> Result<Self, ConfigurationError> {
+        Ok(Self {
+            uri: Uri::from_str(url.as_str()).map_err(|e| {
+                ConfigurationError::InvalidConfiguration {
+                    message: "bad configuration for traffic_shaping plugin",
+                    error: format!("invalid load balancing endpoint '{url}': {e}"),
+                }
+            })?,
+            outstanding: AtomicUsize::new(0),
+            consecutive_failures: AtomicU32::new(0),
+            ejected_until: Mutex::new(None),
+            probe_healthy: AtomicBool::new(true),
+        })
+    }
+
+    fn is_available(&self) -> bool {
+        if !self.probe_healthy.load(Ordering::Relaxed) {
+            return false;
+        }
+        let mut ejected_until = self.ejected_until.lock().expect("lock poisoned");
+        match *ejected_until {
+            Some(until) if until > Instant::now() => false,
+            Some(_) => {
+                *ejected_until = None;
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct LoadBalancer {
+    subgraph_name: String,
+    strategy: Strategy,
+    primary: Vec<Arc<Endpoint>>,
+    failover: Vec<Arc<Endpoint>>,
+    consecutive_failures: u32,
+    ejection_duration: Duration,
+    next: AtomicUsize,
+}
+
+impl LoadBalancer {
+    pub(crate) fn new(
+        subgraph_name: String,
+        config: &LoadBalancingConfig,
+    ) -> Result<Self, ConfigurationError> {
+        if config.endpoints.is_empty() {
+            return Err(ConfigurationError::InvalidConfiguration {
+                message: "bad configuration for traffic_shaping plugin",
+                error: format!(
+                    "no load balancing endpoints defined for subgraph '{subgraph_name}'"
+                ),
+            });
+        }
+
+        let to_endpoints = |urls: &[url::Url]| {
+            urls.iter()
+                .map(|url| Endpoint::new(url).map(Arc::new))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            primary: to_endpoints(&config.endpoints)?,
+            failover: to_endpoints(&config.failover_endpoints)?,
+            strategy: config.strategy.clone(),
+            consecutive_failures: config
+                .outlier_detection
+                .as_ref()
+                .and_then(|o| o.consecutive_failures)
+                .unwrap_or(DEFAULT_CONSECUTIVE_FAILURES),
+            ejection_duration: config
+                .outlier_detection
+                .as_ref()
+                .and_then(|o| o.ejection_duration)
+                .unwrap_or(DEFAULT_EJECTION_DURATION),
+            next: AtomicUsize::new(0),
+            subgraph_name,
+        })
+    }
+
+    fn select(&self, request: &subgraph::Request) -> Arc<Endpoint> {
+        let mut candidates: Vec<&Arc<Endpoint>> =
+            self.primary.iter().filter(|e| e.is_available()).collect();
+        if candidates.is_empty() {
+            candidates = self.failover.iter().filter(|e| e.is_available()).collect();
+        }
+        if candidates.is_empty() {
+            // every endpoint is ejected, spreading the load is better than failing everything
+            candidates = self.primary.iter().collect();
+        }
+
+        let selected = match &self.strategy {
+            Strategy::RoundRobin => {
+                candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+            }
+            Strategy::LeastRequests => candidates
+                .iter()
+                .copied()
+                .min_by_key(|e| e.outstanding.load(Ordering::Relaxed))
+                .expect("there is at least one endpoint; qed"),
+            Strategy::ConsistentHash { header } => {
+                match request
+                    .supergraph_request
+                    .headers()
+                    .get(header)
+                    .map(|value| value.as_bytes())
+                {
+                    // rendezvous hashing: only keys mapped to an ejected endpoint move
+                    Some(key) => candidates
+                        .iter()
+                        .copied()
+                        .max_by_key(|e| {
+                            let mut hasher = DefaultHasher::new();
+                            key.hash(&mut hasher);
+                            e.uri.hash(&mut hasher);
+                            hasher.finish()
+                        })
+                        .expect("there is at least one endpoint; qed"),
+                    None => {
+                        candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+                    }
+                }
+            }
+        };
+
+        selected.clone()
+    }
+
+    fn record(&self, endpoint: &Endpoint, success: bool) {
+        if success {
+            endpoint.consecutive_failures.store(0, Ordering::Relaxed);
+            return;
+        }
+
+        let failures = endpoint
+            .consecutive_failures
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        if failures >= self.consecutive_failures {
+            endpoint.consecutive_failures.store(0, Ordering::Relaxed);
+            *endpoint.ejected_until.lock().expect("lock poisoned") =
+                Some(Instant::now() + self.ejection_duration);
+
+            tracing::warn!(
+                subgraph = %self.subgraph_name,
+                "ejecting endpoint {} for {:?} after {} consecutive failures",
+                endpoint.uri,
+                self.ejection_duration,
+                failures
+            );
+            tracing::info!(
+                monotonic_counter.apollo_router_load_balancing_ejections_total = 1u64,
+                subgraph = %self.subgraph_name,
+                endpoint = %endpoint.uri,
+            );
+        }
+    }
+}
+
+/// Decrements the in flight request count of an endpoint, even if the request is cancelled
+struct OutstandingGuard(Arc<Endpoint>);
+
+impl OutstandingGuard {
+    fn new(endpoint: Arc<Endpoint>) -> Self {
+        endpoint.outstanding.fetch_add(1, Ordering::Relaxed);
+        Self(endpoint)
+    }
+}
+
+impl Drop for OutstandingGuard {
+    fn drop(&mut self) {
+        self.0.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LoadBalancerLayer {
+    balancer: Arc<LoadBalancer>,
+}
+
+impl LoadBalancerLayer {
+    pub(crate) fn new(balancer: LoadBalancer) -> Self {
+        Self {
+            balancer: Arc::new(balancer),
+        }
+    }
+
+    /// URIs of the primary and failover endpoints
+    pub(crate) fn endpoints(&self) -> Vec<Uri> {
+        self.balancer
+            .primary
+            .iter()
+            .chain(self.balancer.failover.iter())
+            .map(|endpoint| endpoint.uri.clone())
+            .collect()
+    }
+
+    /// Record the result of the health probes of an endpoint. Unhealthy endpoints are skipped
+    /// like ejected ones.
+    pub(crate) fn set_endpoint_health(&self, uri: &Uri, healthy: bool) {
+        self.balancer
+            .primary
+            .iter()
+            .chain(self.balancer.failover.iter())
+            .filter(|endpoint| &endpoint.uri == uri)
+            .for_each(|endpoint| endpoint.probe_healthy.store(healthy, Ordering::Relaxed));
+    }
+}
+
+impl<S> Layer<S> for LoadBalancerLayer {
+    type Service = LoadBalancerService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        LoadBalancerService {
+            service,
+            balancer: self.balancer.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LoadBalancerService<S> {
+    service: S,
+    balancer: Arc<LoadBalancer>,
+}
+
+impl<S> Service<subgraph::Request> for LoadBalancerService<S>
+where
+    S: Service<subgraph::Request, Response = subgraph::Response, Error = BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = subgraph::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: subgraph::Request) -> Self::Future {
+        let endpoint = self.balancer.select(&request);
+        *request.subgraph_request.uri_mut() = endpoint.uri.clone();
+
+        let guard = OutstandingGuard::new(endpoint);
+        let balancer = self.balancer.clone();
+        let fut = self.service.call(request);
+
+        Box::pin(async move {
+            let result = fut.await;
+            let success = matches!(&result, Ok(res) if !res.response.status().is_server_error());
+            balancer.record(&guard.0, success);
+
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugin::test::MockSubgraph;
+
+    fn balancer(yaml: &str) -> LoadBalancer {
+        let config: LoadBalancingConfig = serde_yaml::from_str(yaml).unwrap();
+        LoadBalancer::new("test".to_string(), &config).unwrap()
+    }
+
+    fn request_with_header(name: &str, value: &str) -> subgraph::Request {
+        subgraph::Request::fake_builder()
+            .supergraph_request(Arc::new(
+                http::Request::builder()
+                    .header(name, value)
+                    .body(crate::graphql::Request::default())
+                    .unwrap(),
+            ))
+            .build()
+    }
+
+    #[test]
+    fn it_round_robins() {
+        let balancer = balancer(
+            r#"
+            endpoints:
+              - http://a/graphql
+              - http://b/graphql
+            "#,
+        );
+        let request = subgraph::Request::fake_builder().build();
+        let selected: Vec<String> = (0..4)
+            .map(|_| balancer.select(&request).uri.to_string())
+            .collect();
+        assert_eq!(
+            selected,
+            vec![
+                "http://a/graphql",
+                "http://b/graphql",
+                "http://a/graphql",
+                "http://b/graphql"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_picks_least_requests() {
+        let balancer = balancer(
+            r#"
+            strategy: least_requests
+            endpoints:
+              - http://a/graphql
+              - http://b/graphql
+            "#,
+        );
+        let request = subgraph::Request::fake_builder().build();
+        let _guard = OutstandingGuard::new(balancer.select(&request));
+        assert_eq!(
+            balancer.select(&request).uri.to_string(),
+            "http://b/graphql"
+        );
+    }
+
+    #[test]
+    fn it_hashes_consistently() {
+        let balancer = balancer(
+            r#"
+            strategy:
+              consistent_hash:
+                header: x-user
+            endpoints:
+              - http://a/graphql
+              - http://b/graphql
+              - http://c/graphql
+            "#,
+        );
+        let first = balancer
+            .select(&request_with_header("x-user", "alice"))
+            .uri
+            .clone();
+        for _ in 0..10 {
+            assert_eq!(
+                balancer.select(&request_with_header("x-user", "alice")).uri,
+                first
+            );
+        }
+    }
+
+    #[test]
+    fn it_ejects_failing_endpoints_and_fails_over() {
+        let balancer = balancer(
+            r#"
+            endpoints:
+              - http://a/graphql
+            failover_endpoints:
+              - http://b/graphql
+            outlier_detection:
+              consecutive_failures: 2
+              ejection_duration: 50ms
+            "#,
+        );
+        let request = subgraph::Request::fake_builder().build();
+        let primary = balancer.select(&request);
+        balancer.record(&primary, false);
+        assert_eq!(
+            balancer.select(&request).uri.to_string(),
+            "http://a/graphql"
+        );
+        balancer.record(&primary, false);
+        assert_eq!(
+            balancer.select(&request).uri.to_string(),
+            "http://b/graphql"
+        );
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(
+            balancer.select(&request).uri.to_string(),
+            "http://a/graphql"
+        );
+    }
+
+    #[test]
+    fn it_skips_endpoints_failing_health_probes() {
+        let layer = LoadBalancerLayer::new(balancer(
+            r#"
+            endpoints:
+              - http://a/graphql
+              - http://b/graphql
+            "#,
+        ));
+        let request = subgraph::Request::fake_builder().build();
+        layer.set_endpoint_health(&Uri::from_static("http://a/graphql"), false);
+        for _ in 0..4 {
+            assert_eq!(
+                layer.balancer.select(&request).uri.to_string(),
+                "http://b/graphql"
+            );
+        }
+
+        layer.set_endpoint_health(&Uri::from_static("http://a/graphql"), true);
+        assert_eq!(
+            (0..2)
+                .map(|_| layer.balancer.select(&request).uri.to_string())
+                .filter(|uri| uri == "http://a/graphql")
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rewrites_the_subgraph_url() {
+        let layer = LoadBalancerLayer::new(balancer(
+            r#"
+            endpoints:
+              - http://a/graphql
+            "#,
+        ));
+        let service = MockSubgraph::new(HashMap::new()).map_request(|req: subgraph::Request| {
+            assert_eq!(req.subgraph_request.uri().to_string(), "http://a/graphql");
+            req
+        });
+
+        layer
+            .layer(service)
+            .oneshot(subgraph::Request::fake_builder().build())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,582 @@
+//! Limit the number of requests processed concurrently. Implemented as a tower Layer.
+//!
+//! Requests over the limit wait in a bounded queue. When the queue is full, the requests with
+//! the lowest priority are shed first. The limit can adapt to the observed latency.
+
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::response::IntoResponse;
+use futures::future::BoxFuture;
+use http::header::CONTENT_TYPE;
+use http::HeaderValue;
+use http::StatusCode;
+use mime::APPLICATION_JSON;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::sync::oneshot;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+
+use crate::graphql;
+use crate::plugins::telemetry::CLIENT_NAME;
+use crate::services::supergraph;
+
+const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_LATENCY_TOLERANCE: f64 = 2.0;
+/// The baseline latency is reset after this number of samples, to follow changes of the subgraphs
+const BASELINE_SAMPLES: u32 = 1000;
+
+/// Load shedding configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LoadSheddingConfig {
+    /// Maximum number of requests processed concurrently
+    max_concurrent_requests: NonZeroU32,
+    /// Number of requests waiting for a slot when the limit is reached. The default value is 0
+    #[serde(default)]
+    queue_size: u32,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// how long a request waits in the queue before being rejected. The default value is 1s
+    queue_timeout: Option<Duration>,
+    /// Rules assigning a priority to requests, the first matching rule is used. Requests
+    /// matching no rule have the `normal` priority
+    #[serde(default)]
+    priorities: Vec<PriorityRule>,
+    /// Adapt the concurrency limit to the observed latency
+    adaptive: Option<AdaptiveConfig>,
+}
+
+/// Request priority
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub(crate) enum Priority {
+    /// Shed first
+    Low,
+    /// Default priority
+    #[default]
+    Normal,
+    /// Shed last
+    High,
+}
+
+impl Priority {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+}
+
+/// Assign a priority to the requests matching all the conditions
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct PriorityRule {
+    /// Client name, as reported to Apollo Studio
+    client_name: Option<String>,
+    /// Name of the GraphQL operation
+    operation_name: Option<String>,
+    /// Client request header
+    header: Option<HeaderCondition>,
+    /// Priority of the matching requests
+    priority: Priority,
+}
+
+/// Header condition
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct HeaderCondition {
+    /// Name of the header
+    name: String,
+    /// Value of the header. If not set, the header only has to be present
+    value: Option<String>,
+}
+
+/// Adaptive concurrency limit configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct AdaptiveConfig {
+    /// Lowest concurrency limit. The default value is 1
+    min_concurrent_requests: Option<NonZeroU32>,
+    /// the limit is reduced when the latency gets higher than the lowest observed latency
+    /// multiplied by this factor. The default value is 2
+    latency_tolerance: Option<f64>,
+}
+
+impl PriorityRule {
+    fn matches(&self, request: &supergraph::Request) -> bool {
+        if let Some(client_name) = &self.client_name {
+            let name = request.context.get::<_, String>(CLIENT_NAME).ok().flatten();
+            if name.as_ref() != Some(client_name) {
+                return false;
+            }
+        }
+        if let Some(operation_name) = &self.operation_name {
+            if request.supergraph_request.body().operation_name.as_ref() != Some(operation_name) {
+                return false;
+            }
+        }
+        if let Some(header) = &self.header {
+            match request.supergraph_request.headers().get(&header.name) {
+                None => return false,
+                Some(value) => {
+                    if let Some(expected) = &header.value {
+                        if value.to_str().ok() != Some(expected.as_str()) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+/// The request was shed because the router is overloaded.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Overloaded;
+
+impl Overloaded {
+    /// Construct a new Overloaded error
+    pub(crate) fn new() -> Self {
+        Overloaded {}
+    }
+}
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("the router is overloaded, try again later")
+    }
+}
+
+impl IntoResponse for Overloaded {
+    fn into_response(self) -> axum::response::Response {
+        let body = graphql::Response::builder()
+            .error(
+                graphql::Error::builder()
+                    .message(self.to_string())
+                    .extension_code("SERVICE_OVERLOADED")
+                    .build(),
+            )
+            .build();
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(
+                CONTENT_TYPE,
+                HeaderValue::from_static(APPLICATION_JSON.essence_str()),
+            )],
+            serde_json::to_string(&body).expect("a GraphQL response can always be serialized"),
+        )
+            .into_response()
+    }
+}
+
+impl error::Error for Overloaded {}
+
+struct Waiter {
+    id: u64,
+    sender: oneshot::Sender<Permit>,
+}
+
+struct State {
+    in_flight: u32,
+    limit: u32,
+    /// one queue per priority, in priority order
+    queues: [VecDeque<Waiter>; 3],
+    next_id: u64,
+    baseline: Option<Duration>,
+    samples: u32,
+    successes: u32,
+}
+
+impl State {
+    fn queued(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+}
+
+pub(crate) struct LoadShedder {
+    queue_size: usize,
+    queue_timeout: Duration,
+    priorities: Vec<PriorityRule>,
+    max_limit: u32,
+    adaptive: Option<(u32, f64)>,
+    state: Mutex<State>,
+}
+
+impl LoadShedder {
+    pub(crate) fn new(config: &LoadSheddingConfig) -> Self {
+        let max_limit = config.max_concurrent_requests.get();
+        Self {
+            queue_size: config.queue_size as usize,
+            queue_timeout: config.queue_timeout.unwrap_or(DEFAULT_QUEUE_TIMEOUT),
+            priorities: config.priorities.clone(),
+            max_limit,
+            adaptive: config.adaptive.as_ref().map(|adaptive| {
+                (
+                    adaptive
+                        .min_concurrent_requests
+                        .map(NonZeroU32::get)
+                        .unwrap_or(1)
+                        .min(max_limit),
+                    adaptive
+                        .latency_tolerance
+                        .unwrap_or(DEFAULT_LATENCY_TOLERANCE)
+                        .max(1.0),
+                )
+            }),
+            state: Mutex::new(State {
+                in_flight: 0,
+                limit: max_limit,
+                queues: Default::default(),
+                next_id: 0,
+                baseline: None,
+                samples: 0,
+                successes: 0,
+            }),
+        }
+    }
+
+    fn priority(&self, request: &supergraph::Request) -> Priority {
+        self.priorities
+            .iter()
+            .find(|rule| rule.matches(request))
+            .map(|rule| rule.priority)
+            .unwrap_or_default()
+    }
+
+    fn shed(reason: &'static str, priority: Priority) -> Overloaded {
+        tracing::info!(
+            monotonic_counter.apollo_router_load_shed_requests_total = 1u64,
+            reason,
+            priority = priority.as_str(),
+        );
+        Overloaded::new()
+    }
+
+    async fn acquire(self: &Arc<Self>, priority: Priority) -> Result<Permit, Overloaded> {
+        let (id, mut receiver) = {
+            let mut state = self.state.lock().expect("lock poisoned");
+            if state.in_flight < state.limit {
+                state.in_flight += 1;
+                return Ok(Permit::new(self.clone()));
+            }
+
+            if state.queued() >= self.queue_size {
+                // make room by shedding the most recent request with the lowest priority, if it
+                // has a lower priority than this one
+                let lowest = state.queues.iter().position(|queue| !queue.is_empty());
+                match lowest {
+                    Some(lowest) if lowest < priority as usize => {
+                        // dropping the sender rejects the waiting request
+                        state.queues[lowest].pop_back();
+                    }
+                    _ => return Err(Self::shed("queue_full", priority)),
+                }
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.queues[priority as usize].push_back(Waiter { id, sender });
+            (id, receiver)
+        };
+
+        match tokio::time::timeout(self.queue_timeout, &mut receiver).await {
+            Ok(Ok(permit)) => Ok(permit.start()),
+            Ok(Err(_)) => Err(Self::shed("evicted", priority)),
+            Err(_) => {
+                let mut state = self.state.lock().expect("lock poisoned");
+                let queue = &mut state.queues[priority as usize];
+                match queue.iter().position(|waiter| waiter.id == id) {
+                    Some(index) => {
+                        queue.remove(index);
+                        Err(Self::shed("queue_timeout", priority))
+                    }
+                    // the slot was handed over right after the timeout
+                    None => match receiver.try_recv() {
+                        Ok(permit) => Ok(permit.start()),
+                        Err(_) => Err(Self::shed("evicted", priority)),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Release a slot, the latency is only known if the slot was used to process a request
+    fn release(self: &Arc<Self>, latency: Option<Duration>) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        if let (Some((min_limit, tolerance)), Some(latency)) = (self.adaptive, latency) {
+            self.adapt(&mut state, latency, min_limit, tolerance);
+        }
+
+        // hand the slot over to the oldest request with the highest priority
+        let waiter = if state.in_flight <= state.limit {
+            state.queues.iter_mut().rev().find_map(VecDeque::pop_front)
+        } else {
+            None
+        };
+        match waiter {
+            Some(waiter) => {
+                drop(state);
+                // the slot goes with the permit: if the waiting request is gone, or goes away
+                // before receiving it, dropping the permit releases the slot again
+                let _ = waiter.sender.send(Permit::handed_over(self.clone()));
+            }
+            None => state.in_flight -= 1,
+        }
+    }
+
+    /// Additive increase, multiplicative decrease of the limit
+    fn adapt(&self, state: &mut State, latency: Duration, min_limit: u32, tolerance: f64) {
+        state.samples += 1;
+        if state.samples >= BASELINE_SAMPLES {
+            state.samples = 0;
+            state.baseline = None;
+        }
+        let baseline = state
+            .baseline
+            .map_or(latency, |baseline| baseline.min(latency));
+        state.baseline = Some(baseline);
+
+        let previous = state.limit;
+        if latency.as_secs_f64() > baseline.as_secs_f64() * tolerance {
+            state.successes = 0;
+            state.limit = ((state.limit as f64 * 0.9) as u32).max(min_limit);
+        } else {
+            state.successes += 1;
+            if state.successes >= state.limit {
+                state.successes = 0;
+                state.limit = (state.limit + 1).min(self.max_limit);
+            }
+        }
+
+        if state.limit != previous {
+            tracing::info!(value.apollo_router_concurrency_limit = state.limit as u64);
+        }
+    }
+}
+
+/// A slot to process a request, released when dropped
+struct Permit {
+    shedder: Arc<LoadShedder>,
+    /// not set while the permit is handed over to a queued request
+    start: Option<Instant>,
+}
+
+impl Permit {
+    fn new(shedder: Arc<LoadShedder>) -> Self {
+        Self {
+            shedder,
+            start: Some(Instant::now()),
+        }
+    }
+
+    fn handed_over(shedder: Arc<LoadShedder>) -> Self {
+        Self {
+            shedder,
+            start: None,
+        }
+    }
+
+    /// The queued request received the permit and starts processing
+    fn start(mut self) -> Self {
+        self.start = Some(Instant::now());
+        self
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.shedder
+            .release(self.start.map(|start| start.elapsed()));
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LoadSheddingLayer {
+    shedder: Arc<LoadShedder>,
+}
+
+impl LoadSheddingLayer {
+    pub(crate) fn new(shedder: LoadShedder) -> Self {
+        Self {
+            shedder: Arc::new(shedder),
+        }
+    }
+}
+
+impl<S> Layer<S> for LoadSheddingLayer {
+    type Service = LoadSheddingService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        LoadSheddingService {
+            service,
+            shedder: self.shedder.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LoadSheddingService<S> {
+    service: S,
+    shedder: Arc<LoadShedder>,
+}
+
+impl<S> Service<supergraph::Request> for LoadSheddingService<S>
+where
+    S: Service<supergraph::Request, Response = supergraph::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = supergraph::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: supergraph::Request) -> Self::Future {
+        // call the service that was made ready in poll_ready, polling a clone would count the
+        // request again in the layers below
+        let clone = self.service.clone();
+        let mut service = std::mem::replace(&mut self.service, clone);
+        let shedder = self.shedder.clone();
+        let priority = shedder.priority(&request);
+
+        Box::pin(async move {
+            let _permit = shedder.acquire(priority).await?;
+            service.call(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::FutureExt;
+
+    use super::*;
+
+    fn shedder(max_concurrent_requests: u32, queue_size: u32) -> Arc<LoadShedder> {
+        Arc::new(LoadShedder::new(&LoadSheddingConfig {
+            max_concurrent_requests: NonZeroU32::new(max_concurrent_requests).unwrap(),
+            queue_size,
+            queue_timeout: Some(Duration::from_millis(50)),
+            priorities: Vec::new(),
+            adaptive: None,
+        }))
+    }
+
+    #[tokio::test]
+    async fn it_queues_requests_over_the_limit() {
+        let shedder = shedder(1, 1);
+        let permit = shedder.acquire(Priority::Normal).await.unwrap();
+        assert!(shedder.acquire(Priority::Normal).await.is_err());
+
+        let queued = tokio::spawn({
+            let shedder = shedder.clone();
+            async move { shedder.acquire(Priority::Normal).await.is_ok() }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(permit);
+        assert!(queued.await.unwrap());
+        assert_eq!(shedder.state.lock().unwrap().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn it_releases_a_slot_handed_over_to_a_dropped_request() {
+        let shedder = shedder(1, 1);
+        let permit = shedder.acquire(Priority::Normal).await.unwrap();
+
+        let mut queued = Box::pin(shedder.acquire(Priority::Normal));
+        assert!(queued.as_mut().now_or_never().is_none());
+        // the slot is handed over, then the queued request goes away before receiving it
+        drop(permit);
+        drop(queued);
+
+        assert_eq!(shedder.state.lock().unwrap().in_flight, 0);
+        assert!(shedder.acquire(Priority::Normal).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_sheds_low_priority_requests_first() {
+        let shedder = shedder(1, 1);
+        let _permit = shedder.acquire(Priority::Normal).await.unwrap();
+
+        let low = tokio::spawn({
+            let shedder = shedder.clone();
+            async move { shedder.acquire(Priority::Low).await.is_ok() }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let high = tokio::spawn({
+            let shedder = shedder.clone();
+            async move { shedder.acquire(Priority::High).await.is_ok() }
+        });
+        assert!(!low.await.unwrap());
+        // the high priority request is still queued and times out
+        assert!(!high.await.unwrap());
+        assert!(shedder.acquire(Priority::Low).await.is_err());
+    }
+
+    #[test]
+    fn it_classifies_requests() {
+        let rule = PriorityRule {
+            client_name: None,
+            operation_name: None,
+            header: Some(HeaderCondition {
+                name: "x-priority".to_string(),
+                value: Some("low".to_string()),
+            }),
+            priority: Priority::Low,
+        };
+        let request = supergraph::Request::fake_builder()
+            .header("x-priority", "low")
+            .build()
+            .unwrap();
+        assert!(rule.matches(&request));
+        let request = supergraph::Request::fake_builder()
+            .header("x-priority", "high")
+            .build()
+            .unwrap();
+        assert!(!rule.matches(&request));
+    }
+
+    #[test]
+    fn it_reduces_the_limit_when_latency_increases() {
+        let shedder = Arc::new(LoadShedder::new(&LoadSheddingConfig {
+            max_concurrent_requests: NonZeroU32::new(10).unwrap(),
+            queue_size: 0,
+            queue_timeout: None,
+            priorities: Vec::new(),
+            adaptive: Some(AdaptiveConfig {
+                min_concurrent_requests: NonZeroU32::new(5),
+                latency_tolerance: None,
+            }),
+        }));
+        shedder.state.lock().unwrap().in_flight = 3;
+        shedder.release(Some(Duration::from_millis(10)));
+        shedder.release(Some(Duration::from_millis(100)));
+        assert_eq!(shedder.state.lock().unwrap().limit, 9);
+        shedder.release(Some(Duration::from_millis(100)));
+        for _ in 0..10 {
+            shedder.state.lock().unwrap().in_flight += 1;
+            shedder.release(Some(Duration::from_millis(100)));
+        }
+        assert_eq!(shedder.state.lock().unwrap().limit, 5);
+    }
+}
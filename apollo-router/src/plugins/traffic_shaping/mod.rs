@@ -5,24 +5,39 @@
 //! * Timeout
 //! * Compression
 //! * Rate limiting
+//! * Circuit breaking
+//! * Load balancing
+//! * Request hedging
+//! * Active health probing
 //!
 // With regards to ELv2 licensing, this entire file is license key functionality
 mod cache;
+pub(crate) mod circuit_breaker;
 mod deduplication;
+mod health_probe;
+mod hedging;
+mod load_balancing;
+mod load_shedding;
 mod rate;
 mod retry;
 mod timeout;
 
 use std::collections::HashMap;
 use std::num::NonZeroU64;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
 
 use futures::future::BoxFuture;
 use http::header::CONTENT_ENCODING;
 use http::HeaderValue;
+use http::Uri;
+use rustls::RootCertStore;
 use schemars::JsonSchema;
 use serde::Deserialize;
+use tokio::task::JoinHandle;
 use tower::retry::Retry;
 use tower::util::Either;
 use tower::util::Oneshot;
@@ -32,7 +47,25 @@ use tower::ServiceBuilder;
 use tower::ServiceExt;
 
 use self::cache::SubgraphCacheLayer;
+use self::circuit_breaker::CircuitBreakerLayer;
+use self::circuit_breaker::CircuitBreakerState;
+use self::circuit_breaker::Target;
 use self::deduplication::QueryDeduplicationLayer;
+use self::health_probe::HealthProbe;
+use self::health_probe::HealthProbeConfig;
+use self::hedging::HedgeLayer;
+use self::hedging::HedgeState;
+use self::load_balancing::LoadBalancer;
+use self::load_balancing::LoadBalancerLayer;
+use self::load_balancing::LoadBalancingConfig;
+use self::load_shedding::LoadShedder;
+use self::load_shedding::LoadSheddingConfig;
+use self::load_shedding::LoadSheddingLayer;
+pub(crate) use self::load_shedding::Overloaded;
+use self::rate::distributed::DistributedRateLimiter;
+use self::rate::partitioned::PartitionedRateLimitLayer;
+use self::rate::partitioned::PartitionedRateLimiter;
+use self::rate::partitioned::RateLimitKey;
 use self::rate::RateLimitLayer;
 pub(crate) use self::rate::RateLimited;
 use self::retry::RetryPolicy;
@@ -75,6 +108,13 @@ struct Shaping {
     experimental_retry: Option<RetryConfig>,
     /// Enable HTTP2 for subgraphs
     experimental_enable_http2: Option<bool>,
+    /// Circuit breaker configuration
+    experimental_circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Hedging configuration
+    //  *experimental feature*: Enables request hedging for queries
+    experimental_hedging: Option<HedgingConfig>,
+    /// Periodically probe the subgraph health
+    experimental_health_probe: Option<HealthProbeConfig>,
 }
 
 impl Merge for Shaping {
@@ -100,6 +140,21 @@ impl Merge for Shaping {
                     .as_ref()
                     .or(fallback.experimental_enable_http2.as_ref())
                     .cloned(),
+                experimental_circuit_breaker: self
+                    .experimental_circuit_breaker
+                    .as_ref()
+                    .or(fallback.experimental_circuit_breaker.as_ref())
+                    .cloned(),
+                experimental_hedging: self
+                    .experimental_hedging
+                    .as_ref()
+                    .or(fallback.experimental_hedging.as_ref())
+                    .cloned(),
+                experimental_health_probe: self
+                    .experimental_health_probe
+                    .as_ref()
+                    .or(fallback.experimental_health_probe.as_ref())
+                    .cloned(),
             },
         }
     }
@@ -141,6 +196,90 @@ impl Merge for RetryConfig {
     }
 }
 
+/// Hedging configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct HedgingConfig {
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// how long to wait for a response before sending a hedged request. If `percentile` is set,
+    /// this is only used until enough latencies are recorded. The default value is 100ms
+    delay: Option<Duration>,
+    /// send a hedged request when the query takes longer than this percentile (between 0 and
+    /// 100) of the recent subgraph latencies. Disabled by default
+    percentile: Option<f64>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// how long a single deposit should be considered. Must be between 1 and 60 seconds,
+    /// default value is 10 seconds
+    ttl: Option<Duration>,
+    /// minimum rate of hedged requests allowed, even when few requests were sent. The default
+    /// value is 10
+    min_per_sec: Option<u32>,
+    /// percentage of requests that can be hedged. This is in addition to the hedged requests
+    /// allowed for via min_per_sec. Must be between 0 and 1000, default value is 0.1
+    hedge_percent: Option<f32>,
+}
+
+impl Merge for HedgingConfig {
+    fn merge(&self, fallback: Option<&Self>) -> Self {
+        match fallback {
+            None => self.clone(),
+            Some(fallback) => HedgingConfig {
+                delay: self.delay.or(fallback.delay),
+                percentile: self.percentile.or(fallback.percentile),
+                ttl: self.ttl.or(fallback.ttl),
+                min_per_sec: self.min_per_sec.or(fallback.min_per_sec),
+                hedge_percent: self.hedge_percent.or(fallback.hedge_percent),
+            },
+        }
+    }
+}
+
+/// Circuit breaker configuration
+#[derive(PartialEq, Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CircuitBreakerConfig {
+    /// ratio of failed requests over the window, between 0 and 1, above which the circuit
+    /// opens. The default value is 0.5
+    pub(crate) error_rate_threshold: Option<f64>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// requests taking longer than this are counted as failures. Disabled by default
+    pub(crate) latency_threshold: Option<Duration>,
+    /// minimum number of requests in the window before the error rate is evaluated. The
+    /// default value is 20
+    pub(crate) minimum_requests: Option<u64>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// duration of the window over which the error rate is computed. The default value is
+    /// 10 seconds
+    pub(crate) window: Option<Duration>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// how long the circuit stays open before probe requests are let through. The default
+    /// value is 30 seconds
+    pub(crate) open_duration: Option<Duration>,
+    /// number of probe requests allowed while the circuit is half-open. The default value is 1
+    pub(crate) half_open_requests: Option<u32>,
+}
+
+impl Merge for CircuitBreakerConfig {
+    fn merge(&self, fallback: Option<&Self>) -> Self {
+        match fallback {
+            None => self.clone(),
+            Some(fallback) => CircuitBreakerConfig {
+                error_rate_threshold: self.error_rate_threshold.or(fallback.error_rate_threshold),
+                latency_threshold: self.latency_threshold.or(fallback.latency_threshold),
+                minimum_requests: self.minimum_requests.or(fallback.minimum_requests),
+                window: self.window.or(fallback.window),
+                open_duration: self.open_duration.or(fallback.open_duration),
+                half_open_requests: self.half_open_requests.or(fallback.half_open_requests),
+            },
+        }
+    }
+}
+
 // this is a wrapper struct to add subgraph specific options over Shaping
 #[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -149,6 +288,8 @@ struct SubgraphShaping {
     shaping: Shaping,
     /// Enable entity caching
     experimental_entity_caching: Option<SubgraphEntityCaching>,
+    /// Load balance requests over multiple endpoints, only allowed in subgraph specific configuration
+    experimental_load_balancing: Option<LoadBalancingConfig>,
 }
 
 impl Merge for SubgraphShaping {
@@ -162,6 +303,7 @@ impl Merge for SubgraphShaping {
                     .as_ref()
                     .or(fallback.experimental_entity_caching.as_ref())
                     .cloned(),
+                experimental_load_balancing: self.experimental_load_balancing.clone(),
             },
         }
     }
@@ -194,6 +336,12 @@ struct RouterShaping {
     #[schemars(with = "String", default)]
     /// Enable timeout for incoming requests
     timeout: Option<Duration>,
+    /// Enable rate limiting per client
+    experimental_client_rate_limit: Option<ClientRateLimitConf>,
+    /// Share the global and per client rate limits between router instances
+    experimental_distributed_rate_limit: Option<DistributedRateLimitConf>,
+    /// Limit the number of requests processed concurrently
+    experimental_load_shedding: Option<LoadSheddingConfig>,
 }
 
 #[derive(PartialEq, Debug, Clone, Default, Deserialize, JsonSchema)]
@@ -225,6 +373,34 @@ struct RateLimitConf {
     interval: Duration,
 }
 
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct ClientRateLimitConf {
+    /// How requests are assigned to a client
+    key: RateLimitKey,
+    /// Number of requests allowed for each client
+    capacity: NonZeroU64,
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    /// Per interval
+    interval: Duration,
+    /// Specific rate limits for some clients, by key value
+    #[serde(default)]
+    overrides: HashMap<String, RateLimitConf>,
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct DistributedRateLimitConf {
+    /// List of URLs to the Redis cluster storing the rate limit counters
+    urls: Vec<url::Url>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// How long to wait for Redis before falling back to local rate limits. The default value
+    /// is 50ms
+    timeout: Option<Duration>,
+}
+
 impl Merge for RateLimitConf {
     fn merge(&self, fallback: Option<&Self>) -> Self {
         match fallback {
@@ -242,8 +418,24 @@ impl Merge for RateLimitConf {
 pub(crate) struct TrafficShaping {
     config: Config,
     rate_limit_router: Option<RateLimitLayer>,
+    distributed_rate_limit_router: Option<PartitionedRateLimitLayer>,
+    client_rate_limit: Option<PartitionedRateLimitLayer>,
+    load_shedding: Option<LoadSheddingLayer>,
     rate_limit_subgraphs: Mutex<HashMap<String, RateLimitLayer>>,
+    circuit_breakers: Arc<Mutex<HashMap<String, CircuitBreakerLayer>>>,
+    load_balancers: HashMap<String, LoadBalancerLayer>,
     storage: Option<RedisCacheStorage>,
+    /// Health of each probed endpoint, by subgraph
+    probed_endpoints: Arc<Mutex<HashMap<String, HashMap<Uri, bool>>>>,
+    health_probes: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Drop for TrafficShaping {
+    fn drop(&mut self) {
+        for probe in self.health_probes.lock().unwrap().drain(..) {
+            probe.abort();
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -251,7 +443,44 @@ impl Plugin for TrafficShaping {
     type Config = Config;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
-        let rate_limit_router = init
+        if init
+            .config
+            .all
+            .as_ref()
+            .map(|all| all.experimental_load_balancing.is_some())
+            .unwrap_or_default()
+        {
+            return Err(ConfigurationError::InvalidConfiguration {
+                message: "bad configuration for traffic_shaping plugin",
+                error:
+                    "experimental_load_balancing can only be set in subgraph specific configuration"
+                        .to_string(),
+            }
+            .into());
+        }
+
+        let distributed_rate_limit = match init
+            .config
+            .router
+            .as_ref()
+            .and_then(|r| r.experimental_distributed_rate_limit.as_ref())
+        {
+            // validating the configuration does not connect to Redis
+            Some(_) if crate::plugin::is_validating() => None,
+            Some(conf) => match RedisCacheStorage::new(conf.urls.clone(), None).await {
+                Ok(storage) => Some((storage, conf.timeout)),
+                Err(e) => {
+                    tracing::error!(
+                        "cannot connect to the distributed rate limit storage, using local rate limits: {}",
+                        e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let mut rate_limit_router = init
             .config
             .router
             .as_ref()
@@ -274,11 +503,81 @@ impl Plugin for TrafficShaping {
             })
             .transpose()?;
 
+        // with a distributed rate limit, the global rate limit is applied with a single partition
+        let distributed_rate_limit_router = match (&distributed_rate_limit, &rate_limit_router) {
+            (Some((storage, timeout)), Some(_)) => init
+                .config
+                .router
+                .as_ref()
+                .and_then(|r| r.global_rate_limit.as_ref())
+                .map(|router_rate_limit_conf| {
+                    PartitionedRateLimitLayer::new(PartitionedRateLimiter::new(
+                        RateLimitKey::Global,
+                        router_rate_limit_conf.capacity,
+                        router_rate_limit_conf.interval,
+                        HashMap::new(),
+                        Some(DistributedRateLimiter::new(
+                            storage.clone(),
+                            "global",
+                            *timeout,
+                        )),
+                    ))
+                }),
+            _ => None,
+        };
+        if distributed_rate_limit_router.is_some() {
+            rate_limit_router = None;
+        }
+
+        let client_rate_limit = init
+            .config
+            .router
+            .as_ref()
+            .and_then(|r| r.experimental_client_rate_limit.as_ref())
+            .map(|client_rate_limit_conf| {
+                PartitionedRateLimitLayer::new(PartitionedRateLimiter::new(
+                    client_rate_limit_conf.key.clone(),
+                    client_rate_limit_conf.capacity,
+                    client_rate_limit_conf.interval,
+                    client_rate_limit_conf
+                        .overrides
+                        .iter()
+                        .map(|(key, conf)| (key.clone(), (conf.capacity, conf.interval)))
+                        .collect(),
+                    distributed_rate_limit.as_ref().map(|(storage, timeout)| {
+                        DistributedRateLimiter::new(storage.clone(), "client", *timeout)
+                    }),
+                ))
+            });
+
+        let load_shedding = init
+            .config
+            .router
+            .as_ref()
+            .and_then(|r| r.experimental_load_shedding.as_ref())
+            .map(|load_shedding_conf| LoadSheddingLayer::new(LoadShedder::new(load_shedding_conf)));
+
+        let load_balancers = init
+            .config
+            .subgraphs
+            .iter()
+            .filter_map(|(name, config)| {
+                config
+                    .experimental_load_balancing
+                    .as_ref()
+                    .map(|lb_config| {
+                        LoadBalancer::new(name.clone(), lb_config)
+                            .map(|balancer| (name.clone(), LoadBalancerLayer::new(balancer)))
+                    })
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
         {
             let storage = if let Some(urls) = init
                 .config
                 .experimental_cache
                 .as_ref()
+                .filter(|_| !crate::plugin::is_validating())
                 .map(|cache| cache.urls.clone())
             {
                 Some(RedisCacheStorage::new(urls, None).await?)
@@ -288,8 +587,15 @@ impl Plugin for TrafficShaping {
             Ok(Self {
                 config: init.config,
                 rate_limit_router,
+                distributed_rate_limit_router,
+                client_rate_limit,
+                load_shedding,
                 rate_limit_subgraphs: Mutex::new(HashMap::new()),
+                circuit_breakers: Arc::new(Mutex::new(HashMap::new())),
+                load_balancers,
                 storage,
+                probed_endpoints: Arc::new(Mutex::new(HashMap::new())),
+                health_probes: Mutex::new(Vec::new()),
             })
         }
     }
@@ -311,8 +617,20 @@ impl TrafficShaping {
         supergraph::Request,
         Response = supergraph::Response,
         Error = BoxError,
-        Future = timeout::future::ResponseFuture<
-            Oneshot<tower::util::Either<rate::service::RateLimit<S>, S>, supergraph::Request>,
+        Future = Either<
+            BoxFuture<'static, Result<supergraph::Response, BoxError>>,
+            Either<
+                BoxFuture<'static, Result<supergraph::Response, BoxError>>,
+                Either<
+                    BoxFuture<'static, Result<supergraph::Response, BoxError>>,
+                    timeout::future::ResponseFuture<
+                        Oneshot<
+                            tower::util::Either<rate::service::RateLimit<S>, S>,
+                            supergraph::Request,
+                        >,
+                    >,
+                >,
+            >,
         >,
     > + Clone
            + Send
@@ -327,6 +645,9 @@ impl TrafficShaping {
         <S as Service<supergraph::Request>>::Future: std::marker::Send,
     {
         ServiceBuilder::new()
+            .option_layer(self.client_rate_limit.clone())
+            .option_layer(self.distributed_rate_limit_router.clone())
+            .option_layer(self.load_shedding.clone())
             .layer(TimeoutLayer::new(
                 self.config
                     .router
@@ -338,6 +659,101 @@ impl TrafficShaping {
             .service(service)
     }
 
+    /// Names of the subgraphs that are currently not sent requests because their circuit breaker is open,
+    /// or whose probed endpoints are all unhealthy
+    pub(crate) fn unhealthy_subgraphs(&self) -> Vec<String> {
+        let mut unhealthy: Vec<String> = self
+            .circuit_breakers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, breaker)| breaker.is_open())
+            .map(|(name, _)| name.clone())
+            .collect();
+        unhealthy.extend(
+            self.probed_endpoints
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, endpoints)| endpoints.values().all(|healthy| !healthy))
+                .map(|(name, _)| name.clone()),
+        );
+        unhealthy.sort();
+        unhealthy.dedup();
+        unhealthy
+    }
+
+    /// Start probing the health of a subgraph, if configured. The load balanced endpoints are
+    /// probed instead of the subgraph URL if there are some.
+    ///
+    /// Failed probes take endpoints out of load balancing and open the subgraph circuit breaker,
+    /// which is half-opened again once the probes succeed. `tls_cert_store` holds the certificate authorities used for the subgraph requests.
+    pub(crate) fn start_health_probes(
+        &self,
+        name: &str,
+        url: &Uri,
+        tls_cert_store: Option<&RootCertStore>,
+    ) -> Result<(), BoxError> {
+        let config =
+            match Self::merge_config(self.config.all.as_ref(), self.config.subgraphs.get(name))
+                .and_then(|config| config.shaping.experimental_health_probe)
+            {
+                Some(config) => config,
+                None => return Ok(()),
+            };
+
+        let load_balancer = self.subgraph_load_balancer(name);
+        let endpoints = match &load_balancer {
+            Some(load_balancer) => load_balancer.endpoints(),
+            None => vec![url.clone()],
+        };
+
+        self.probed_endpoints.lock().unwrap().insert(
+            name.to_string(),
+            endpoints
+                .iter()
+                .map(|endpoint| (endpoint.clone(), true))
+                .collect(),
+        );
+
+        // whether the probes opened the circuit breaker, to only half-open it if they did
+        let tripped = Arc::new(AtomicBool::new(false));
+        let mut health_probes = self.health_probes.lock().unwrap();
+        for endpoint in endpoints {
+            let probe =
+                HealthProbe::new(name.to_string(), endpoint, &config, tls_cert_store.cloned())?;
+            let subgraph_name = name.to_string();
+            let load_balancer = load_balancer.clone();
+            let circuit_breakers = self.circuit_breakers.clone();
+            let probed_endpoints = self.probed_endpoints.clone();
+            let tripped = tripped.clone();
+
+            health_probes.push(probe.spawn(move |endpoint, healthy| {
+                if let Some(load_balancer) = &load_balancer {
+                    load_balancer.set_endpoint_health(endpoint, healthy);
+                }
+
+                let subgraph_healthy = {
+                    let mut probed_endpoints = probed_endpoints.lock().unwrap();
+                    let endpoints = probed_endpoints.entry(subgraph_name.clone()).or_default();
+                    endpoints.insert(endpoint.clone(), healthy);
+                    endpoints.values().any(|healthy| *healthy)
+                };
+                if let Some(circuit_breaker) = circuit_breakers.lock().unwrap().get(&subgraph_name)
+                {
+                    if !subgraph_healthy {
+                        circuit_breaker.trip();
+                        tripped.store(true, Ordering::SeqCst);
+                    } else if tripped.swap(false, Ordering::SeqCst) {
+                        circuit_breaker.half_open();
+                    }
+                }
+            }));
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn subgraph_service_internal<S>(
         &self,
         name: &str,
@@ -351,13 +767,32 @@ impl TrafficShaping {
                 BoxFuture<'static, Result<subgraph::Response, BoxError>>,
                 Either<
                     BoxFuture<'static, Result<subgraph::Response, BoxError>>,
-                    timeout::future::ResponseFuture<
-                        Oneshot<
-                            Either<
-                                Retry<RetryPolicy, Either<rate::service::RateLimit<S>, S>>,
-                                Either<rate::service::RateLimit<S>, S>,
+                    Either<
+                        BoxFuture<'static, Result<subgraph::Response, BoxError>>,
+                        Either<
+                            circuit_breaker::future::ResponseFuture<
+                                timeout::future::ResponseFuture<
+                                    Oneshot<
+                                        Either<
+                                            Retry<
+                                                RetryPolicy,
+                                                Either<rate::service::RateLimit<S>, S>,
+                                            >,
+                                            Either<rate::service::RateLimit<S>, S>,
+                                        >,
+                                        subgraph::Request,
+                                    >,
+                                >,
+                            >,
+                            timeout::future::ResponseFuture<
+                                Oneshot<
+                                    Either<
+                                        Retry<RetryPolicy, Either<rate::service::RateLimit<S>, S>>,
+                                        Either<rate::service::RateLimit<S>, S>,
+                                    >,
+                                    subgraph::Request,
+                                >,
                             >,
-                            subgraph::Request,
                         >,
                     >,
                 >,
@@ -423,12 +858,49 @@ impl TrafficShaping {
                 tower::retry::RetryLayer::new(retry_policy)
             });
 
+            let hedging = config.shaping.experimental_hedging.as_ref().map(|config| {
+                HedgeLayer::new(HedgeState::new(
+                    name.to_string(),
+                    config.delay,
+                    config.percentile,
+                    config.ttl,
+                    config.min_per_sec,
+                    config.hedge_percent,
+                ))
+            });
+
+            let circuit_breaker =
+                config
+                    .shaping
+                    .experimental_circuit_breaker
+                    .as_ref()
+                    .map(|circuit_breaker_conf| {
+                        self.circuit_breakers
+                            .lock()
+                            .unwrap()
+                            .entry(name.to_string())
+                            .or_insert_with(|| {
+                                CircuitBreakerLayer::new(CircuitBreakerState::new(
+                                    Target::Subgraph(name.to_string()),
+                                    circuit_breaker_conf.error_rate_threshold,
+                                    circuit_breaker_conf.latency_threshold,
+                                    circuit_breaker_conf.minimum_requests,
+                                    circuit_breaker_conf.window,
+                                    circuit_breaker_conf.open_duration,
+                                    circuit_breaker_conf.half_open_requests,
+                                ))
+                            })
+                            .clone()
+                    });
+
             Either::A(ServiceBuilder::new()
             .option_layer(entity_caching)
 
                 .option_layer(config.shaping.deduplicate_query.unwrap_or_default().then(
                   QueryDeduplicationLayer::default
                 ))
+                    .option_layer(hedging)
+                    .option_layer(circuit_breaker)
                     .layer(TimeoutLayer::new(
                         config.shaping
                         .timeout
@@ -450,6 +922,10 @@ impl TrafficShaping {
         }
     }
 
+    pub(crate) fn subgraph_load_balancer(&self, service_name: &str) -> Option<LoadBalancerLayer> {
+        self.load_balancers.get(service_name).cloned()
+    }
+
     pub(crate) fn enable_subgraph_http2(&self, service_name: &str) -> bool {
         self.config
             .subgraphs
@@ -471,6 +947,11 @@ mod test {
     use serde_json_bytes::ByteString;
     use serde_json_bytes::Value;
     use tower::Service;
+    use wiremock::matchers::method;
+    use wiremock::matchers::path;
+    use wiremock::Mock;
+    use wiremock::MockServer;
+    use wiremock::ResponseTemplate;
 
     use super::*;
     use crate::json_ext::Object;
@@ -482,6 +963,7 @@ mod test {
     use crate::services::router;
     use crate::services::router_service::RouterCreator;
     use crate::services::PluggableSupergraphServiceBuilder;
+    use crate::services::SubgraphResponse;
     use crate::services::SupergraphRequest;
     use crate::services::SupergraphResponse;
     use crate::Configuration;
@@ -692,6 +1174,30 @@ mod test {
         );
     }
 
+    #[tokio::test]
+    async fn it_rejects_load_balancing_for_all_subgraphs() {
+        let config = serde_yaml::from_str::<serde_json::Value>(
+            r#"
+        all:
+            experimental_load_balancing:
+                endpoints:
+                    - http://localhost:4001
+                    - http://localhost:4002
+        "#,
+        )
+        .unwrap();
+
+        let error = crate::plugin::plugins()
+            .find(|factory| factory.name == APOLLO_TRAFFIC_SHAPING)
+            .expect("Plugin not found")
+            .create_instance_without_schema(&config)
+            .await
+            .expect_err("load balancing must be set per subgraph");
+        assert!(error.to_string().contains(
+            "experimental_load_balancing can only be set in subgraph specific configuration"
+        ));
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn it_rate_limit_subgraph_requests() {
         let config = serde_yaml::from_str::<serde_json::Value>(
@@ -745,6 +1251,150 @@ mod test {
             .unwrap();
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_circuit_breaks_subgraph_requests() {
+        let config = serde_yaml::from_str::<serde_json::Value>(
+            r#"
+        subgraphs:
+            test:
+                experimental_circuit_breaker:
+                    minimum_requests: 2
+                    open_duration: 100ms
+        "#,
+        )
+        .unwrap();
+
+        let plugin = get_traffic_shaping_plugin(&config).await;
+        let shaping = plugin.as_any().downcast_ref::<TrafficShaping>().unwrap();
+
+        let failing_service = tower::service_fn(|_req: SubgraphRequest| async {
+            Err::<SubgraphResponse, BoxError>("connection refused".into())
+        });
+
+        for _ in 0..2 {
+            let err = shaping
+                .subgraph_service_internal("test", failing_service)
+                .oneshot(SubgraphRequest::fake_builder().build())
+                .await
+                .expect_err("the subgraph is failing");
+            assert!(!err.is::<circuit_breaker::error::CircuitOpen>());
+        }
+
+        let err = shaping
+            .subgraph_service_internal("test", failing_service)
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .expect_err("the circuit should be open");
+        assert!(err.is::<circuit_breaker::error::CircuitOpen>());
+
+        // other subgraphs are not affected
+        let _response = shaping
+            .subgraph_service_internal("another", MockSubgraph::new(HashMap::new()))
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .unwrap();
+
+        // once half-open, a successful probe closes the circuit
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let _response = shaping
+            .subgraph_service_internal("test", MockSubgraph::new(HashMap::new()))
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .unwrap();
+        let _response = shaping
+            .subgraph_service_internal("test", MockSubgraph::new(HashMap::new()))
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_reports_subgraphs_healthy_after_the_open_duration() {
+        let config = serde_yaml::from_str::<serde_json::Value>(
+            r#"
+        subgraphs:
+            test:
+                experimental_circuit_breaker:
+                    minimum_requests: 2
+                    open_duration: 100ms
+        "#,
+        )
+        .unwrap();
+
+        let plugin = get_traffic_shaping_plugin(&config).await;
+        let shaping = plugin.as_any().downcast_ref::<TrafficShaping>().unwrap();
+
+        let failing_service = tower::service_fn(|_req: SubgraphRequest| async {
+            Err::<SubgraphResponse, BoxError>("connection refused".into())
+        });
+        for _ in 0..2 {
+            let _ = shaping
+                .subgraph_service_internal("test", failing_service)
+                .oneshot(SubgraphRequest::fake_builder().build())
+                .await;
+        }
+        assert_eq!(shaping.unhealthy_subgraphs(), vec!["test".to_string()]);
+
+        // an unready router gets no traffic, the subgraph must not stay unhealthy without requests
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(shaping.unhealthy_subgraphs().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_half_opens_the_circuit_when_probes_recover() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/healthz"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let config = serde_yaml::from_str::<serde_json::Value>(
+            r#"
+        subgraphs:
+            test:
+                experimental_circuit_breaker:
+                    open_duration: 60s
+                experimental_health_probe:
+                    path: /healthz
+                    interval: 10ms
+                    unhealthy_threshold: 1
+                    healthy_threshold: 1
+        "#,
+        )
+        .unwrap();
+
+        let plugin = get_traffic_shaping_plugin(&config).await;
+        let shaping = plugin.as_any().downcast_ref::<TrafficShaping>().unwrap();
+        // creates the circuit breaker of the subgraph
+        let _service = shaping.subgraph_service_internal("test", MockSubgraph::new(HashMap::new()));
+        shaping
+            .start_health_probes("test", &server.uri().parse().unwrap(), None)
+            .unwrap();
+
+        let wait_for = |unhealthy: Vec<String>| async move {
+            for _ in 0..100 {
+                if shaping.unhealthy_subgraphs() == unhealthy {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("the unhealthy subgraphs should be {unhealthy:?}");
+        };
+        wait_for(vec!["test".to_string()]).await;
+        assert!(shaping.circuit_breakers.lock().unwrap()["test"].is_open());
+
+        server.reset().await;
+        Mock::given(method("GET"))
+            .and(path("/healthz"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        // well before the open duration
+        wait_for(vec![]).await;
+        assert!(!shaping.circuit_breakers.lock().unwrap()["test"].is_open());
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn it_rate_limit_router_requests() {
         let config = serde_yaml::from_str::<serde_json::Value>(
@@ -0,0 +1,225 @@
+//! Rate limits shared by all router instances, stored in Redis.
+//!
+//! Uses a sliding window counter: the number of requests in the current fixed window is added
+//! to the count of the previous window, weighted by how much of it overlaps the sliding window.
+//! Only admitted requests are counted: a rejected request is removed from the current window.
+
+use std::num::NonZeroU64;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use tower::BoxError;
+
+use crate::cache::redis::RedisCacheStorage;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Counters shared by the router instances
+#[async_trait::async_trait]
+pub(crate) trait WindowCounters: Send + Sync + 'static {
+    /// Increments the counter at `key` and returns its new value with the value at `previous_key`
+    async fn incr_window(
+        &self,
+        key: String,
+        previous_key: String,
+        expiration: Duration,
+    ) -> Result<(i64, i64), BoxError>;
+
+    /// Decrements the counter at `key`
+    async fn decr(&self, key: String) -> Result<(), BoxError>;
+}
+
+#[async_trait::async_trait]
+impl WindowCounters for RedisCacheStorage {
+    async fn incr_window(
+        &self,
+        key: String,
+        previous_key: String,
+        expiration: Duration,
+    ) -> Result<(i64, i64), BoxError> {
+        Ok(RedisCacheStorage::incr_window(self, key, previous_key, expiration).await?)
+    }
+
+    async fn decr(&self, key: String) -> Result<(), BoxError> {
+        Ok(RedisCacheStorage::decr(self, key).await?)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct DistributedRateLimiter {
+    storage: Arc<dyn WindowCounters>,
+    prefix: String,
+    timeout: Duration,
+}
+
+impl std::fmt::Debug for DistributedRateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DistributedRateLimiter")
+            .field("prefix", &self.prefix)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl DistributedRateLimiter {
+    pub(crate) fn new(
+        storage: impl WindowCounters,
+        prefix: &str,
+        timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            storage: Arc::new(storage),
+            prefix: format!("apollo_router_rate_limit:{prefix}"),
+            timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// Count a request against the shared quota of the partition.
+    ///
+    /// The inner result is an error with how long to wait before retrying if the quota is
+    /// exceeded. The outer result is an error if Redis could not be reached in time.
+    pub(crate) async fn check(
+        &self,
+        partition: &str,
+        capacity: NonZeroU64,
+        interval: Duration,
+    ) -> Result<Result<(), Duration>, BoxError> {
+        let interval_ms = (interval.as_millis() as u64).max(1);
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let window = now_ms / interval_ms;
+        let elapsed_ms = now_ms % interval_ms;
+
+        let key = format!("{}:{partition}:{window}", self.prefix);
+
+        let (count, previous) = tokio::time::timeout(
+            self.timeout,
+            self.storage.incr_window(
+                key.clone(),
+                format!("{}:{partition}:{}", self.prefix, window.saturating_sub(1)),
+                // the counter is still read as the previous window during the next interval
+                interval * 2,
+            ),
+        )
+        .await??;
+
+        if estimate(count, previous, elapsed_ms as f64 / interval_ms as f64) > capacity.get() as f64
+        {
+            // rejected requests do not use the quota, otherwise clients retrying while they are
+            // limited would never get under the limit
+            let uncounted = tokio::time::timeout(self.timeout, self.storage.decr(key)).await;
+            if let Err(e) = uncounted.map_err(BoxError::from).and_then(|result| result) {
+                tracing::debug!("cannot uncount a rejected request: {}", e);
+            }
+            Ok(Err(Duration::from_millis(interval_ms - elapsed_ms)))
+        } else {
+            Ok(Ok(()))
+        }
+    }
+}
+
+/// Number of requests in the sliding window ending now
+fn estimate(count: i64, previous: i64, elapsed_fraction: f64) -> f64 {
+    previous as f64 * (1.0 - elapsed_fraction) + count as f64
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// In process stand-in for the counters stored in Redis
+    #[derive(Clone, Default)]
+    struct InMemoryCounters {
+        counters: Arc<Mutex<HashMap<String, i64>>>,
+    }
+
+    impl InMemoryCounters {
+        fn total(&self) -> i64 {
+            self.counters.lock().unwrap().values().sum()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl WindowCounters for InMemoryCounters {
+        async fn incr_window(
+            &self,
+            key: String,
+            previous_key: String,
+            _expiration: Duration,
+        ) -> Result<(i64, i64), BoxError> {
+            let mut counters = self.counters.lock().unwrap();
+            let previous = counters.get(&previous_key).copied().unwrap_or_default();
+            let count = counters.entry(key).or_default();
+            *count += 1;
+            Ok((*count, previous))
+        }
+
+        async fn decr(&self, key: String) -> Result<(), BoxError> {
+            *self.counters.lock().unwrap().entry(key).or_default() -= 1;
+            Ok(())
+        }
+    }
+
+    /// Stand-in for a Redis instance that cannot be reached
+    pub(in crate::plugins::traffic_shaping::rate) struct UnavailableCounters;
+
+    #[async_trait::async_trait]
+    impl WindowCounters for UnavailableCounters {
+        async fn incr_window(
+            &self,
+            _key: String,
+            _previous_key: String,
+            _expiration: Duration,
+        ) -> Result<(i64, i64), BoxError> {
+            Err("connection refused".into())
+        }
+
+        async fn decr(&self, _key: String) -> Result<(), BoxError> {
+            Err("connection refused".into())
+        }
+    }
+
+    #[test]
+    fn it_weights_the_previous_window() {
+        assert_eq!(estimate(1, 10, 0.0), 11.0);
+        assert_eq!(estimate(1, 10, 0.5), 6.0);
+        assert_eq!(estimate(1, 10, 1.0), 1.0);
+        assert_eq!(estimate(3, 0, 0.3), 3.0);
+    }
+
+    #[tokio::test]
+    async fn it_shares_the_quota_between_limiters() {
+        let counters = InMemoryCounters::default();
+        let first = DistributedRateLimiter::new(counters.clone(), "client", None);
+        let second = DistributedRateLimiter::new(counters.clone(), "client", None);
+        let capacity = NonZeroU64::new(2).unwrap();
+        let interval = Duration::from_secs(60);
+
+        assert!(first.check("a", capacity, interval).await.unwrap().is_ok());
+        assert!(second.check("a", capacity, interval).await.unwrap().is_ok());
+        assert!(first.check("a", capacity, interval).await.unwrap().is_err());
+        assert!(second
+            .check("a", capacity, interval)
+            .await
+            .unwrap()
+            .is_err());
+        // partitions have their own quota
+        assert!(second.check("b", capacity, interval).await.unwrap().is_ok());
+
+        // rejected requests are not counted
+        assert_eq!(counters.total(), 3);
+    }
+
+    #[tokio::test]
+    async fn it_fails_if_the_counters_cannot_be_reached() {
+        let limiter = DistributedRateLimiter::new(UnavailableCounters, "client", None);
+        assert!(limiter
+            .check("a", NonZeroU64::new(1).unwrap(), Duration::from_secs(60))
+            .await
+            .is_err());
+    }
+}
@@ -2,18 +2,30 @@
 
 use std::error;
 use std::fmt;
+use std::time::Duration;
 
 use axum::response::IntoResponse;
+use http::header::RETRY_AFTER;
+use http::HeaderValue;
 use http::StatusCode;
 
 /// The rate limit error.
-#[derive(Debug, Default)]
-pub(crate) struct RateLimited;
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RateLimited {
+    retry_after: Option<Duration>,
+}
 
 impl RateLimited {
     /// Construct a new RateLimited error
     pub(crate) fn new() -> Self {
-        RateLimited {}
+        RateLimited { retry_after: None }
+    }
+
+    /// Construct a new RateLimited error telling the client when to retry
+    pub(crate) fn with_retry_after(retry_after: Duration) -> Self {
+        RateLimited {
+            retry_after: Some(retry_after),
+        }
     }
 }
 
@@ -25,7 +37,15 @@ impl fmt::Display for RateLimited {
 
 impl IntoResponse for RateLimited {
     fn into_response(self) -> axum::response::Response {
-        (StatusCode::TOO_MANY_REQUESTS, self.to_string()).into_response()
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, self.to_string()).into_response();
+        if let Some(retry_after) = self.retry_after {
+            // Retry-After is expressed in whole seconds, round up so clients do not retry too early
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds.max(1)));
+        }
+        response
     }
 }
 
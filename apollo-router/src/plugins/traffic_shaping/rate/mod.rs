@@ -1,8 +1,10 @@
 //! Limit the rate at which requests are processed.
 
+pub(crate) mod distributed;
 mod error;
 pub(crate) mod future;
 mod layer;
+pub(crate) mod partitioned;
 #[allow(clippy::module_inception)]
 mod rate;
 pub(crate) mod service;
@@ -0,0 +1,415 @@
+//! Rate limits partitioned by client.
+//!
+//! Each client, identified by a key extracted from the request, gets its own token bucket.
+//! When Redis is configured, the quota is shared by all router instances and the local buckets
+//! are only used if Redis cannot be reached.
+
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+
+use super::distributed::DistributedRateLimiter;
+use super::error::RateLimited;
+use crate::axum_factory::ConnectionInfo;
+use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+use crate::plugins::telemetry::CLIENT_NAME;
+use crate::plugins::telemetry::CLIENT_VERSION;
+use crate::services::supergraph;
+
+/// Idle partitions are removed every time this number of requests went through the limiter
+const CLEANUP_EVERY: usize = 10_000;
+
+/// How requests are assigned to a rate limit partition
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub(crate) enum RateLimitKey {
+    /// Value of a client request header
+    Header(String),
+    /// Value of a claim from the JWT validated by the authentication plugin
+    Claim(String),
+    /// Client name and version, as reported to Apollo Studio
+    ClientName,
+    /// IP address of the client connection
+    ClientIp,
+    /// All requests share the same partition
+    #[serde(skip)]
+    Global,
+}
+
+impl RateLimitKey {
+    /// Requests for which the key cannot be extracted share the same partition
+    fn extract(&self, request: &supergraph::Request) -> String {
+        match self {
+            RateLimitKey::Header(name) => request
+                .supergraph_request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            RateLimitKey::Claim(claim) => request
+                .context
+                .get::<_, serde_json::Value>(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+                .ok()
+                .flatten()
+                .and_then(|claims| claims.get(claim).cloned())
+                .map(|value| match value {
+                    serde_json::Value::String(s) => s,
+                    other => other.to_string(),
+                }),
+            RateLimitKey::ClientName => {
+                let name = request
+                    .context
+                    .get::<_, String>(CLIENT_NAME)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                let version = request
+                    .context
+                    .get::<_, String>(CLIENT_VERSION)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                Some(format!("{name}:{version}"))
+            }
+            RateLimitKey::ClientIp => request
+                .supergraph_request
+                .extensions()
+                .get::<ConnectionInfo>()
+                .and_then(|info| info.peer_address)
+                .map(|address| address.ip().to_string()),
+            RateLimitKey::Global => None,
+        }
+        .unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new(capacity: NonZeroU64, interval: Duration) -> Self {
+        let capacity = capacity.get() as f64;
+        Self {
+            capacity,
+            refill_per_sec: capacity / interval.as_secs_f64(),
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token, or return how long to wait until one is available
+    fn acquire(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.refill_per_sec,
+            ))
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        self.tokens + elapsed * self.refill_per_sec >= self.capacity
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PartitionedRateLimiter {
+    key: RateLimitKey,
+    capacity: NonZeroU64,
+    interval: Duration,
+    overrides: HashMap<String, (NonZeroU64, Duration)>,
+    buckets: DashMap<String, Bucket>,
+    calls: AtomicUsize,
+    distributed: Option<DistributedRateLimiter>,
+}
+
+impl PartitionedRateLimiter {
+    pub(crate) fn new(
+        key: RateLimitKey,
+        capacity: NonZeroU64,
+        interval: Duration,
+        overrides: HashMap<String, (NonZeroU64, Duration)>,
+        distributed: Option<DistributedRateLimiter>,
+    ) -> Self {
+        Self {
+            key,
+            capacity,
+            interval,
+            overrides,
+            buckets: DashMap::new(),
+            calls: AtomicUsize::new(0),
+            distributed,
+        }
+    }
+
+    async fn check(&self, partition: String) -> Result<(), RateLimited> {
+        let (capacity, interval) = self
+            .overrides
+            .get(&partition)
+            .copied()
+            .unwrap_or((self.capacity, self.interval));
+
+        let result = match &self.distributed {
+            Some(distributed) => match distributed.check(&partition, capacity, interval).await {
+                Ok(result) => result,
+                Err(e) => {
+                    tracing::warn!(
+                        monotonic_counter.apollo_router_distributed_rate_limit_fallback_total =
+                            1u64,
+                        "cannot reach the distributed rate limiter, using local limits: {}",
+                        e
+                    );
+                    self.check_local(partition, capacity, interval)
+                }
+            },
+            None => self.check_local(partition, capacity, interval),
+        };
+
+        result.map_err(|retry_after| {
+            tracing::info!(
+                monotonic_counter.apollo_router_client_rate_limited_total = 1u64,
+                "client rate limit exceeded"
+            );
+            RateLimited::with_retry_after(retry_after)
+        })
+    }
+
+    fn check_local(
+        &self,
+        partition: String,
+        capacity: NonZeroU64,
+        interval: Duration,
+    ) -> Result<(), Duration> {
+        if self.calls.fetch_add(1, Ordering::Relaxed) % CLEANUP_EVERY == CLEANUP_EVERY - 1 {
+            // buckets that refilled completely are equivalent to new ones
+            self.buckets.retain(|_, bucket| !bucket.is_idle());
+        }
+
+        let mut bucket = self
+            .buckets
+            .entry(partition)
+            .or_insert_with(|| Bucket::new(capacity, interval));
+
+        bucket.acquire()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PartitionedRateLimitLayer {
+    limiter: Arc<PartitionedRateLimiter>,
+}
+
+impl PartitionedRateLimitLayer {
+    pub(crate) fn new(limiter: PartitionedRateLimiter) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+        }
+    }
+}
+
+impl<S> Layer<S> for PartitionedRateLimitLayer {
+    type Service = PartitionedRateLimit<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        PartitionedRateLimit {
+            inner: service,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PartitionedRateLimit<S> {
+    inner: S,
+    limiter: Arc<PartitionedRateLimiter>,
+}
+
+impl<S> Service<supergraph::Request> for PartitionedRateLimit<S>
+where
+    S: Service<supergraph::Request, Response = supergraph::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = supergraph::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: supergraph::Request) -> Self::Future {
+        let partition = self.limiter.key.extract(&request);
+        let limiter = self.limiter.clone();
+        // call the service that was made ready in poll_ready, polling a clone would count the
+        // request again in the layers below
+        let clone = self.inner.clone();
+        let mut service = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            limiter.check(partition).await?;
+            service.call(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugins::traffic_shaping::rate::distributed::test::UnavailableCounters;
+    use crate::plugins::traffic_shaping::rate::RateLimitLayer;
+
+    fn limiter(key: RateLimitKey) -> PartitionedRateLimiter {
+        let mut overrides = HashMap::new();
+        overrides.insert(
+            "premium".to_string(),
+            (NonZeroU64::new(2).unwrap(), Duration::from_secs(60)),
+        );
+        PartitionedRateLimiter::new(
+            key,
+            NonZeroU64::new(1).unwrap(),
+            Duration::from_secs(60),
+            overrides,
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn it_limits_each_partition_separately() {
+        let limiter = limiter(RateLimitKey::Header("x-client".to_string()));
+        assert!(limiter.check("a".to_string()).await.is_ok());
+        assert!(limiter.check("a".to_string()).await.is_err());
+        assert!(limiter.check("b".to_string()).await.is_ok());
+
+        assert!(limiter.check("premium".to_string()).await.is_ok());
+        assert!(limiter.check("premium".to_string()).await.is_ok());
+        assert!(limiter.check("premium".to_string()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_to_local_limits_if_redis_is_down() {
+        let limiter = PartitionedRateLimiter::new(
+            RateLimitKey::Header("x-client".to_string()),
+            NonZeroU64::new(1).unwrap(),
+            Duration::from_secs(60),
+            HashMap::new(),
+            Some(DistributedRateLimiter::new(
+                UnavailableCounters,
+                "client",
+                None,
+            )),
+        );
+        assert!(limiter.check("a".to_string()).await.is_ok());
+        assert!(limiter.check("a".to_string()).await.is_err());
+        assert!(limiter.check("b".to_string()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_counts_each_request_once_in_the_global_rate_limit() {
+        let global_rate_limit =
+            RateLimitLayer::new(NonZeroU64::new(10).unwrap(), Duration::from_secs(60)).layer(
+                tower::service_fn(|request: supergraph::Request| async move {
+                    supergraph::Response::fake_builder()
+                        .context(request.context)
+                        .build()
+                }),
+            );
+        let mut service = PartitionedRateLimitLayer::new(PartitionedRateLimiter::new(
+            RateLimitKey::Global,
+            NonZeroU64::new(10).unwrap(),
+            Duration::from_secs(60),
+            HashMap::new(),
+            None,
+        ))
+        .layer(global_rate_limit);
+
+        for _ in 0..2 {
+            let request = supergraph::Request::fake_builder().build().unwrap();
+            service.ready().await.unwrap().call(request).await.unwrap();
+        }
+        // the global rate limit window starts at 1
+        assert_eq!(service.inner.current_nb_requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn it_extracts_keys() {
+        let request = supergraph::Request::fake_builder()
+            .header("x-client", "a")
+            .build()
+            .unwrap();
+        assert_eq!(
+            RateLimitKey::Header("x-client".to_string()).extract(&request),
+            "a"
+        );
+        assert_eq!(
+            RateLimitKey::Header("x-other".to_string()).extract(&request),
+            ""
+        );
+
+        request
+            .context
+            .insert(
+                APOLLO_AUTHENTICATION_JWT_CLAIMS,
+                serde_json::json!({ "sub": "user-1", "tier": 2 }),
+            )
+            .unwrap();
+        assert_eq!(
+            RateLimitKey::Claim("sub".to_string()).extract(&request),
+            "user-1"
+        );
+        assert_eq!(
+            RateLimitKey::Claim("tier".to_string()).extract(&request),
+            "2"
+        );
+
+        request
+            .context
+            .insert(CLIENT_NAME, "web".to_string())
+            .unwrap();
+        request
+            .context
+            .insert(CLIENT_VERSION, "1.0".to_string())
+            .unwrap();
+        assert_eq!(RateLimitKey::ClientName.extract(&request), "web:1.0");
+        assert_eq!(RateLimitKey::Global.extract(&request), "");
+    }
+
+    #[test]
+    fn it_computes_retry_after() {
+        let mut bucket = Bucket::new(NonZeroU64::new(1).unwrap(), Duration::from_secs(10));
+        assert!(bucket.acquire().is_ok());
+        let retry_after = bucket.acquire().unwrap_err();
+        assert!(retry_after > Duration::from_secs(9) && retry_after <= Duration::from_secs(10));
+    }
+}
@@ -61,6 +61,10 @@ where
         }
     }
 
+    pub(crate) async fn cache_size(&self) -> usize {
+        self.cache.len().await
+    }
+
     pub(crate) async fn cache_keys(&self, count: usize) -> Vec<(String, Option<String>)> {
         let keys = self.cache.in_memory_keys().await;
         keys.into_iter()
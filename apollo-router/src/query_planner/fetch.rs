@@ -3,6 +3,7 @@ use std::fmt::Display;
 use std::sync::Arc;
 
 use indexmap::IndexSet;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 use tower::ServiceExt;
@@ -27,7 +28,7 @@ use crate::services::SubgraphRequest;
 use crate::spec::Schema;
 
 /// GraphQL operation type.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 #[non_exhaustive]
 pub enum OperationKind {
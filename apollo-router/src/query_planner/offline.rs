@@ -0,0 +1,488 @@
+//! Query planning and operation validation without a running router, to review client operations
+//! and their plans before deploying them or a new supergraph schema
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use serde_json::json;
+use serde_json::Value;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use super::BridgeQueryPlanner;
+use super::PlanNode;
+use crate::services::QueryPlannerContent;
+use crate::services::QueryPlannerRequest;
+use crate::spec::Query;
+use crate::Configuration;
+use crate::Context;
+
+/// A GraphQL document to plan, and where it comes from
+pub(crate) struct OperationDocument {
+    pub(crate) source: String,
+    pub(crate) document: String,
+}
+
+/// The plans of a list of operations, printed as JSON or as readable trees
+pub(crate) struct PlanReport {
+    pub(crate) output: String,
+    /// Operations that could not be planned
+    pub(crate) failures: usize,
+}
+
+/// The validation of a list of operations
+pub(crate) struct ValidationReport {
+    pub(crate) output: String,
+    /// Documents that the router would reject
+    pub(crate) failures: usize,
+}
+
+/// What planning an operation with one supergraph gave
+enum Outcome {
+    Planned {
+        root: PlanNode,
+        formatted: Option<String>,
+    },
+    Introspection,
+    Failed(String),
+}
+
+/// Shape of a query plan, to spot fan-out regressions
+#[derive(Debug, Default, PartialEq)]
+struct PlanStats {
+    /// Subgraph requests made by the plan
+    fetches: usize,
+    /// Longest chain of subgraph requests that must be made one after the other
+    depth: usize,
+    subgraphs: BTreeSet<String>,
+}
+
+impl PlanStats {
+    fn new(node: &PlanNode) -> Self {
+        let mut subgraphs = BTreeSet::new();
+        let (fetches, depth) = visit(node, &mut subgraphs);
+        Self {
+            fetches,
+            depth,
+            subgraphs,
+        }
+    }
+}
+
+/// Fetches and sequential depth of a plan node
+fn visit(node: &PlanNode, subgraphs: &mut BTreeSet<String>) -> (usize, usize) {
+    match node {
+        PlanNode::Fetch(fetch) => {
+            subgraphs.insert(fetch.service_name.clone());
+            (1, 1)
+        }
+        PlanNode::Sequence { nodes } => nodes.iter().fold((0, 0), |(fetches, depth), node| {
+            let (node_fetches, node_depth) = visit(node, subgraphs);
+            (fetches + node_fetches, depth + node_depth)
+        }),
+        PlanNode::Parallel { nodes } => nodes.iter().fold((0, 0), |(fetches, depth), node| {
+            let (node_fetches, node_depth) = visit(node, subgraphs);
+            (fetches + node_fetches, depth.max(node_depth))
+        }),
+        PlanNode::Flatten(flatten) => visit(&flatten.node, subgraphs),
+        PlanNode::Defer { primary, deferred } => {
+            let (mut fetches, primary_depth) = primary
+                .node
+                .as_ref()
+                .map(|node| visit(node, subgraphs))
+                .unwrap_or_default();
+            let mut deferred_depth = 0;
+            for node in deferred
+                .iter()
+                .filter_map(|deferred| deferred.node.as_ref())
+            {
+                let (node_fetches, node_depth) = visit(node, subgraphs);
+                fetches += node_fetches;
+                deferred_depth = deferred_depth.max(node_depth);
+            }
+            (fetches, primary_depth + deferred_depth)
+        }
+        PlanNode::Condition {
+            if_clause,
+            else_clause,
+            ..
+        } => [if_clause, else_clause]
+            .into_iter()
+            .flatten()
+            .map(|node| visit(node, subgraphs))
+            .fold((0, 0), |(fetches, depth), (node_fetches, node_depth)| {
+                (fetches.max(node_fetches), depth.max(node_depth))
+            }),
+    }
+}
+
+/// Plan every operation of the documents with `supergraph`, and compare the plans with the ones
+/// of `previous_supergraph` if provided
+pub(crate) async fn plan_operations(
+    configuration: Arc<Configuration>,
+    supergraph: String,
+    previous_supergraph: Option<String>,
+    documents: Vec<OperationDocument>,
+    json: bool,
+) -> Result<PlanReport, BoxError> {
+    let planner = BridgeQueryPlanner::new(supergraph, configuration.clone()).await?;
+    let previous_planner = match previous_supergraph {
+        Some(previous_supergraph) => {
+            Some(BridgeQueryPlanner::new(previous_supergraph, configuration.clone()).await?)
+        }
+        None => None,
+    };
+
+    let mut output = String::new();
+    let mut reports = Vec::new();
+    let mut failures = 0;
+    for OperationDocument { source, document } in documents {
+        let operation_names = match Query::parse(&document, &planner.schema(), &configuration) {
+            Ok(query) => query
+                .operations
+                .iter()
+                .map(|operation| operation.name().map(ToString::to_string))
+                .collect(),
+            // the planner reports the error
+            Err(_) => vec![None],
+        };
+
+        for operation_name in operation_names {
+            let source = match &operation_name {
+                Some(name) => format!("{source} ({name})"),
+                None => source.clone(),
+            };
+            let outcome = plan(&planner, &document, operation_name.clone()).await;
+            let previous = match &previous_planner {
+                Some(previous_planner) => {
+                    Some(plan(previous_planner, &document, operation_name).await)
+                }
+                None => None,
+            };
+            if matches!(outcome, Outcome::Failed(_)) {
+                failures += 1;
+            }
+
+            if json {
+                reports.push(json_report(&source, &outcome, previous.as_ref()));
+            } else {
+                write_report(&mut output, &source, &outcome, previous.as_ref());
+            }
+        }
+    }
+
+    if json {
+        output = serde_json::to_string_pretty(&reports)?;
+        output.push('\n');
+    }
+    Ok(PlanReport { output, failures })
+}
+
+/// Validate the operations of the documents against `supergraph` like the router does when it
+/// receives them, and report their deprecated fields, depth and estimated cost
+pub(crate) async fn validate_operations(
+    configuration: Arc<Configuration>,
+    supergraph: String,
+    documents: Vec<OperationDocument>,
+) -> Result<ValidationReport, BoxError> {
+    let planner = BridgeQueryPlanner::new(supergraph, configuration.clone()).await?;
+    let schema = planner.schema();
+    let api_schema = schema.api_schema();
+
+    let mut output = String::new();
+    let mut failures = 0;
+    for OperationDocument { source, document } in documents {
+        let _ = writeln!(output, "# {source}");
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+        if let Some(max_request_bytes) = configuration
+            .server
+            .experimental_http_max_request_bytes
+            .filter(|limit| document.len() > *limit)
+        {
+            errors.push(format!(
+                "the document is {} bytes, above the request limit of {max_request_bytes} bytes",
+                document.len()
+            ));
+        }
+
+        match Query::parse(&document, api_schema, &configuration) {
+            Ok(query) => {
+                // the query planner decides whether the router accepts an operation, the
+                // diagnostics of the compiler are only reported
+                let compiler = query.uncached_compiler(Some(api_schema));
+                warnings.extend(
+                    compiler
+                        .validate()
+                        .into_iter()
+                        .map(|diagnostic| diagnostic.to_string()),
+                );
+                if !configuration.supergraph.introspection && query.contains_introspection() {
+                    errors.push("introspection is disabled".to_string());
+                }
+
+                for operation in &query.operations {
+                    let name = operation.name();
+                    let _ = writeln!(output, "operation {}:", name.unwrap_or("(anonymous)"));
+                    if let Outcome::Failed(error) =
+                        plan(&planner, &document, name.map(ToString::to_string)).await
+                    {
+                        errors.push(format!(
+                            "operation {}: {error}",
+                            name.unwrap_or("(anonymous)")
+                        ));
+                    }
+                    let mut deprecated = query
+                        .schema_coordinates(name, api_schema)
+                        .into_iter()
+                        .filter_map(|coordinate| {
+                            let reason = api_schema.deprecated_fields.get(&coordinate)?;
+                            Some(match reason {
+                                Some(reason) => format!("{coordinate} is deprecated: {reason}"),
+                                None => format!("{coordinate} is deprecated"),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    deprecated.sort();
+                    for warning in deprecated {
+                        let _ = writeln!(output, "  warning: {warning}");
+                    }
+                    if let Some((depth, cost)) = query.complexity(name) {
+                        let _ = writeln!(output, "  depth: {depth}, estimated cost: {cost}");
+                    }
+                }
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+
+        for warning in warnings {
+            let _ = writeln!(output, "warning: {warning}");
+        }
+        if errors.is_empty() {
+            let _ = writeln!(output, "valid");
+        } else {
+            failures += 1;
+            for error in errors {
+                let _ = writeln!(output, "error: {error}");
+            }
+        }
+        output.push('\n');
+    }
+    Ok(ValidationReport { output, failures })
+}
+
+async fn plan(
+    planner: &BridgeQueryPlanner,
+    document: &str,
+    operation_name: Option<String>,
+) -> Outcome {
+    let request = QueryPlannerRequest::builder()
+        .query(document)
+        .and_operation_name(operation_name)
+        .context(Context::new())
+        .build();
+    match planner.clone().oneshot(request).await {
+        Ok(response) => match response.content {
+            Some(QueryPlannerContent::Plan { plan }) => Outcome::Planned {
+                root: plan.root.clone(),
+                formatted: plan.formatted_query_plan.clone(),
+            },
+            Some(QueryPlannerContent::Introspection { .. })
+            | Some(QueryPlannerContent::IntrospectionDisabled) => Outcome::Introspection,
+            None => Outcome::Failed("the query planner did not return a plan".to_string()),
+        },
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+/// Fan-out regressions between the previous and the new plan
+fn warnings(outcome: &Outcome, previous: Option<&Outcome>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    match (outcome, previous) {
+        (Outcome::Introspection, _) => warnings.push(
+            "introspection operation, the router answers it without a query plan".to_string(),
+        ),
+        (Outcome::Failed(_), Some(Outcome::Planned { .. })) => {
+            warnings.push("the operation could be planned with the previous supergraph".to_string())
+        }
+        (
+            Outcome::Planned { root, .. },
+            Some(Outcome::Planned {
+                root: previous_root,
+                ..
+            }),
+        ) => {
+            let (stats, previous_stats) = (PlanStats::new(root), PlanStats::new(previous_root));
+            if stats.fetches > previous_stats.fetches {
+                warnings.push(format!(
+                    "subgraph requests increased from {} to {}",
+                    previous_stats.fetches, stats.fetches
+                ));
+            }
+            if stats.depth > previous_stats.depth {
+                warnings.push(format!(
+                    "sequential subgraph requests increased from {} to {}",
+                    previous_stats.depth, stats.depth
+                ));
+            }
+            let added = stats
+                .subgraphs
+                .difference(&previous_stats.subgraphs)
+                .cloned()
+                .collect::<Vec<_>>();
+            if !added.is_empty() {
+                warnings.push(format!("now calls {}", added.join(", ")));
+            }
+        }
+        _ => {}
+    }
+    warnings
+}
+
+fn json_report(source: &str, outcome: &Outcome, previous: Option<&Outcome>) -> Value {
+    fn outcome_json(outcome: &Outcome) -> Value {
+        match outcome {
+            Outcome::Planned { root, .. } => {
+                let stats = PlanStats::new(root);
+                json!({
+                    "plan": root,
+                    "fetches": stats.fetches,
+                    "depth": stats.depth,
+                    "subgraphs": stats.subgraphs,
+                })
+            }
+            Outcome::Introspection => json!({ "introspection": true }),
+            Outcome::Failed(error) => json!({ "error": error }),
+        }
+    }
+
+    let mut report = json!({ "operation": source });
+    let object = report.as_object_mut().expect("created as an object");
+    if let Value::Object(outcome) = outcome_json(outcome) {
+        object.extend(outcome);
+    }
+    object.insert("warnings".to_string(), json!(warnings(outcome, previous)));
+    if let Some(previous) = previous {
+        object.insert("previous".to_string(), outcome_json(previous));
+    }
+    report
+}
+
+fn write_report(output: &mut String, source: &str, outcome: &Outcome, previous: Option<&Outcome>) {
+    let _ = writeln!(output, "# {source}");
+    match outcome {
+        Outcome::Planned { root, formatted } => {
+            let formatted = formatted_plan(root, formatted.as_deref());
+            match previous {
+                Some(Outcome::Planned {
+                    root: previous_root,
+                    formatted: previous_formatted,
+                }) => {
+                    let previous_formatted =
+                        formatted_plan(previous_root, previous_formatted.as_deref());
+                    if previous_formatted == formatted {
+                        let _ = writeln!(output, "{formatted}");
+                        let _ = writeln!(output, "plan unchanged");
+                    } else {
+                        for line in diff::lines(&previous_formatted, &formatted) {
+                            let _ = match line {
+                                diff::Result::Left(line) => writeln!(output, "-{line}"),
+                                diff::Result::Both(line, _) => writeln!(output, " {line}"),
+                                diff::Result::Right(line) => writeln!(output, "+{line}"),
+                            };
+                        }
+                    }
+                }
+                _ => {
+                    let _ = writeln!(output, "{formatted}");
+                }
+            }
+            let stats = PlanStats::new(root);
+            let _ = writeln!(
+                output,
+                "subgraph requests: {}, sequential: {}, subgraphs: {}",
+                stats.fetches,
+                stats.depth,
+                stats.subgraphs.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+        Outcome::Introspection => {}
+        Outcome::Failed(error) => {
+            let _ = writeln!(output, "error: {error}");
+        }
+    }
+    for warning in warnings(outcome, previous) {
+        let _ = writeln!(output, "warning: {warning}");
+    }
+    output.push('\n');
+}
+
+/// The plan as printed by the query planner, or its JSON representation if not available
+fn formatted_plan(root: &PlanNode, formatted: Option<&str>) -> String {
+    match formatted {
+        Some(formatted) => formatted.trim_end().to_string(),
+        None => serde_json::to_string_pretty(root).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_computes_plan_stats() {
+        let root: PlanNode = serde_json::from_value(json!({
+            "kind": "Sequence",
+            "nodes": [
+                {
+                    "kind": "Fetch",
+                    "serviceName": "accounts",
+                    "variableUsages": [],
+                    "operation": "{me{__typename id}}",
+                    "operationKind": "query"
+                },
+                {
+                    "kind": "Parallel",
+                    "nodes": [
+                        {
+                            "kind": "Flatten",
+                            "path": ["me"],
+                            "node": {
+                                "kind": "Fetch",
+                                "serviceName": "reviews",
+                                "variableUsages": [],
+                                "operation": "{reviews{id}}",
+                                "operationKind": "query"
+                            }
+                        },
+                        {
+                            "kind": "Flatten",
+                            "path": ["me"],
+                            "node": {
+                                "kind": "Fetch",
+                                "serviceName": "inventory",
+                                "variableUsages": [],
+                                "operation": "{stock}",
+                                "operationKind": "query"
+                            }
+                        }
+                    ]
+                }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            PlanStats::new(&root),
+            PlanStats {
+                fetches: 3,
+                depth: 2,
+                subgraphs: ["accounts", "inventory", "reviews"]
+                    .into_iter()
+                    .map(ToString::to_string)
+                    .collect(),
+            }
+        );
+    }
+}
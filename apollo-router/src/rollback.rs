@@ -0,0 +1,216 @@
+//! Rollback of reloads that make the router fail more often than before.
+//!
+//! After a reload, the error rate of the new router is watched for a while and compared to the
+//! error rate of the previous one. If it increased too much, the state machine applies the
+//! previous configuration and schema again.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tracing::instrument::WithSubscriber;
+
+use crate::configuration::Configuration;
+use crate::configuration::ReloadRollback;
+use crate::Context;
+
+/// Set in the request context when a subgraph request failed
+pub(crate) const SUBGRAPH_FAILED: &str = "apollo_router::rollback::subgraph_failed";
+
+/// How many times the error rate is checked during the watch window
+const CHECKS: u32 = 10;
+
+/// Counts the requests handled by a router, and how many of them failed.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RequestOutcomes {
+    requests: Arc<AtomicU64>,
+    failures: Arc<AtomicU64>,
+}
+
+impl RequestOutcomes {
+    pub(crate) fn record(&self, failed: bool) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Outcomes {
+        Outcomes {
+            requests: self.requests.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Requests handled and failed at some point in time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Outcomes {
+    pub(crate) requests: u64,
+    pub(crate) failures: u64,
+}
+
+impl Outcomes {
+    fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.requests as f64
+        }
+    }
+}
+
+/// Remember that a subgraph request failed, so the client request is counted as failed
+pub(crate) fn record_subgraph_failure(context: &Context) {
+    if let Err(e) = context.insert(SUBGRAPH_FAILED, true) {
+        tracing::debug!("could not record the subgraph failure: {}", e);
+    }
+}
+
+pub(crate) fn subgraph_failed(context: &Context) -> bool {
+    context
+        .get::<_, bool>(SUBGRAPH_FAILED)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// Asks the state machine to apply the previous configuration and schema again
+pub(crate) struct RollBack {
+    /// The router generation to roll back, the request is ignored if it was already replaced
+    pub(crate) generation: u64,
+    pub(crate) configuration: Arc<Configuration>,
+    pub(crate) schema: Arc<String>,
+    pub(crate) reason: String,
+}
+
+/// Watch the error rate of a new router for the configured window, and send `rollback` if it
+/// increased too much compared to the previous router.
+pub(crate) fn watch(
+    settings: ReloadRollback,
+    previous: Outcomes,
+    current: RequestOutcomes,
+    mut rollback: RollBack,
+    sender: mpsc::Sender<RollBack>,
+) {
+    let task = async move {
+        let period = settings.window / CHECKS;
+        for _ in 0..CHECKS {
+            tokio::time::sleep(period).await;
+            if let Some(reason) = check(&settings, previous, current.snapshot()) {
+                rollback.reason = reason;
+                if let Err(e) = sender.send(rollback).await {
+                    tracing::debug!("failed to request the rollback. This is likely to be because the router is shutting down: {e}");
+                }
+                return;
+            }
+        }
+        tracing::debug!(
+            "the error rate did not increase within {} after the reload",
+            humantime::format_duration(settings.window)
+        );
+    };
+    drop(tokio::task::spawn(task.with_current_subscriber()));
+}
+
+/// Why the new router should be rolled back, if it should
+fn check(settings: &ReloadRollback, previous: Outcomes, current: Outcomes) -> Option<String> {
+    if current.requests < settings.min_requests.max(1) {
+        return None;
+    }
+    let (previous_rate, current_rate) = (previous.error_rate(), current.error_rate());
+    (current_rate - previous_rate > settings.max_error_rate_increase).then(|| {
+        format!(
+            "the error rate went from {:.1}% to {:.1}% ({} failed out of {} requests) after the reload, above the allowed increase of {:.1}%",
+            previous_rate * 100.0,
+            current_rate * 100.0,
+            current.failures,
+            current.requests,
+            settings.max_error_rate_increase * 100.0
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn it_checks_the_error_rate_increase() {
+        let settings = ReloadRollback::builder()
+            .min_requests(10)
+            .max_error_rate_increase(0.2)
+            .build();
+        let previous = Outcomes {
+            requests: 1000,
+            failures: 100,
+        };
+
+        // not enough requests yet
+        assert!(check(
+            &settings,
+            previous,
+            Outcomes {
+                requests: 9,
+                failures: 9
+            }
+        )
+        .is_none());
+        // 10% to 25%
+        assert!(check(
+            &settings,
+            previous,
+            Outcomes {
+                requests: 100,
+                failures: 25
+            }
+        )
+        .is_none());
+        // 10% to 50%
+        assert_eq!(
+            check(
+                &settings,
+                previous,
+                Outcomes {
+                    requests: 100,
+                    failures: 50
+                }
+            )
+            .unwrap(),
+            "the error rate went from 10.0% to 50.0% (50 failed out of 100 requests) after the reload, above the allowed increase of 20.0%"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_requests_a_rollback() {
+        let settings = ReloadRollback::builder()
+            .enabled(true)
+            .window(Duration::from_millis(50))
+            .min_requests(2)
+            .build();
+        let current = RequestOutcomes::default();
+        current.record(true);
+        current.record(true);
+        let (sender, mut receiver) = mpsc::channel(1);
+
+        watch(
+            settings,
+            Outcomes::default(),
+            current,
+            RollBack {
+                generation: 3,
+                configuration: Default::default(),
+                schema: Arc::new("type Query { me: String }".to_string()),
+                reason: String::new(),
+            },
+            sender,
+        );
+
+        let rollback = receiver.recv().await.unwrap();
+        assert_eq!(rollback.generation, 3);
+        assert!(rollback.reason.contains("from 0.0% to 100.0%"));
+    }
+}
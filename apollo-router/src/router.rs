@@ -6,7 +6,6 @@ use std::fmt::Debug;
 use std::fmt::Formatter;
 use std::fs;
 use std::net::IpAddr;
-use std::path::Path;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::str::FromStr;
@@ -21,6 +20,7 @@ use derive_more::From;
 use displaydoc::Display as DisplayDoc;
 use futures::channel::oneshot;
 use futures::prelude::*;
+use futures::stream::BoxStream;
 use futures::FutureExt;
 use http_body::Body as _;
 use hyper::Body;
@@ -37,11 +37,15 @@ use self::Event::NoMoreSchema;
 use self::Event::Shutdown;
 use self::Event::UpdateConfiguration;
 use self::Event::UpdateSchema;
+use crate::admin::ReloadTrigger;
 use crate::axum_factory::make_axum_router;
 use crate::axum_factory::AxumHttpServerFactory;
 use crate::axum_factory::ListenAddrAndRouter;
+use crate::configuration::configuration_files;
+use crate::configuration::merge_yaml_configurations;
 use crate::configuration::Configuration;
 use crate::configuration::ListenAddr;
+use crate::last_known_good::LastKnownGood;
 use crate::orbiter::OrbiterRouterSuperServiceFactory;
 use crate::plugin::DynPlugin;
 use crate::router::Event::NoMoreEntitlement;
@@ -78,6 +82,7 @@ async fn make_router_service<RF>(
             schema.to_string(),
             None,
             Some(extra_plugins),
+            false,
         )
         .await?;
     let web_endpoints = service_factory.web_endpoints();
@@ -175,6 +180,23 @@ pub enum SchemaSource {
         delay: Option<Duration>,
     },
 
+    /// A schema served over HTTP, polled for changes.
+    #[display(fmt = "Url")]
+    Url {
+        /// The URL of the schema.
+        url: Url,
+
+        /// Headers sent with every request, to authenticate for example.
+        #[derivative(Debug = "ignore")]
+        headers: http::HeaderMap,
+
+        /// The duration between polling.
+        poll_interval: Duration,
+
+        /// The HTTP client timeout for each poll.
+        timeout: Duration,
+    },
+
     /// Apollo managed federation.
     #[display(fmt = "Registry")]
     Registry {
@@ -205,7 +227,9 @@ impl From<&'_ str> for SchemaSource {
 
 impl SchemaSource {
     /// Convert this schema into a stream regardless of if is static or not. Allows for unified handling later.
-    fn into_stream(self) -> impl Stream<Item = Event> {
+    ///
+    /// Remote sources fall back to the last known good schema, if any, when they do not answer in time.
+    fn into_stream(self, last_known_good: Option<&LastKnownGood>) -> impl Stream<Item = Event> {
         match self {
             SchemaSource::Static { schema_sdl: schema } => {
                 stream::once(future::ready(UpdateSchema(schema))).boxed()
@@ -246,6 +270,15 @@ impl SchemaSource {
                     }
                 }
             }
+            SchemaSource::Url {
+                url,
+                headers,
+                poll_interval,
+                timeout,
+            } => with_schema_fallback(
+                crate::urls::poll(url, headers, poll_interval, timeout),
+                last_known_good,
+            ),
             SchemaSource::Registry {
                 apollo_key,
                 apollo_graph_ref,
@@ -255,7 +288,7 @@ impl SchemaSource {
             } => {
                 // With regards to ELv2 licensing, the code inside this block
                 // is license key functionality
-                stream_from_uplink::<SupergraphSdlQuery, String>(
+                let schemas = stream_from_uplink::<SupergraphSdlQuery, String>(
                     apollo_key,
                     apollo_graph_ref,
                     urls.map(Endpoints::fallback),
@@ -264,18 +297,54 @@ impl SchemaSource {
                 )
                 .filter_map(|res| {
                     future::ready(match res {
-                        Ok(schema) => Some(UpdateSchema(schema)),
+                        Ok(schema) => Some(schema),
                         Err(e) => {
                             tracing::error!("{}", e);
                             None
                         }
                     })
-                })
-                .boxed()
+                });
+                with_schema_fallback(schemas, last_known_good)
             }
         }
         .chain(stream::iter(vec![NoMoreSchema]))
     }
+
+    /// Read the schema file again each time a reload is triggered. Other sources ignore reloads.
+    fn reload_stream(&self, reload_trigger: &ReloadTrigger) -> impl Stream<Item = Event> {
+        match self {
+            #[allow(deprecated)]
+            SchemaSource::File { path, .. } => {
+                let path = path.clone();
+                reload_trigger
+                    .subscribe("schema")
+                    .filter_map(move |()| {
+                        future::ready(match std::fs::read_to_string(&path) {
+                            Ok(schema) => Some(UpdateSchema(schema)),
+                            Err(err) => {
+                                tracing::error!("Failed to read schema: {}", err);
+                                None
+                            }
+                        })
+                    })
+                    .boxed()
+            }
+            _ => stream::empty().boxed(),
+        }
+    }
+}
+
+fn with_schema_fallback(
+    schemas: impl Stream<Item = String> + Send + 'static,
+    last_known_good: Option<&LastKnownGood>,
+) -> BoxStream<'static, Event> {
+    match last_known_good {
+        Some(last_known_good) => last_known_good
+            .schema_fallback(schemas)
+            .map(UpdateSchema)
+            .boxed(),
+        None => schemas.map(UpdateSchema).boxed(),
+    }
 }
 
 type ConfigurationStream = Pin<Box<dyn Stream<Item = Configuration> + Send>>;
@@ -312,6 +381,33 @@ pub enum ConfigurationSource {
         #[deprecated]
         delay: Option<Duration>,
     },
+
+    /// A yaml configuration served over HTTP, polled for changes.
+    #[display(fmt = "Url")]
+    Url {
+        /// The URL of the configuration.
+        url: Url,
+
+        /// Headers sent with every request, to authenticate for example.
+        #[derivative(Debug = "ignore")]
+        headers: http::HeaderMap,
+
+        /// The duration between polling.
+        poll_interval: Duration,
+
+        /// The HTTP client timeout for each poll.
+        timeout: Duration,
+    },
+
+    /// Yaml files, or directories of yaml files, merged in order, that may be watched for changes
+    #[display(fmt = "Files")]
+    Files {
+        /// The paths of the configuration files, or of directories containing them.
+        paths: Vec<PathBuf>,
+
+        /// `true` to watch the files for changes and hot apply them.
+        watch: bool,
+    },
 }
 
 impl Default for ConfigurationSource {
@@ -333,80 +429,150 @@ impl ConfigurationSource {
                 path,
                 watch,
                 delay: _,
-            } => {
-                // Sanity check, does the config file exists, if it doesn't then bail.
-                if !path.exists() {
-                    tracing::error!(
-                        "configuration file at path '{}' does not exist.",
-                        path.to_string_lossy()
-                    );
-                    stream::empty().boxed()
-                } else if watch {
-                    crate::files::watch(&path)
-                        .map(move |_| match ConfigurationSource::read_config(&path) {
-                            Ok(config) => UpdateConfiguration(config),
-                            Err(err) => {
-                                tracing::error!("{}", err);
-                                NoMoreConfiguration
-                            }
-                        })
-                        .boxed()
-                } else {
-                    match ConfigurationSource::read_config(&path) {
-                        Ok(configuration) => {
-                            #[cfg(any(test, not(unix)))]
-                            {
-                                stream::once(future::ready(UpdateConfiguration(configuration)))
-                                    .boxed()
-                            }
+            } => ConfigurationSource::files_stream(vec![path], watch),
+            ConfigurationSource::Files { paths, watch } => {
+                ConfigurationSource::files_stream(paths, watch)
+            }
+            ConfigurationSource::Url {
+                url,
+                headers,
+                poll_interval,
+                timeout,
+            } => crate::urls::poll(url, headers, poll_interval, timeout)
+                .map(|config| match config.parse() {
+                    Ok(configuration) => UpdateConfiguration(configuration),
+                    Err(err) => {
+                        tracing::error!("{}", ReadConfigError::Validation(err));
+                        NoMoreConfiguration
+                    }
+                })
+                .boxed(),
+        }
+        .chain(stream::iter(vec![NoMoreConfiguration]))
+        .boxed()
+    }
 
-                            #[cfg(all(not(test), unix))]
-                            {
-                                let mut sighup_stream = tokio::signal::unix::signal(
-                                    tokio::signal::unix::SignalKind::hangup(),
-                                )
+    /// Read the configuration file again each time a reload is triggered. Other sources ignore
+    /// reloads.
+    fn reload_stream(&self, reload_trigger: &ReloadTrigger) -> impl Stream<Item = Event> {
+        match self {
+            #[allow(deprecated)]
+            ConfigurationSource::File { path, .. } => {
+                ConfigurationSource::reload_files(vec![path.clone()], reload_trigger)
+            }
+            ConfigurationSource::Files { paths, .. } => {
+                ConfigurationSource::reload_files(paths.clone(), reload_trigger)
+            }
+            _ => stream::empty().boxed(),
+        }
+    }
+
+    fn reload_files(
+        paths: Vec<PathBuf>,
+        reload_trigger: &ReloadTrigger,
+    ) -> BoxStream<'static, Event> {
+        reload_trigger
+            .subscribe("configuration")
+            .filter_map(move |()| {
+                future::ready(match ConfigurationSource::read_config(&paths) {
+                    Ok(configuration) => Some(UpdateConfiguration(configuration)),
+                    Err(err) => {
+                        tracing::error!("{}", err);
+                        None
+                    }
+                })
+            })
+            .boxed()
+    }
+
+    fn files_stream(paths: Vec<PathBuf>, watch: bool) -> BoxStream<'static, Event> {
+        // Sanity check, do the config files exist, if they don't then bail.
+        if let Some(path) = paths.iter().find(|path| !path.exists()) {
+            tracing::error!(
+                "configuration file at path '{}' does not exist.",
+                path.to_string_lossy()
+            );
+            return stream::empty().boxed();
+        }
+
+        if watch {
+            // directories are watched too, the files they contain are listed again on each change
+            stream::select_all(paths.iter().map(|path| crate::files::watch(path).boxed()))
+                .map(move |_| match ConfigurationSource::read_config(&paths) {
+                    Ok(config) => UpdateConfiguration(config),
+                    Err(err) => {
+                        tracing::error!("{}", err);
+                        NoMoreConfiguration
+                    }
+                })
+                .boxed()
+        } else {
+            match ConfigurationSource::read_config(&paths) {
+                Ok(configuration) => {
+                    #[cfg(any(test, not(unix)))]
+                    {
+                        stream::once(future::ready(UpdateConfiguration(configuration))).boxed()
+                    }
+
+                    #[cfg(all(not(test), unix))]
+                    {
+                        let mut sighup_stream =
+                            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
                                 .expect("Failed to install SIGHUP signal handler");
 
-                                let (mut tx, rx) = futures::channel::mpsc::channel(1);
-                                tokio::task::spawn(async move {
-                                    while let Some(()) = sighup_stream.recv().await {
-                                        tx.send(()).await.unwrap();
-                                    }
-                                });
-                                futures::stream::select(
-                                    stream::once(future::ready(UpdateConfiguration(configuration)))
-                                        .boxed(),
-                                    rx.filter_map(
-                                        move |()| match ConfigurationSource::read_config(&path) {
-                                            Ok(configuration) => future::ready(Some(
-                                                UpdateConfiguration(configuration),
-                                            )),
-                                            Err(err) => {
-                                                tracing::error!("{}", err);
-                                                future::ready(None)
-                                            }
-                                        },
-                                    )
-                                    .boxed(),
-                                )
-                                .boxed()
+                        let (mut tx, rx) = futures::channel::mpsc::channel(1);
+                        tokio::task::spawn(async move {
+                            while let Some(()) = sighup_stream.recv().await {
+                                tx.send(()).await.unwrap();
                             }
-                        }
-                        Err(err) => {
-                            tracing::error!("{}", err);
-                            stream::empty().boxed()
-                        }
+                        });
+                        futures::stream::select(
+                            stream::once(future::ready(UpdateConfiguration(configuration))).boxed(),
+                            rx.filter_map(move |()| {
+                                match ConfigurationSource::read_config(&paths) {
+                                    Ok(configuration) => {
+                                        future::ready(Some(UpdateConfiguration(configuration)))
+                                    }
+                                    Err(err) => {
+                                        tracing::error!("{}", err);
+                                        future::ready(None)
+                                    }
+                                }
+                            })
+                            .boxed(),
+                        )
+                        .boxed()
                     }
                 }
+                Err(err) => {
+                    tracing::error!("{}", err);
+                    stream::empty().boxed()
+                }
             }
         }
-        .chain(stream::iter(vec![NoMoreConfiguration]))
-        .boxed()
     }
 
-    fn read_config(path: &Path) -> Result<Configuration, ReadConfigError> {
-        let config = fs::read_to_string(path)?;
-        config.parse().map_err(ReadConfigError::Validation)
+    fn read_config(paths: &[PathBuf]) -> Result<Configuration, ReadConfigError> {
+        let files = configuration_files(paths)?;
+        let documents = files
+            .iter()
+            .map(fs::read_to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        let config = merge_yaml_configurations(&documents).map_err(ReadConfigError::Validation)?;
+        config.parse().map_err(|err| {
+            if files.len() > 1 {
+                ReadConfigError::MergedValidation(
+                    files
+                        .iter()
+                        .map(|file| file.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    err,
+                )
+            } else {
+                ReadConfigError::Validation(err)
+            }
+        })
     }
 }
 type EntitlementStream = Pin<Box<dyn Stream<Item = Entitlement> + Send>>;
@@ -469,7 +635,9 @@ impl Default for EntitlementSource {
 
 impl EntitlementSource {
     /// Convert this entitlement into a stream regardless of if is static or not. Allows for unified handling later.
-    fn into_stream(self) -> impl Stream<Item = Event> {
+    ///
+    /// The registry falls back to the last known good entitlement, if any, when it does not answer in time.
+    fn into_stream(self, last_known_good: Option<&LastKnownGood>) -> impl Stream<Item = Event> {
         match self {
             EntitlementSource::Static { entitlement } => {
                 stream::once(future::ready(entitlement)).boxed()
@@ -534,23 +702,30 @@ impl EntitlementSource {
                 urls,
                 poll_interval,
                 timeout,
-            } => stream_from_uplink::<EntitlementQuery, Entitlement>(
-                apollo_key,
-                apollo_graph_ref,
-                urls.map(Endpoints::fallback),
-                poll_interval,
-                timeout,
-            )
-            .filter_map(|res| {
-                future::ready(match res {
-                    Ok(entitlement) => Some(entitlement),
-                    Err(e) => {
-                        tracing::error!("{}", e);
-                        None
+            } => {
+                let entitlements = stream_from_uplink::<EntitlementQuery, Entitlement>(
+                    apollo_key,
+                    apollo_graph_ref,
+                    urls.map(Endpoints::fallback),
+                    poll_interval,
+                    timeout,
+                )
+                .filter_map(|res| {
+                    future::ready(match res {
+                        Ok(entitlement) => Some(entitlement),
+                        Err(e) => {
+                            tracing::error!("{}", e);
+                            None
+                        }
+                    })
+                });
+                match last_known_good {
+                    Some(last_known_good) => {
+                        last_known_good.entitlement_fallback(entitlements).boxed()
                     }
-                })
-            })
-            .boxed(),
+                    None => entitlements.boxed(),
+                }
+            }
             EntitlementSource::Env => {
                 // EXPERIMENTAL and not subject to semver.
                 match std::env::var("APOLLO_ROUTER_ENTITLEMENT").map(|e| Entitlement::from_str(&e))
@@ -575,6 +750,8 @@ enum ReadConfigError {
     Io(std::io::Error),
     /// {0}
     Validation(crate::configuration::ConfigurationError),
+    /// in the configuration merged from {0}: {1}
+    MergedValidation(String, crate::configuration::ConfigurationError),
 }
 
 type ShutdownFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
@@ -703,6 +880,11 @@ impl RouterHttpServer {
     ///   Specifies when the server should gracefully shut down.
     ///   If not provided, the default is [`ShutdownSource::CtrlC`].
     ///
+    /// * `.last_known_good(`[`LastKnownGood`]`)`
+    ///   Optional.
+    ///   Specifies where to keep the last supergraph schema and entitlement the router ran with,
+    ///   used at startup when the registry or a schema URL does not answer in time.
+    ///
     /// * `.start()`
     ///   Finishes the builder,
     ///   starts an HTTP server in a separate Tokio task,
@@ -723,18 +905,23 @@ impl RouterHttpServer {
         configuration: Option<ConfigurationSource>,
         entitlement: Option<EntitlementSource>,
         shutdown: Option<ShutdownSource>,
+        last_known_good: Option<LastKnownGood>,
     ) -> RouterHttpServer {
         let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let reload_trigger = ReloadTrigger::new();
         let event_stream = generate_event_stream(
             shutdown.unwrap_or(ShutdownSource::CtrlC),
             configuration.unwrap_or_default(),
             schema,
             entitlement.unwrap_or_default(),
             shutdown_receiver,
+            &reload_trigger,
+            last_known_good.as_ref(),
         );
-        let server_factory = AxumHttpServerFactory::new();
+        let server_factory = AxumHttpServerFactory::new().with_reload_trigger(reload_trigger);
         let router_factory = OrbiterRouterSuperServiceFactory::new(YamlRouterFactory::default());
-        let state_machine = StateMachine::new(server_factory, router_factory);
+        let state_machine =
+            StateMachine::new(server_factory, router_factory).with_last_known_good(last_known_good);
         let listen_addresses = state_machine.listen_addresses.clone();
         let result = spawn(
             async move { state_machine.process_events(event_stream).await }
@@ -871,13 +1058,17 @@ fn generate_event_stream(
     schema: SchemaSource,
     entitlement: EntitlementSource,
     shutdown_receiver: oneshot::Receiver<()>,
+    reload_trigger: &ReloadTrigger,
+    last_known_good: Option<&LastKnownGood>,
 ) -> impl Stream<Item = Event> {
     // Chain is required so that the final shutdown message is sent.
     stream::select_all(vec![
         shutdown.into_stream().boxed(),
+        configuration.reload_stream(reload_trigger).boxed(),
         configuration.into_stream().boxed(),
-        schema.into_stream().boxed(),
-        entitlement.into_stream().boxed(),
+        schema.reload_stream(reload_trigger).boxed(),
+        schema.into_stream(last_known_good).boxed(),
+        entitlement.into_stream(last_known_good).boxed(),
         shutdown_receiver.into_stream().map(|_| Shutdown).boxed(),
     ])
     .take_while(|msg| future::ready(!matches!(msg, Shutdown)))
@@ -891,6 +1082,11 @@ mod tests {
 
     use serde_json::to_string_pretty;
     use test_log::test;
+    use wiremock::matchers::method;
+    use wiremock::matchers::path;
+    use wiremock::Mock;
+    use wiremock::MockServer;
+    use wiremock::ResponseTemplate;
 
     use super::*;
     use crate::files::tests::create_temp_file;
@@ -1025,6 +1221,86 @@ mod tests {
         assert!(matches!(stream.next().await.unwrap(), NoMoreConfiguration));
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn config_by_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/router.yaml"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("testdata/supergraph_config.router.yaml")),
+            )
+            .mount(&server)
+            .await;
+
+        let mut stream = ConfigurationSource::Url {
+            url: Url::parse(&format!("{}/router.yaml", server.uri())).unwrap(),
+            headers: Default::default(),
+            poll_interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+        }
+        .into_stream();
+        assert!(matches!(
+            stream.next().await.unwrap(),
+            UpdateConfiguration(_)
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn config_by_files_merged() {
+        let (base_path, mut base) = create_temp_file();
+        let contents = include_str!("testdata/supergraph_config.router.yaml");
+        write_and_flush(&mut base, contents).await;
+        let (overlay_path, mut overlay) = create_temp_file();
+        write_and_flush(&mut overlay, "supergraph:\n  path: /graphql\n").await;
+
+        let mut stream = ConfigurationSource::Files {
+            paths: vec![base_path, overlay_path],
+            watch: false,
+        }
+        .into_stream();
+        match stream.next().await.unwrap() {
+            UpdateConfiguration(configuration) => {
+                assert_eq!(configuration.supergraph.path, "/graphql");
+                assert_eq!(
+                    configuration.supergraph.listen.to_string(),
+                    "http://127.0.0.1:0"
+                );
+            }
+            _ => panic!("the merged configuration should be valid"),
+        }
+        assert!(matches!(stream.next().await.unwrap(), NoMoreConfiguration));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn config_by_directory_watching() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("a.yaml"),
+            include_str!("testdata/supergraph_config.router.yaml"),
+        )
+        .unwrap();
+        let mut stream = ConfigurationSource::Files {
+            paths: vec![dir.path().to_path_buf()],
+            watch: true,
+        }
+        .into_stream()
+        .boxed();
+        assert!(matches!(
+            stream.next().await.unwrap(),
+            UpdateConfiguration(_)
+        ));
+
+        // a file added to the directory is merged on the next update
+        std::fs::write(dir.path().join("b.yaml"), "supergraph:\n  path: /graphql\n").unwrap();
+        match stream.next().await.unwrap() {
+            UpdateConfiguration(configuration) => {
+                assert_eq!(configuration.supergraph.path, "/graphql")
+            }
+            _ => panic!("the merged configuration should be valid"),
+        }
+    }
+
     #[test(tokio::test)]
     async fn schema_by_file_watching() {
         let (path, mut file) = create_temp_file();
@@ -1035,7 +1311,7 @@ mod tests {
             watch: true,
             delay: None,
         }
-        .into_stream()
+        .into_stream(None)
         .boxed();
 
         // First update is guaranteed
@@ -1055,7 +1331,7 @@ mod tests {
             watch: true,
             delay: None,
         }
-        .into_stream();
+        .into_stream(None);
 
         // First update fails because the file is invalid.
         assert!(matches!(stream.next().await.unwrap(), NoMoreSchema));
@@ -1072,7 +1348,7 @@ mod tests {
             watch: false,
             delay: None,
         }
-        .into_stream();
+        .into_stream(None);
         assert!(matches!(stream.next().await.unwrap(), UpdateSchema(_)));
         assert!(matches!(stream.next().await.unwrap(), NoMoreSchema));
     }
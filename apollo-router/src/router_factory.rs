@@ -25,6 +25,7 @@ use crate::plugin::PluginFactory;
 use crate::plugins::traffic_shaping::TrafficShaping;
 use crate::plugins::traffic_shaping::APOLLO_TRAFFIC_SHAPING;
 use crate::query_planner::BridgeQueryPlanner;
+use crate::rollback::RequestOutcomes;
 use crate::services::new_service::ServiceFactory;
 use crate::services::router;
 use crate::services::router_service::RouterCreator;
@@ -32,7 +33,9 @@ use crate::services::transport;
 use crate::services::PluggableSupergraphServiceBuilder;
 use crate::services::SubgraphService;
 use crate::services::SupergraphCreator;
+use crate::spec::Query;
 use crate::spec::Schema;
+use crate::spec::SchemaDiff;
 use crate::ListenAddr;
 
 #[derive(Clone)]
@@ -106,6 +109,16 @@ pub(crate) trait RouterFactory:
     type Future: Send;
 
     fn web_endpoints(&self) -> MultiMap<ListenAddr, Endpoint>;
+
+    /// Names of the subgraphs that are currently considered unavailable
+    fn unhealthy_subgraphs(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Requests handled by the routers this factory created, and how many of them failed
+    fn request_outcomes(&self) -> RequestOutcomes {
+        RequestOutcomes::default()
+    }
 }
 
 /// Factory for creating a RouterFactory
@@ -116,12 +129,15 @@ pub(crate) trait RouterFactory:
 pub(crate) trait RouterSuperServiceFactory: Send + Sync + 'static {
     type RouterFactory: RouterFactory;
 
+    /// `rolling_back` is set when going back to a configuration and schema that a previous
+    /// router already ran with
     async fn create<'a>(
         &'a mut self,
         configuration: Arc<Configuration>,
         schema: String,
         previous_router: Option<&'a Self::RouterFactory>,
         extra_plugins: Option<Vec<(String, Box<dyn DynPlugin>)>>,
+        rolling_back: bool,
     ) -> Result<Self::RouterFactory, BoxError>;
 }
 
@@ -139,6 +155,7 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
         schema: String,
         previous_router: Option<&'a Self::RouterFactory>,
         extra_plugins: Option<Vec<(String, Box<dyn DynPlugin>)>>,
+        rolling_back: bool,
     ) -> Result<Self::RouterFactory, BoxError> {
         // QueryPlannerService takes an UnplannedRequest and outputs PlannedRequest
         let bridge_query_planner = match previous_router.as_ref().map(|router| router.planner()) {
@@ -151,6 +168,11 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
 
         let schema = bridge_query_planner.schema();
 
+        // the schema of a rollback was already served, its changes were checked back then
+        if let Some(router) = previous_router.filter(|_| !rolling_back) {
+            check_schema_changes(&configuration, &router.schema(), &schema, router).await?;
+        }
+
         // Process the plugins.
         let plugins = create_plugins(&configuration, &schema, extra_plugins).await?;
 
@@ -164,7 +186,7 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
         let mut builder = PluggableSupergraphServiceBuilder::new(bridge_query_planner);
         builder = builder.with_configuration(configuration.clone());
 
-        for (name, _) in schema.subgraphs() {
+        for (name, url) in schema.subgraphs() {
             let subgraph_root_store = configuration
                 .tls
                 .subgraph
@@ -180,23 +202,28 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
                 .find(|i| i.0.as_str() == APOLLO_TRAFFIC_SHAPING)
                 .and_then(|plugin| (*plugin.1).as_any().downcast_ref::<TrafficShaping>())
             {
-                Some(shaping) => Either::A(
-                    shaping.subgraph_service_internal(
-                        name,
-                        SubgraphService::new(
+                Some(shaping) => {
+                    shaping.start_health_probes(name, url, subgraph_root_store.as_ref())?;
+                    Either::A(
+                        shaping.subgraph_service_internal(
                             name,
-                            configuration
-                                .apq
-                                .subgraph
-                                .subgraphs
-                                .get(name)
-                                .map(|apq| apq.enabled)
-                                .unwrap_or(configuration.apq.subgraph.all.enabled),
-                            subgraph_root_store,
-                            shaping.enable_subgraph_http2(name),
+                            ServiceBuilder::new()
+                                .option_layer(shaping.subgraph_load_balancer(name))
+                                .service(SubgraphService::new(
+                                    name,
+                                    configuration
+                                        .apq
+                                        .subgraph
+                                        .subgraphs
+                                        .get(name)
+                                        .map(|apq| apq.enabled)
+                                        .unwrap_or(configuration.apq.subgraph.all.enabled),
+                                    subgraph_root_store,
+                                    shaping.enable_subgraph_http2(name),
+                                )),
                         ),
-                    ),
-                ),
+                    )
+                }
                 None => Either::B(SubgraphService::new(name, false, subgraph_root_store, true)),
             };
             builder = builder.with_subgraph_service(name, subgraph_service);
@@ -226,8 +253,64 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
             }
         }
 
-        Ok(Self::RouterFactory::new(Arc::new(supergraph_creator), &configuration).await)
+        Ok(
+            Self::RouterFactory::new(Arc::new(supergraph_creator), &configuration)
+                .await
+                .with_admin_endpoints(&configuration),
+        )
+    }
+}
+
+/// Report the changes between the current and the new API schema, and refuse the new schema if
+/// its breaking changes affect recently executed operations and the configuration asks for it
+async fn check_schema_changes(
+    configuration: &Configuration,
+    previous_schema: &Schema,
+    schema: &Schema,
+    previous_router: &RouterCreator<SupergraphCreator>,
+) -> Result<(), BoxError> {
+    if previous_schema.schema_id == schema.schema_id {
+        return Ok(());
+    }
+    let previous_api_schema = previous_schema.api_schema();
+    let diff = SchemaDiff::new(previous_api_schema, schema.api_schema());
+    diff.report();
+
+    let settings = &configuration.supergraph.experimental_breaking_changes;
+    if !settings.reject || diff.breaking_changes().next().is_none() {
+        return Ok(());
     }
+    let mut affected = Vec::new();
+    for (query, operation_name) in previous_router.cache_keys(settings.recent_operations).await {
+        // operations that did not parse with the current schema were already failing
+        let query = match Query::parse(query, previous_api_schema, configuration) {
+            Ok(query) => query,
+            Err(_) => continue,
+        };
+        let coordinates = query.schema_coordinates(operation_name.as_deref(), previous_api_schema);
+        let changes = diff
+            .affecting(&coordinates)
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        if !changes.is_empty() {
+            affected.push(format!(
+                "{} ({})",
+                operation_name.as_deref().unwrap_or("anonymous operation"),
+                changes.join(", ")
+            ));
+        }
+    }
+    if affected.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!(monotonic_counter.apollo_router_schema_rejected_count = 1u64);
+    Err(format!(
+        "the new schema has breaking changes affecting {} recently executed operations: {}",
+        affected.len(),
+        affected.join("; ")
+    )
+    .into())
 }
 
 impl YamlRouterFactory {
@@ -262,7 +345,7 @@ impl YamlRouterFactory {
         let mut builder = PluggableSupergraphServiceBuilder::new(bridge_query_planner);
         builder = builder.with_configuration(configuration.clone());
 
-        for (name, _) in schema.subgraphs() {
+        for (name, url) in schema.subgraphs() {
             let subgraph_root_store = configuration
                 .tls
                 .subgraph
@@ -278,23 +361,28 @@ impl YamlRouterFactory {
                 .find(|i| i.0.as_str() == APOLLO_TRAFFIC_SHAPING)
                 .and_then(|plugin| (*plugin.1).as_any().downcast_ref::<TrafficShaping>())
             {
-                Some(shaping) => Either::A(
-                    shaping.subgraph_service_internal(
-                        name,
-                        SubgraphService::new(
+                Some(shaping) => {
+                    shaping.start_health_probes(name, url, subgraph_root_store.as_ref())?;
+                    Either::A(
+                        shaping.subgraph_service_internal(
                             name,
-                            configuration
-                                .apq
-                                .subgraph
-                                .subgraphs
-                                .get(name)
-                                .map(|apq| apq.enabled)
-                                .unwrap_or(configuration.apq.subgraph.all.enabled),
-                            subgraph_root_store,
-                            shaping.enable_subgraph_http2(name),
+                            ServiceBuilder::new()
+                                .option_layer(shaping.subgraph_load_balancer(name))
+                                .service(SubgraphService::new(
+                                    name,
+                                    configuration
+                                        .apq
+                                        .subgraph
+                                        .subgraphs
+                                        .get(name)
+                                        .map(|apq| apq.enabled)
+                                        .unwrap_or(configuration.apq.subgraph.all.enabled),
+                                    subgraph_root_store,
+                                    shaping.enable_subgraph_http2(name),
+                                )),
                         ),
-                    ),
-                ),
+                    )
+                }
                 None => Either::B(SubgraphService::new(name, false, subgraph_root_store, true)),
             };
             builder = builder.with_subgraph_service(name, subgraph_service);
@@ -308,6 +396,43 @@ impl YamlRouterFactory {
     }
 }
 
+/// Schema used to create the plugins when validating a configuration without a supergraph
+const PLACEHOLDER_SCHEMA: &str = "type Query { _placeholder: String }";
+
+/// Check that a router could be created from this configuration, without serving it: the
+/// plugins are created, the certificates loaded and, if a supergraph schema is provided, the
+/// query planner is built. The plugins are created in validation mode, so they do not connect to
+/// external services or start background work.
+pub(crate) async fn validate_configuration(
+    configuration: Arc<Configuration>,
+    schema: Option<String>,
+) -> Result<(), BoxError> {
+    let schema = match schema {
+        Some(schema) => BridgeQueryPlanner::new(schema, configuration.clone())
+            .await?
+            .schema(),
+        None => Arc::new(Schema::parse(PLACEHOLDER_SCHEMA, &configuration, None)?),
+    };
+
+    // the plugins are dropped without being activated
+    crate::plugin::validating(create_plugins(&configuration, &schema, None)).await?;
+
+    configuration
+        .tls
+        .subgraph
+        .all
+        .create_certificate_store()
+        .transpose()?;
+    for subgraph in configuration.tls.subgraph.subgraphs.values() {
+        subgraph.create_certificate_store().transpose()?;
+    }
+    if let Some(tls) = &configuration.tls.supergraph {
+        tls.tls_config()?;
+    }
+
+    Ok(())
+}
+
 impl TlsSubgraph {
     fn create_certificate_store(&self) -> Option<Result<RootCertStore, ConfigurationError>> {
         self.certificate_authorities
@@ -361,7 +486,7 @@ pub async fn create_test_service_factory_from_yaml(schema: &str, configuration:
     let config: Configuration = serde_yaml::from_str(configuration).unwrap();
 
     let service = YamlRouterFactory::default()
-        .create(Arc::new(config), schema.to_string(), None, None)
+        .create(Arc::new(config), schema.to_string(), None, None, false)
         .await;
     assert_eq!(
         service.map(|_| ()).unwrap_err().to_string().as_str(),
@@ -536,6 +661,7 @@ mod test {
     use schemars::JsonSchema;
     use serde::Deserialize;
     use serde_json::json;
+    use tower::ServiceExt;
     use tower_http::BoxError;
 
     use crate::configuration::Configuration;
@@ -543,8 +669,11 @@ mod test {
     use crate::plugin::PluginInit;
     use crate::register_plugin;
     use crate::router_factory::inject_schema_id;
+    use crate::router_factory::validate_configuration;
     use crate::router_factory::RouterSuperServiceFactory;
     use crate::router_factory::YamlRouterFactory;
+    use crate::services::new_service::ServiceFactory;
+    use crate::services::supergraph;
     use crate::spec::Schema;
 
     #[derive(Debug)]
@@ -658,11 +787,141 @@ mod test {
         assert!(service.is_err())
     }
 
+    #[tokio::test]
+    async fn test_validate_configuration() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            plugins:
+                apollo.test.always_starts_and_stops:
+                    name: albert
+        "#,
+        )
+        .unwrap();
+        let config = Arc::new(config);
+        assert!(validate_configuration(config.clone(), None).await.is_ok());
+        let schema = include_str!("testdata/supergraph.graphql");
+        assert!(validate_configuration(config, Some(schema.to_string()))
+            .await
+            .is_ok());
+
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            plugins:
+                apollo.test.always_fails_to_start:
+                    name: albert
+        "#,
+        )
+        .unwrap();
+        assert!(validate_configuration(Arc::new(config), None)
+            .await
+            .is_err());
+
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            plugins:
+                apollo.test.always_starts_and_stops:
+                    name: 12
+        "#,
+        )
+        .unwrap();
+        assert!(validate_configuration(Arc::new(config), None)
+            .await
+            .is_err());
+
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            plugins:
+                apollo.test.unknown:
+                    name: albert
+        "#,
+        )
+        .unwrap();
+        assert!(validate_configuration(Arc::new(config), None)
+            .await
+            .is_err());
+
+        // checks made when creating a plugin are applied
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            traffic_shaping:
+                all:
+                    experimental_load_balancing:
+                        endpoints:
+                            - http://localhost:4001
+        "#,
+        )
+        .unwrap();
+        assert!(validate_configuration(Arc::new(config), None)
+            .await
+            .is_err());
+
+        // without connecting to external services
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            traffic_shaping:
+                experimental_cache:
+                    urls: ["redis://127.0.0.1:1"]
+        "#,
+        )
+        .unwrap();
+        assert!(validate_configuration(Arc::new(config), None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_breaking_changes_affecting_recent_operations_are_rejected() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            supergraph:
+                experimental_breaking_changes:
+                    reject: true
+        "#,
+        )
+        .unwrap();
+        let config = Arc::new(config);
+        let schema = include_str!("testdata/supergraph.graphql");
+        let mut factory = YamlRouterFactory::default();
+        let router = factory
+            .create(config.clone(), schema.to_string(), None, None, false)
+            .await
+            .unwrap();
+
+        // the subgraphs are not running, but the operation is planned and cached
+        let request = supergraph::Request::fake_builder()
+            .query("{ me { name } }")
+            .build()
+            .unwrap();
+        let _ = router.create().oneshot(request.try_into().unwrap()).await;
+
+        let next_schema = schema.replace("  name: String @join__field(graph: ACCOUNTS)\n", "");
+        let error = factory
+            .create(
+                config.clone(),
+                next_schema.clone(),
+                Some(&router),
+                None,
+                false,
+            )
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the new schema has breaking changes affecting 1 recently executed operations: \
+            anonymous operation (field User.name was removed)"
+        );
+
+        // a rollback goes back to a schema that was already served
+        assert!(factory
+            .create(config, next_schema, Some(&router), None, true)
+            .await
+            .is_ok());
+    }
+
     async fn create_service(config: Configuration) -> Result<(), BoxError> {
         let schema = include_str!("testdata/supergraph.graphql");
 
         let service = YamlRouterFactory::default()
-            .create(Arc::new(config), schema.to_string(), None, None)
+            .create(Arc::new(config), schema.to_string(), None, None, false)
             .await;
         service.map(|_| ())
     }
@@ -94,6 +94,8 @@ pub(crate) struct Externalizable<T> {
     pub(crate) service_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) status_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) query_plan: Option<serde_json::Value>,
 }
 
 impl<T> Externalizable<T>
@@ -44,6 +44,14 @@ impl APQLayer {
         Self { cache: None }
     }
 
+    /// Number of persisted queries in the in memory cache, if APQ is enabled
+    pub(crate) async fn cache_size(&self) -> Option<usize> {
+        match &self.cache {
+            Some(cache) => Some(cache.len().await),
+            None => None,
+        }
+    }
+
     pub(crate) async fn supergraph_request(
         &self,
         request: SupergraphRequest,
@@ -14,6 +14,8 @@ use futures::future::BoxFuture;
 use futures::stream;
 use futures::stream::once;
 use futures::stream::StreamExt;
+use futures::FutureExt;
+use http::header::CONTENT_LENGTH;
 use http::header::CONTENT_TYPE;
 use http::header::VARY;
 use http::HeaderMap;
@@ -45,17 +47,25 @@ use super::HasPlugins;
 #[cfg(test)]
 use super::SupergraphCreator;
 use super::MULTIPART_DEFER_CONTENT_TYPE;
+use crate::admin;
+use crate::admin::InFlightRequests;
 use crate::cache::DeduplicatingCache;
+use crate::configuration::Server;
 use crate::graphql;
 #[cfg(test)]
 use crate::plugin::test::MockSupergraphService;
+use crate::plugins::traffic_shaping::TrafficShaping;
+use crate::plugins::traffic_shaping::APOLLO_TRAFFIC_SHAPING;
 use crate::query_planner::QueryPlanResult;
+use crate::rollback;
+use crate::rollback::RequestOutcomes;
 use crate::router_factory::RouterFactory;
 use crate::services::layers::content_negociation::GRAPHQL_JSON_RESPONSE_HEADER_VALUE;
 use crate::services::RouterRequest;
 use crate::services::RouterResponse;
 use crate::services::SupergraphRequest;
 use crate::services::SupergraphResponse;
+use crate::spec::Schema;
 use crate::Configuration;
 use crate::Endpoint;
 use crate::ListenAddr;
@@ -68,20 +78,61 @@ where
 {
     supergraph_creator: Arc<SF>,
     apq_layer: APQLayer,
+    server: Server,
 }
 
 impl<SF> RouterService<SF>
 where
     SF: ServiceFactory<supergraph::Request> + Clone + Send + Sync + 'static,
 {
-    pub(crate) fn new(supergraph_creator: Arc<SF>, apq_layer: APQLayer) -> Self {
+    pub(crate) fn new(supergraph_creator: Arc<SF>, apq_layer: APQLayer, server: Server) -> Self {
         RouterService {
             supergraph_creator,
             apq_layer,
+            server,
         }
     }
 }
 
+/// A request that cannot be processed
+struct InvalidRequest {
+    status: StatusCode,
+    error: &'static str,
+    message: &'static str,
+    extension_code: &'static str,
+    extension_details: String,
+}
+
+impl InvalidRequest {
+    fn bad_request(error: &'static str, extension_details: String) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            error,
+            message: "Invalid GraphQL request",
+            extension_code: "INVALID_GRAPHQL_REQUEST",
+            extension_details,
+        }
+    }
+
+    fn too_large(error: &'static str, extension_code: &'static str, limit: usize) -> Self {
+        Self {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            error,
+            message: "Request too large",
+            extension_code,
+            extension_details: format!("{error}, the limit is {limit} bytes"),
+        }
+    }
+}
+
+/// Total size of the header names and values
+fn header_bytes(headers: &HeaderMap<HeaderValue>) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum()
+}
+
 #[cfg(test)]
 pub(crate) async fn from_supergraph_mock_callback_and_configuration(
     supergraph_callback: impl FnMut(supergraph::Request) -> supergraph::ServiceResult
@@ -179,44 +230,101 @@ where
 
         let supergraph_creator = self.supergraph_creator.clone();
         let apq = self.apq_layer.clone();
+        let server = self.server.clone();
 
         let fut = async move {
-            let graphql_request: Result<graphql::Request, (&str, String)> = if parts.method
-                == Method::GET
+            let max_request_bytes = server
+                .experimental_http_max_request_bytes
+                .unwrap_or(usize::MAX);
+            let content_length = parts
+                .headers
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<usize>().ok());
+
+            let graphql_request: Result<graphql::Request, InvalidRequest> = if let Some(limit) =
+                server
+                    .experimental_http_max_header_bytes
+                    .filter(|limit| header_bytes(&parts.headers) > *limit)
             {
+                Err(InvalidRequest {
+                    status: StatusCode::BAD_REQUEST,
+                    error: "request headers are too large",
+                    message: "Request headers too large",
+                    extension_code: "REQUEST_HEADERS_TOO_LARGE",
+                    extension_details: format!(
+                        "request headers are too large, the limit is {limit} bytes"
+                    ),
+                })
+            } else if parts.method == Method::GET {
                 parts
                     .uri
                     .query()
                     .map(|q| {
                         graphql::Request::from_urlencoded_query(q.to_string()).map_err(|e| {
-                            (
+                            InvalidRequest::bad_request(
                                 "failed to decode a valid GraphQL request from path",
                                 format!("failed to decode a valid GraphQL request from path {e}"),
                             )
                         })
                     })
                     .unwrap_or_else(|| {
-                        Err(("missing query string", "missing query string".to_string()))
+                        Err(InvalidRequest::bad_request(
+                            "missing query string",
+                            "missing query string".to_string(),
+                        ))
                     })
+            } else if content_length.unwrap_or_default() > max_request_bytes {
+                Err(InvalidRequest::too_large(
+                    "request body is too large",
+                    "REQUEST_BODY_TOO_LARGE",
+                    max_request_bytes,
+                ))
             } else {
-                hyper::body::to_bytes(body)
+                // the limit is checked while receiving the body, before it is fully buffered
+                hyper::body::to_bytes(http_body::Limited::new(body, max_request_bytes))
                     .instrument(tracing::debug_span!("receive_body"))
                     .await
                     .map_err(|e| {
-                        (
-                            "failed to get the request body",
-                            format!("failed to get the request body: {e}"),
-                        )
+                        if e.is::<http_body::LengthLimitError>() {
+                            InvalidRequest::too_large(
+                                "request body is too large",
+                                "REQUEST_BODY_TOO_LARGE",
+                                max_request_bytes,
+                            )
+                        } else {
+                            InvalidRequest::bad_request(
+                                "failed to get the request body",
+                                format!("failed to get the request body: {e}"),
+                            )
+                        }
                     })
                     .and_then(|bytes| {
                         serde_json::from_reader(bytes.reader()).map_err(|err| {
-                            (
+                            InvalidRequest::bad_request(
                                 "failed to deserialize the request body into JSON",
                                 format!("failed to deserialize the request body into JSON: {err}"),
                             )
                         })
                     })
             };
+            let graphql_request = graphql_request.and_then(|graphql_request| {
+                match server.experimental_max_variables_bytes {
+                    Some(limit)
+                        if serde_json::to_vec(&graphql_request.variables)
+                            .map(|variables| variables.len())
+                            .unwrap_or_default()
+                            > limit =>
+                    {
+                        Err(InvalidRequest::too_large(
+                            "request variables are too large",
+                            "VARIABLES_TOO_LARGE",
+                            limit,
+                        ))
+                    }
+                    _ => Ok(graphql_request),
+                }
+            });
 
             match graphql_request {
                 Ok(graphql_request) => {
@@ -388,27 +496,33 @@ where
                         }
                     }
                 }
-                Err((error, extension_details)) => {
-                    // BAD REQUEST
+                Err(InvalidRequest {
+                    status,
+                    error,
+                    message,
+                    extension_code,
+                    extension_details,
+                }) => {
+                    // BAD REQUEST or PAYLOAD TOO LARGE
                     ::tracing::error!(
                         monotonic_counter.apollo_router_http_requests_total = 1u64,
-                        status = %400,
+                        status = %status.as_u16(),
                         error = %error,
                         %error
                     );
                     Ok(router::Response {
                         response: http::Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
+                            .status(status)
                             .header(CONTENT_TYPE, APPLICATION_JSON.to_string())
                             .body(Body::from(
                                 serde_json::to_string(
                                     &graphql::Error::builder()
-                                        .message(String::from("Invalid GraphQL request"))
-                                        .extension_code("INVALID_GRAPHQL_REQUEST")
+                                        .message(String::from(message))
+                                        .extension_code(extension_code)
                                         .extension("details", extension_details)
                                         .build(),
                                 )
-                                .unwrap_or_else(|_| String::from("Invalid GraphQL request")),
+                                .unwrap_or_else(|_| String::from(message)),
                             ))
                             .expect("cannot fail"),
                         context,
@@ -437,6 +551,10 @@ where
     supergraph_creator: Arc<SF>,
     static_page: StaticPageLayer,
     apq_layer: APQLayer,
+    server: Server,
+    in_flight: InFlightRequests,
+    outcomes: RequestOutcomes,
+    admin_endpoints: MultiMap<ListenAddr, Endpoint>,
 }
 
 impl<SF> ServiceFactory<router::Request> for RouterCreator<SF>
@@ -473,8 +591,22 @@ where
             .plugins()
             .values()
             .for_each(|p| mm.extend(p.web_endpoints()));
+        mm.extend(self.admin_endpoints.clone());
         mm
     }
+
+    fn unhealthy_subgraphs(&self) -> Vec<String> {
+        self.supergraph_creator
+            .plugins()
+            .get(APOLLO_TRAFFIC_SHAPING)
+            .and_then(|plugin| plugin.as_any().downcast_ref::<TrafficShaping>())
+            .map(TrafficShaping::unhealthy_subgraphs)
+            .unwrap_or_default()
+    }
+
+    fn request_outcomes(&self) -> RequestOutcomes {
+        self.outcomes.clone()
+    }
 }
 
 impl<SF> RouterCreator<SF>
@@ -500,6 +632,10 @@ where
             supergraph_creator,
             static_page,
             apq_layer,
+            server: configuration.server.clone(),
+            in_flight: InFlightRequests::default(),
+            outcomes: RequestOutcomes::default(),
+            admin_endpoints: MultiMap::new(),
         }
     }
 
@@ -514,9 +650,29 @@ where
         let router_service = content_negociation::RouterLayer::default().layer(RouterService::new(
             self.supergraph_creator.clone(),
             self.apq_layer.clone(),
+            self.server.clone(),
         ));
 
+        let in_flight = self.in_flight.clone();
+        let outcomes = self.outcomes.clone();
         ServiceBuilder::new()
+            .map_future(move |future: BoxFuture<'static, router::ServiceResult>| {
+                let guard = in_flight.start();
+                let outcomes = outcomes.clone();
+                async move {
+                    let result = future.await;
+                    drop(guard);
+                    outcomes.record(match &result {
+                        Ok(response) => {
+                            response.response.status().is_server_error()
+                                || rollback::subgraph_failed(&response.context)
+                        }
+                        Err(_) => true,
+                    });
+                    result
+                }
+                .boxed()
+            })
             .layer(self.static_page.clone())
             .service(
                 self.supergraph_creator
@@ -529,6 +685,17 @@ where
 }
 
 impl RouterCreator<crate::services::supergraph_service::SupergraphCreator> {
+    /// Serve the admin API, if it is enabled in the configuration
+    pub(crate) fn with_admin_endpoints(mut self, configuration: &Configuration) -> Self {
+        self.admin_endpoints = admin::endpoints(
+            configuration,
+            self.supergraph_creator.clone(),
+            self.apq_layer.clone(),
+            self.in_flight.clone(),
+        );
+        self
+    }
+
     pub(crate) async fn cache_keys(&self, count: usize) -> Vec<(String, Option<String>)> {
         self.supergraph_creator.cache_keys(count).await
     }
@@ -536,6 +703,10 @@ impl RouterCreator<crate::services::supergraph_service::SupergraphCreator> {
     pub(crate) fn planner(&self) -> Arc<Planner<QueryPlanResult>> {
         self.supergraph_creator.planner()
     }
+
+    pub(crate) fn schema(&self) -> Arc<Schema> {
+        self.supergraph_creator.schema()
+    }
 }
 
 #[cfg(test)]
@@ -705,4 +876,52 @@ mod tests {
         assert_eq!(expected_error, actual_error);
         assert!(response.errors[0].extensions.contains_key("code"));
     }
+
+    #[tokio::test]
+    async fn it_rejects_large_requests() {
+        let configuration = Arc::new(
+            Configuration::fake_builder()
+                .server(
+                    Server::builder()
+                        .http_max_request_bytes(100)
+                        .max_variables_bytes(10)
+                        .build(),
+                )
+                .build()
+                .unwrap(),
+        );
+
+        let router_service = from_supergraph_mock_callback_and_configuration(
+            move |_req| unreachable!(),
+            configuration.clone(),
+        )
+        .await;
+        let request = SupergraphRequest::fake_builder()
+            .query("query { ".to_string() + &"a ".repeat(100) + "}")
+            .build()
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let response = router_service.oneshot(request).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("REQUEST_BODY_TOO_LARGE"));
+
+        let router_service = from_supergraph_mock_callback_and_configuration(
+            move |_req| unreachable!(),
+            configuration,
+        )
+        .await;
+        let request = SupergraphRequest::fake_builder()
+            .query("query { a }".to_string())
+            .variable("input", "a long value")
+            .build()
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let response = router_service.oneshot(request).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("VARIABLES_TOO_LARGE"));
+    }
 }
@@ -45,8 +45,10 @@ use super::layers::content_negociation::GRAPHQL_JSON_RESPONSE_HEADER_VALUE;
 use super::Plugins;
 use crate::error::FetchError;
 use crate::graphql;
+use crate::layers::ServiceBuilderExt;
 use crate::plugins::telemetry::LOGGING_DISPLAY_BODY;
 use crate::plugins::telemetry::LOGGING_DISPLAY_HEADERS;
+use crate::rollback;
 use crate::services::layers::apq;
 use crate::services::SubgraphRequest;
 use crate::services::SubgraphResponse;
@@ -510,10 +512,29 @@ impl SubgraphServiceFactory {
     ) -> Option<BoxService<SubgraphRequest, SubgraphResponse, BoxError>> {
         self.services.get(name).map(|service| {
             let service = service.make();
-            self.plugins
+            let service = self
+                .plugins
                 .iter()
                 .rev()
-                .fold(service, |acc, (_, e)| e.subgraph_service(name, acc))
+                .fold(service, |acc, (_, e)| e.subgraph_service(name, acc));
+            ServiceBuilder::new()
+                .map_future_with_request_data(
+                    |request: &SubgraphRequest| request.context.clone(),
+                    |context: Context, future| async move {
+                        let result: Result<SubgraphResponse, BoxError> = future.await;
+                        // failed subgraph requests count toward the error rate watched after reloads
+                        let failed = match &result {
+                            Ok(response) => response.response.status().is_server_error(),
+                            Err(_) => true,
+                        };
+                        if failed {
+                            rollback::record_subgraph_failure(&context);
+                        }
+                        result
+                    },
+                )
+                .service(service)
+                .boxed()
         })
     }
 }
@@ -445,6 +445,14 @@ impl SupergraphCreator {
         self.query_planner_service.planner()
     }
 
+    pub(crate) fn schema(&self) -> Arc<Schema> {
+        self.schema.clone()
+    }
+
+    pub(crate) async fn query_plan_cache_size(&self) -> usize {
+        self.query_planner_service.cache_size().await
+    }
+
     pub(crate) async fn warm_up_query_planner(
         &mut self,
         cache_keys: Vec<(String, Option<String>)>,
@@ -6,6 +6,7 @@ mod field_type;
 mod fragments;
 pub(crate) mod query;
 mod schema;
+mod schema_diff;
 mod selection;
 
 use displaydoc::Display;
@@ -14,6 +15,7 @@ pub(crate) use fragments::*;
 pub(crate) use query::Query;
 pub(crate) use query::TYPENAME;
 pub(crate) use schema::Schema;
+pub(crate) use schema_diff::SchemaDiff;
 pub(crate) use selection::*;
 use serde::Deserialize;
 use serde::Serialize;
@@ -1006,6 +1006,329 @@ impl Query {
             .iter()
             .any(|selection| selection.contains_error_path(&path.0, &self.fragments))
     }
+
+    /// The types and fields, as `Type.field`, an operation of this query uses. Input types used
+    /// by its variables are included with all their fields, the input fields and enum values of
+    /// its literal arguments as `Type.field` and `Type.VALUE`.
+    pub(crate) fn schema_coordinates(
+        &self,
+        operation_name: Option<&str>,
+        schema: &Schema,
+    ) -> HashSet<String> {
+        let mut coordinates = HashSet::new();
+        if let Some(operation) = self.operation(operation_name) {
+            let root = schema.root_operation_name(operation.kind);
+            coordinates.insert(root.to_string());
+            let mut visited_fragments = HashSet::new();
+            selection_coordinates(
+                &operation.selection_set,
+                root,
+                &self.fragments,
+                &mut visited_fragments,
+                &mut coordinates,
+            );
+            for variable in operation.variables.values() {
+                if let Some(name) = variable.field_type.inner_type_name() {
+                    input_coordinates(name, schema, &mut coordinates);
+                }
+            }
+            self.literal_coordinates(operation.name.as_deref(), root, schema, &mut coordinates);
+        }
+        coordinates
+    }
+
+    /// Argument values are not kept once parsed, so the literal ones are read from the document
+    fn literal_coordinates(
+        &self,
+        operation_name: Option<&str>,
+        root: &str,
+        schema: &Schema,
+        coordinates: &mut HashSet<String>,
+    ) {
+        let mut compiler = ApolloCompiler::new();
+        let _id = compiler.add_executable(&self.string, "query");
+        let operations = compiler.db.all_operations();
+        let operation = match operation_name {
+            Some(name) => operations
+                .iter()
+                .find(|operation| operation.name() == Some(name)),
+            None => operations.first(),
+        };
+        if let Some(operation) = operation {
+            let all_fragments = compiler.db.all_fragments();
+            let fragments = all_fragments
+                .iter()
+                .map(|(name, fragment)| (name.as_str(), fragment.as_ref()))
+                .collect();
+            hir_literal_coordinates(
+                operation.selection_set(),
+                root,
+                schema,
+                &fragments,
+                &mut HashSet::new(),
+                coordinates,
+            );
+        }
+    }
+
+    pub(crate) fn operation_kind(&self, operation_name: Option<&str>) -> Option<OperationKind> {
+        self.operation(operation_name)
+            .map(|operation| *operation.kind())
+    }
+
+    /// The depth of an operation and an estimate of its cost, where every field costs 1 and the
+    /// selections of list fields are counted `ESTIMATED_LIST_SIZE` times
+    pub(crate) fn complexity(&self, operation_name: Option<&str>) -> Option<(usize, u64)> {
+        let operation = self.operation(operation_name)?;
+        Some(selection_complexity(
+            &operation.selection_set,
+            &self.fragments,
+            &mut Vec::new(),
+        ))
+    }
+}
+
+/// How many items list fields are assumed to return when estimating the cost of an operation
+pub(crate) const ESTIMATED_LIST_SIZE: u64 = 10;
+
+fn selection_complexity<'a>(
+    selection_set: &'a [Selection],
+    fragments: &'a Fragments,
+    fragment_path: &mut Vec<&'a str>,
+) -> (usize, u64) {
+    let mut depth = 0;
+    let mut cost = 0;
+    for selection in selection_set {
+        let (selection_depth, selection_cost) = match selection {
+            Selection::Field {
+                field_type,
+                selection_set,
+                ..
+            } => {
+                let (child_depth, child_cost) = selection_set
+                    .as_ref()
+                    .map(|selection_set| {
+                        selection_complexity(selection_set, fragments, fragment_path)
+                    })
+                    .unwrap_or_default();
+                let multiplier = if is_list(field_type) {
+                    ESTIMATED_LIST_SIZE
+                } else {
+                    1
+                };
+                (child_depth + 1, 1 + child_cost * multiplier)
+            }
+            Selection::InlineFragment { selection_set, .. } => {
+                selection_complexity(selection_set, fragments, fragment_path)
+            }
+            Selection::FragmentSpread { name, .. } => match fragments.get(name) {
+                // fragment cycles are rejected by validation, this only avoids looping forever
+                Some(fragment) if !fragment_path.contains(&name.as_str()) => {
+                    fragment_path.push(name);
+                    let complexity =
+                        selection_complexity(&fragment.selection_set, fragments, fragment_path);
+                    fragment_path.pop();
+                    complexity
+                }
+                _ => (0, 0),
+            },
+        };
+        depth = depth.max(selection_depth);
+        cost += selection_cost;
+    }
+    (depth, cost)
+}
+
+fn is_list(field_type: &FieldType) -> bool {
+    match field_type {
+        FieldType::List(_) => true,
+        FieldType::NonNull(inner) => is_list(inner),
+        _ => false,
+    }
+}
+
+fn selection_coordinates(
+    selection_set: &[Selection],
+    parent: &str,
+    fragments: &Fragments,
+    visited_fragments: &mut HashSet<String>,
+    coordinates: &mut HashSet<String>,
+) {
+    for selection in selection_set {
+        match selection {
+            Selection::Field {
+                name,
+                field_type,
+                selection_set,
+                ..
+            } => {
+                coordinates.insert(format!("{parent}.{}", name.as_str()));
+                if let Some(type_name) = field_type.inner_type_name() {
+                    coordinates.insert(type_name.to_string());
+                    if let Some(selection_set) = selection_set {
+                        selection_coordinates(
+                            selection_set,
+                            type_name,
+                            fragments,
+                            visited_fragments,
+                            coordinates,
+                        );
+                    }
+                }
+            }
+            Selection::InlineFragment {
+                type_condition,
+                selection_set,
+                ..
+            } => {
+                coordinates.insert(type_condition.clone());
+                selection_coordinates(
+                    selection_set,
+                    type_condition,
+                    fragments,
+                    visited_fragments,
+                    coordinates,
+                );
+            }
+            Selection::FragmentSpread { name, .. } => {
+                if let Some(fragment) = fragments.get(name) {
+                    if visited_fragments.insert(name.clone()) {
+                        coordinates.insert(fragment.type_condition.clone());
+                        selection_coordinates(
+                            &fragment.selection_set,
+                            &fragment.type_condition,
+                            fragments,
+                            visited_fragments,
+                            coordinates,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn input_coordinates(type_name: &str, schema: &Schema, coordinates: &mut HashSet<String>) {
+    if !coordinates.insert(type_name.to_string()) {
+        return;
+    }
+    if let Some(input_type) = schema.input_types.get(type_name) {
+        for (field, (field_type, _)) in &input_type.fields {
+            coordinates.insert(format!("{type_name}.{field}"));
+            if let Some(field_type_name) = field_type.inner_type_name() {
+                input_coordinates(field_type_name, schema, coordinates);
+            }
+        }
+    }
+}
+
+fn hir_literal_coordinates(
+    selection_set: &hir::SelectionSet,
+    parent: &str,
+    schema: &Schema,
+    fragments: &HashMap<&str, &hir::FragmentDefinition>,
+    visited_fragments: &mut HashSet<String>,
+    coordinates: &mut HashSet<String>,
+) {
+    for selection in selection_set.selection() {
+        match selection {
+            hir::Selection::Field(field) => {
+                let (field_type, arguments) = match schema
+                    .object_types
+                    .get(parent)
+                    .map(|ty| (&ty.fields, &ty.arguments))
+                    .or_else(|| {
+                        schema
+                            .interfaces
+                            .get(parent)
+                            .map(|ty| (&ty.fields, &ty.arguments))
+                    }) {
+                    Some((fields, arguments)) => {
+                        (fields.get(field.name()), arguments.get(field.name()))
+                    }
+                    None => continue,
+                };
+                for argument in field.arguments() {
+                    if let Some((argument_type, _)) =
+                        arguments.and_then(|arguments| arguments.get(argument.name()))
+                    {
+                        value_coordinates(argument.value(), argument_type, schema, coordinates);
+                    }
+                }
+                if let Some(type_name) = field_type.and_then(|ty| ty.inner_type_name()) {
+                    hir_literal_coordinates(
+                        field.selection_set(),
+                        type_name,
+                        schema,
+                        fragments,
+                        visited_fragments,
+                        coordinates,
+                    );
+                }
+            }
+            hir::Selection::InlineFragment(inline_fragment) => {
+                hir_literal_coordinates(
+                    inline_fragment.selection_set(),
+                    inline_fragment.type_condition().unwrap_or(parent),
+                    schema,
+                    fragments,
+                    visited_fragments,
+                    coordinates,
+                );
+            }
+            hir::Selection::FragmentSpread(fragment_spread) => {
+                if let Some(definition) = fragments.get(fragment_spread.name()) {
+                    if visited_fragments.insert(fragment_spread.name().to_owned()) {
+                        hir_literal_coordinates(
+                            definition.selection_set(),
+                            definition.type_condition(),
+                            schema,
+                            fragments,
+                            visited_fragments,
+                            coordinates,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn value_coordinates(
+    value: &hir::Value,
+    value_type: &FieldType,
+    schema: &Schema,
+    coordinates: &mut HashSet<String>,
+) {
+    let type_name = match value_type.inner_type_name() {
+        Some(type_name) => type_name,
+        None => return,
+    };
+    match value {
+        hir::Value::Enum(name) => {
+            coordinates.insert(type_name.to_string());
+            coordinates.insert(format!("{type_name}.{}", name.src()));
+        }
+        // the type of the items of a list is also its inner type
+        hir::Value::List(values) => {
+            for value in values {
+                value_coordinates(value, value_type, schema, coordinates);
+            }
+        }
+        hir::Value::Object(fields) => {
+            coordinates.insert(type_name.to_string());
+            let input_type = schema.input_types.get(type_name);
+            for (name, value) in fields {
+                coordinates.insert(format!("{type_name}.{}", name.src()));
+                if let Some((field_type, _)) =
+                    input_type.and_then(|input_type| input_type.fields.get(name.src()))
+                {
+                    value_coordinates(value, field_type, schema, coordinates);
+                }
+            }
+        }
+        _ => {}
+    }
 }
 
 /// Intermediate structure for arguments passed through the entire formatting
@@ -1106,6 +1429,10 @@ impl Operation {
     pub(crate) fn kind(&self) -> &OperationKind {
         &self.kind
     }
+
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
 }
 
 impl From<hir::OperationType> for OperationKind {
@@ -5425,3 +5425,102 @@ fn test_query_not_named_query() {
         "unexpected selection {selection:?}"
     );
 }
+
+#[test]
+fn test_query_complexity_and_deprecated_fields() {
+    let config = Default::default();
+    let schema = Schema::parse_test(
+        r#"
+        schema
+            @core(feature: "https://specs.apollo.dev/core/v0.1")
+            @core(feature: "https://specs.apollo.dev/join/v0.1")
+            {
+            query: Query
+        }
+        directive @core(feature: String!) repeatable on SCHEMA
+        directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+        enum join__Graph {
+            TEST @join__graph(name: "test", url: "http://localhost:4001/graphql")
+        }
+
+        type Query { me: User, users: [User!]! }
+        type User {
+            name: String
+            username: String @deprecated(reason: "use name")
+            friends: [User]
+        }
+        "#,
+        &config,
+    )
+    .unwrap();
+    let query = Query::parse(
+        "{ me { name ...friends } } fragment friends on User { friends { username } }",
+        schema.api_schema(),
+        &config,
+    )
+    .unwrap();
+
+    // me, name, friends and 10 times username
+    assert_eq!(query.complexity(None), Some((3, 13)));
+    assert_eq!(
+        schema.api_schema().deprecated_fields.get("User.username"),
+        Some(&Some("use name".to_string()))
+    );
+    assert!(query
+        .schema_coordinates(None, schema.api_schema())
+        .contains("User.username"));
+}
+
+#[test]
+fn test_schema_coordinates_of_literal_arguments() {
+    let config = Default::default();
+    let schema = Schema::parse_test(
+        r#"
+        schema
+            @core(feature: "https://specs.apollo.dev/core/v0.1")
+            @core(feature: "https://specs.apollo.dev/join/v0.1")
+            {
+            query: Query
+        }
+        directive @core(feature: String!) repeatable on SCHEMA
+        directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+        enum join__Graph {
+            TEST @join__graph(name: "test", url: "http://localhost:4001/graphql")
+        }
+
+        type Query { products(filter: ProductFilter, status: Status): [Product] }
+        type Product { name: String, related(status: Status): [Product] }
+        input ProductFilter { a: Int, b: Int, price: PriceFilter }
+        input PriceFilter { min: Int, max: Int }
+        enum Status { ACTIVE, ARCHIVED }
+        "#,
+        &config,
+    )
+    .unwrap();
+    let query = Query::parse(
+        "{ products(filter: { a: 1, price: { min: 2 } }) { ...related } }
+        fragment related on Product { related(status: ACTIVE) { name } }",
+        schema.api_schema(),
+        &config,
+    )
+    .unwrap();
+
+    let coordinates = query.schema_coordinates(None, schema.api_schema());
+    for coordinate in [
+        "ProductFilter",
+        "ProductFilter.a",
+        "ProductFilter.price",
+        "PriceFilter",
+        "PriceFilter.min",
+        "Status",
+        "Status.ACTIVE",
+    ] {
+        assert!(
+            coordinates.contains(coordinate),
+            "missing {coordinate} in {coordinates:?}"
+        );
+    }
+    for coordinate in ["ProductFilter.b", "PriceFilter.max", "Status.ARCHIVED"] {
+        assert!(!coordinates.contains(coordinate), "unexpected {coordinate}");
+    }
+}
@@ -33,6 +33,8 @@ pub(crate) struct Schema {
     pub(crate) input_types: HashMap<String, InputObjectType>,
     pub(crate) custom_scalars: HashSet<String>,
     pub(crate) enums: HashMap<String, HashSet<String>>,
+    /// Deprecated fields, as `Type.field`, with the reason of the deprecation
+    pub(crate) deprecated_fields: HashMap<String, Option<String>>,
     api_schema: Option<Box<Schema>>,
     pub(crate) schema_id: Option<String>,
     root_operations: HashMap<OperationKind, String>,
@@ -157,6 +159,31 @@ impl Schema {
             })
             .collect();
 
+        let deprecated_fields = compiler
+            .db
+            .object_types_with_built_ins()
+            .iter()
+            .flat_map(|(name, def)| def.fields().map(move |field| (name, field)))
+            .chain(
+                compiler
+                    .db
+                    .interfaces()
+                    .iter()
+                    .flat_map(|(name, def)| def.fields().map(move |field| (name, field))),
+            )
+            .filter_map(|(name, field)| {
+                let deprecated = field
+                    .directives()
+                    .iter()
+                    .find(|directive| directive.name() == "deprecated")?;
+                let reason = deprecated
+                    .argument_by_name("reason")
+                    .and_then(as_string)
+                    .cloned();
+                Some((format!("{name}.{}", field.name()), reason))
+            })
+            .collect();
+
         let root_operations = compiler
             .db
             .schema()
@@ -198,6 +225,7 @@ impl Schema {
             input_types,
             custom_scalars,
             enums,
+            deprecated_fields,
             api_schema: None,
             schema_id,
             root_operations,
@@ -245,11 +273,15 @@ pub(crate) struct InvalidObject;
 #[derive(Debug, Clone)]
 pub(crate) struct ObjectType {
     pub(crate) fields: HashMap<String, FieldType>,
+    /// The arguments of each field, with their type and default value
+    pub(crate) arguments: HashMap<String, HashMap<String, (FieldType, Option<Value>)>>,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct Interface {
     pub(crate) fields: HashMap<String, FieldType>,
+    /// The arguments of each field, with their type and default value
+    pub(crate) arguments: HashMap<String, HashMap<String, (FieldType, Option<Value>)>>,
 }
 
 macro_rules! implement_object_type_or_interface {
@@ -261,6 +293,26 @@ macro_rules! implement_object_type_or_interface {
                         .fields()
                         .map(|field| (field.name().to_owned(), field.ty().into()))
                         .collect(),
+                    arguments: def
+                        .fields()
+                        .map(|field| {
+                            let arguments = field
+                                .arguments()
+                                .input_values()
+                                .iter()
+                                .map(|argument| {
+                                    (
+                                        argument.name().to_owned(),
+                                        (
+                                            argument.ty().into(),
+                                            argument.default_value().and_then(parse_hir_value),
+                                        ),
+                                    )
+                                })
+                                .collect();
+                            (field.name().to_owned(), arguments)
+                        })
+                        .collect(),
                 }
             }
         }
@@ -0,0 +1,555 @@
+//! Structural differences between two versions of an API schema.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use displaydoc::Display;
+
+use crate::json_ext::Value;
+use crate::spec::FieldType;
+use crate::spec::Schema;
+
+/// A change between two versions of an API schema
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub(crate) enum SchemaChange {
+    /// type {0} was added
+    TypeAdded(String),
+    /// type {0} was removed
+    TypeRemoved(String),
+    /// field {type_name}.{field} was added
+    FieldAdded {
+        type_name: String,
+        field: String,
+        /// a required input field, clients must now send it
+        breaking: bool,
+    },
+    /// field {type_name}.{field} was removed
+    FieldRemoved { type_name: String, field: String },
+    /// field {type_name}.{field} changed from {from} to {to}
+    FieldTypeChanged {
+        type_name: String,
+        field: String,
+        from: FieldType,
+        to: FieldType,
+        breaking: bool,
+    },
+    /// argument {type_name}.{field}({argument}:) was added
+    ArgumentAdded {
+        type_name: String,
+        field: String,
+        argument: String,
+        /// a required argument, clients must now send it
+        breaking: bool,
+    },
+    /// argument {type_name}.{field}({argument}:) was removed
+    ArgumentRemoved {
+        type_name: String,
+        field: String,
+        argument: String,
+    },
+    /// argument {type_name}.{field}({argument}:) changed from {from} to {to}
+    ArgumentTypeChanged {
+        type_name: String,
+        field: String,
+        argument: String,
+        from: FieldType,
+        to: FieldType,
+        breaking: bool,
+    },
+    /// enum value {type_name}.{value} was added
+    EnumValueAdded { type_name: String, value: String },
+    /// enum value {type_name}.{value} was removed
+    EnumValueRemoved { type_name: String, value: String },
+}
+
+impl SchemaChange {
+    /// Whether operations that were valid with the previous schema may fail with the new one
+    pub(crate) fn is_breaking(&self) -> bool {
+        match self {
+            SchemaChange::TypeAdded(_) | SchemaChange::EnumValueAdded { .. } => false,
+            SchemaChange::TypeRemoved(_)
+            | SchemaChange::FieldRemoved { .. }
+            | SchemaChange::ArgumentRemoved { .. }
+            | SchemaChange::EnumValueRemoved { .. } => true,
+            SchemaChange::FieldAdded { breaking, .. }
+            | SchemaChange::FieldTypeChanged { breaking, .. }
+            | SchemaChange::ArgumentAdded { breaking, .. }
+            | SchemaChange::ArgumentTypeChanged { breaking, .. } => *breaking,
+        }
+    }
+
+    /// Name of the kind of change, used as a metric attribute
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            SchemaChange::TypeAdded(_) => "type_added",
+            SchemaChange::TypeRemoved(_) => "type_removed",
+            SchemaChange::FieldAdded { .. } => "field_added",
+            SchemaChange::FieldRemoved { .. } => "field_removed",
+            SchemaChange::FieldTypeChanged { .. } => "field_type_changed",
+            SchemaChange::ArgumentAdded { .. } => "argument_added",
+            SchemaChange::ArgumentRemoved { .. } => "argument_removed",
+            SchemaChange::ArgumentTypeChanged { .. } => "argument_type_changed",
+            SchemaChange::EnumValueAdded { .. } => "enum_value_added",
+            SchemaChange::EnumValueRemoved { .. } => "enum_value_removed",
+        }
+    }
+
+    /// The type or field, as `Type.field`, that an operation must use to be affected by the change
+    fn coordinate(&self) -> String {
+        match self {
+            SchemaChange::TypeAdded(type_name) | SchemaChange::TypeRemoved(type_name) => {
+                type_name.clone()
+            }
+            // operations using the input type or the enum are affected
+            SchemaChange::FieldAdded { type_name, .. }
+            | SchemaChange::EnumValueAdded { type_name, .. }
+            | SchemaChange::EnumValueRemoved { type_name, .. } => type_name.clone(),
+            // operations using the field are affected by the changes of its arguments
+            SchemaChange::FieldRemoved { type_name, field }
+            | SchemaChange::FieldTypeChanged {
+                type_name, field, ..
+            }
+            | SchemaChange::ArgumentAdded {
+                type_name, field, ..
+            }
+            | SchemaChange::ArgumentRemoved {
+                type_name, field, ..
+            }
+            | SchemaChange::ArgumentTypeChanged {
+                type_name, field, ..
+            } => format!("{type_name}.{field}"),
+        }
+    }
+}
+
+/// The changes between two versions of an API schema: types, fields, arguments and enum values
+/// added or removed, and field and argument types changed.
+#[derive(Debug, Default)]
+pub(crate) struct SchemaDiff {
+    pub(crate) changes: Vec<SchemaChange>,
+}
+
+impl SchemaDiff {
+    pub(crate) fn new(previous: &Schema, next: &Schema) -> Self {
+        let mut changes = Vec::new();
+
+        let previous_types = type_names(previous);
+        let next_types = type_names(next);
+        changes.extend(
+            previous_types
+                .difference(&next_types)
+                .map(|name| SchemaChange::TypeRemoved(name.to_string())),
+        );
+        changes.extend(
+            next_types
+                .difference(&previous_types)
+                .map(|name| SchemaChange::TypeAdded(name.to_string())),
+        );
+
+        for (name, previous_type) in &previous.object_types {
+            if let Some(next_type) = next.object_types.get(name) {
+                diff_output_fields(name, &previous_type.fields, &next_type.fields, &mut changes);
+                diff_arguments(
+                    name,
+                    &previous_type.arguments,
+                    &next_type.arguments,
+                    &mut changes,
+                );
+            }
+        }
+        for (name, previous_type) in &previous.interfaces {
+            if let Some(next_type) = next.interfaces.get(name) {
+                diff_output_fields(name, &previous_type.fields, &next_type.fields, &mut changes);
+                diff_arguments(
+                    name,
+                    &previous_type.arguments,
+                    &next_type.arguments,
+                    &mut changes,
+                );
+            }
+        }
+        for (name, previous_type) in &previous.input_types {
+            if let Some(next_type) = next.input_types.get(name) {
+                for (field, (previous_field, _)) in &previous_type.fields {
+                    match next_type.fields.get(field) {
+                        None => changes.push(SchemaChange::FieldRemoved {
+                            type_name: name.clone(),
+                            field: field.clone(),
+                        }),
+                        Some((next_field, _)) if next_field != previous_field => {
+                            changes.push(SchemaChange::FieldTypeChanged {
+                                type_name: name.clone(),
+                                field: field.clone(),
+                                from: previous_field.clone(),
+                                to: next_field.clone(),
+                                // input values accepted before must still be accepted
+                                breaking: !accepts(next_field, previous_field),
+                            })
+                        }
+                        Some(_) => {}
+                    }
+                }
+                for (field, (next_field, default_value)) in &next_type.fields {
+                    if !previous_type.fields.contains_key(field) {
+                        changes.push(SchemaChange::FieldAdded {
+                            type_name: name.clone(),
+                            field: field.clone(),
+                            breaking: next_field.is_non_null() && default_value.is_none(),
+                        });
+                    }
+                }
+            }
+        }
+        for (name, previous_values) in &previous.enums {
+            if let Some(next_values) = next.enums.get(name) {
+                changes.extend(previous_values.difference(next_values).map(|value| {
+                    SchemaChange::EnumValueRemoved {
+                        type_name: name.clone(),
+                        value: value.clone(),
+                    }
+                }));
+                changes.extend(next_values.difference(previous_values).map(|value| {
+                    SchemaChange::EnumValueAdded {
+                        type_name: name.clone(),
+                        value: value.clone(),
+                    }
+                }));
+            }
+        }
+
+        changes.sort_by_key(|change| change.to_string());
+        Self { changes }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub(crate) fn breaking_changes(&self) -> impl Iterator<Item = &SchemaChange> {
+        self.changes.iter().filter(|change| change.is_breaking())
+    }
+
+    /// The breaking changes affecting an operation, from the types and fields it uses
+    pub(crate) fn affecting<'a>(
+        &'a self,
+        coordinates: &'a HashSet<String>,
+    ) -> impl Iterator<Item = &'a SchemaChange> {
+        self.breaking_changes()
+            .filter(|change| coordinates.contains(&change.coordinate()))
+    }
+
+    /// Log the changes and record them as metrics
+    pub(crate) fn report(&self) {
+        if self.is_empty() {
+            return;
+        }
+        let breaking = self.breaking_changes().count();
+        tracing::info!(
+            "the new schema has {} changes, {} of them breaking",
+            self.changes.len(),
+            breaking
+        );
+
+        let mut counts: HashMap<(&'static str, bool), u64> = HashMap::new();
+        for change in &self.changes {
+            if change.is_breaking() {
+                tracing::warn!("breaking schema change: {}", change);
+            } else {
+                tracing::info!("schema change: {}", change);
+            }
+            *counts
+                .entry((change.kind(), change.is_breaking()))
+                .or_default() += 1;
+        }
+        for ((kind, breaking), count) in counts {
+            tracing::info!(
+                monotonic_counter.apollo_router_schema_changes = count,
+                change = kind,
+                breaking
+            );
+        }
+    }
+}
+
+fn type_names(schema: &Schema) -> HashSet<&str> {
+    schema
+        .object_types
+        .keys()
+        .chain(schema.interfaces.keys())
+        .chain(schema.input_types.keys())
+        .chain(schema.enums.keys())
+        .chain(schema.custom_scalars.iter())
+        .map(String::as_str)
+        .collect()
+}
+
+fn diff_output_fields(
+    type_name: &str,
+    previous: &HashMap<String, FieldType>,
+    next: &HashMap<String, FieldType>,
+    changes: &mut Vec<SchemaChange>,
+) {
+    for (field, previous_field) in previous {
+        match next.get(field) {
+            None => changes.push(SchemaChange::FieldRemoved {
+                type_name: type_name.to_string(),
+                field: field.clone(),
+            }),
+            Some(next_field) if next_field != previous_field => {
+                changes.push(SchemaChange::FieldTypeChanged {
+                    type_name: type_name.to_string(),
+                    field: field.clone(),
+                    from: previous_field.clone(),
+                    to: next_field.clone(),
+                    // responses must still match what clients expected
+                    breaking: !accepts(previous_field, next_field),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for field in next.keys() {
+        if !previous.contains_key(field) {
+            changes.push(SchemaChange::FieldAdded {
+                type_name: type_name.to_string(),
+                field: field.clone(),
+                breaking: false,
+            });
+        }
+    }
+}
+
+/// Arguments removed, added or whose type changed, for the fields present in both schemas
+fn diff_arguments(
+    type_name: &str,
+    previous: &HashMap<String, HashMap<String, (FieldType, Option<Value>)>>,
+    next: &HashMap<String, HashMap<String, (FieldType, Option<Value>)>>,
+    changes: &mut Vec<SchemaChange>,
+) {
+    for (field, previous_arguments) in previous {
+        let next_arguments = match next.get(field) {
+            Some(next_arguments) => next_arguments,
+            // reported as a removed field
+            None => continue,
+        };
+        for (argument, (previous_argument, _)) in previous_arguments {
+            match next_arguments.get(argument) {
+                None => changes.push(SchemaChange::ArgumentRemoved {
+                    type_name: type_name.to_string(),
+                    field: field.clone(),
+                    argument: argument.clone(),
+                }),
+                Some((next_argument, _)) if next_argument != previous_argument => {
+                    changes.push(SchemaChange::ArgumentTypeChanged {
+                        type_name: type_name.to_string(),
+                        field: field.clone(),
+                        argument: argument.clone(),
+                        from: previous_argument.clone(),
+                        to: next_argument.clone(),
+                        // argument values accepted before must still be accepted
+                        breaking: !accepts(next_argument, previous_argument),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for (argument, (next_argument, default_value)) in next_arguments {
+            if !previous_arguments.contains_key(argument) {
+                changes.push(SchemaChange::ArgumentAdded {
+                    type_name: type_name.to_string(),
+                    field: field.clone(),
+                    argument: argument.clone(),
+                    breaking: next_argument.is_non_null() && default_value.is_none(),
+                });
+            }
+        }
+    }
+}
+
+/// Whether every value of type `value` is a valid value of type `expected`
+fn accepts(expected: &FieldType, value: &FieldType) -> bool {
+    match (expected, value) {
+        (FieldType::NonNull(expected), FieldType::NonNull(value)) => accepts(expected, value),
+        (expected, FieldType::NonNull(value)) => accepts(expected, value),
+        (FieldType::NonNull(_), _) => false,
+        (FieldType::List(expected), FieldType::List(value)) => accepts(expected, value),
+        (expected, value) => expected == value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(types: &str) -> Schema {
+        Schema::parse_test(
+            &format!(
+                r#"
+schema
+  @core(feature: "https://specs.apollo.dev/core/v0.1")
+  @core(feature: "https://specs.apollo.dev/join/v0.1")
+{{
+  query: Query
+}}
+directive @core(feature: String!) repeatable on SCHEMA
+directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+enum join__Graph {{
+  ACCOUNTS @join__graph(name: "accounts", url: "http://localhost:4001")
+}}
+{types}
+"#
+            ),
+            &Default::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn it_diffs_api_schemas() {
+        let previous = schema(
+            r#"
+type Query {
+  me: User!
+  users(filter: Filter): [User]
+  legacy: String
+}
+type User {
+  name: String
+  role: Role
+}
+input Filter {
+  name: String
+}
+enum Role {
+  ADMIN
+  USER
+}
+type Unused {
+  id: ID
+}
+"#,
+        );
+        let next = schema(
+            r#"
+type Query {
+  me: User
+  users(filter: Filter): [User]
+}
+type User {
+  name: String!
+  role: Role
+  email: String
+}
+input Filter {
+  name: String
+  role: Role!
+}
+enum Role {
+  USER
+  GUEST
+}
+"#,
+        );
+
+        let diff = SchemaDiff::new(previous.api_schema(), next.api_schema());
+        let changes: Vec<(String, bool)> = diff
+            .changes
+            .iter()
+            .map(|change| (change.to_string(), change.is_breaking()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("enum value Role.ADMIN was removed".to_string(), true),
+                ("enum value Role.GUEST was added".to_string(), false),
+                ("field Filter.role was added".to_string(), true),
+                ("field Query.legacy was removed".to_string(), true),
+                (
+                    "field Query.me changed from User! to User".to_string(),
+                    true
+                ),
+                ("field User.email was added".to_string(), false),
+                (
+                    "field User.name changed from String to String!".to_string(),
+                    false
+                ),
+                ("type Unused was removed".to_string(), true),
+            ]
+        );
+
+        let query = crate::spec::Query::parse(
+            "query($filter: Filter) { users(filter: $filter) { name } }",
+            previous.api_schema(),
+            &Default::default(),
+        )
+        .unwrap();
+        let coordinates = query.schema_coordinates(None, previous.api_schema());
+        let affecting: Vec<String> = diff
+            .affecting(&coordinates)
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(affecting, vec!["field Filter.role was added".to_string()]);
+    }
+
+    #[test]
+    fn it_diffs_field_arguments() {
+        let previous = schema(
+            r#"
+type Query {
+  users(first: Int, after: String, role: String!, legacy: Boolean): [User]
+  user(id: ID!): User
+}
+type User {
+  name: String
+}
+"#,
+        );
+        let next = schema(
+            r#"
+type Query {
+  users(first: Int!, after: String, role: String, sort: String!, limit: Int! = 10): [User]
+  user(id: ID!, version: Int): User
+}
+type User {
+  name: String
+}
+"#,
+        );
+
+        let diff = SchemaDiff::new(previous.api_schema(), next.api_schema());
+        let changes: Vec<(String, bool)> = diff
+            .changes
+            .iter()
+            .map(|change| (change.to_string(), change.is_breaking()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("argument Query.user(version:) was added".to_string(), false),
+                (
+                    "argument Query.users(first:) changed from Int to Int!".to_string(),
+                    true
+                ),
+                (
+                    "argument Query.users(legacy:) was removed".to_string(),
+                    true
+                ),
+                ("argument Query.users(limit:) was added".to_string(), false),
+                (
+                    "argument Query.users(role:) changed from String! to String".to_string(),
+                    false
+                ),
+                ("argument Query.users(sort:) was added".to_string(), true),
+            ]
+        );
+
+        let query = crate::spec::Query::parse(
+            "{ users(role: \"admin\") { name } }",
+            previous.api_schema(),
+            &Default::default(),
+        )
+        .unwrap();
+        let coordinates = query.schema_coordinates(None, previous.api_schema());
+        assert_eq!(diff.affecting(&coordinates).count(), 3);
+    }
+}
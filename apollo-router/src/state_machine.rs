@@ -4,6 +4,7 @@ use std::fmt::Debug;
 use std::fmt::Formatter;
 use std::sync::Arc;
 
+use futures::future::Either;
 use futures::prelude::*;
 use tokio::sync::mpsc;
 use tokio::sync::OwnedRwLockWriteGuard;
@@ -16,6 +17,8 @@ use Event::Shutdown;
 
 use super::http_server_factory::HttpServerFactory;
 use super::http_server_factory::HttpServerHandle;
+use super::http_server_factory::StartupHealth;
+use super::http_server_factory::StartupHealthHandle;
 use super::router::ApolloRouterError::NoConfiguration;
 use super::router::ApolloRouterError::NoSchema;
 use super::router::ApolloRouterError::{self};
@@ -28,6 +31,9 @@ use super::state_machine::State::Startup;
 use super::state_machine::State::Stopped;
 use crate::configuration::Configuration;
 use crate::configuration::ListenAddr;
+use crate::last_known_good::LastKnownGood;
+use crate::rollback;
+use crate::rollback::RollBack;
 use crate::router::Event::UpdateEntitlement;
 use crate::router_factory::RouterFactory;
 use crate::router_factory::RouterSuperServiceFactory;
@@ -116,6 +122,7 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
         new_schema: Option<Arc<String>>,
         new_configuration: Option<Arc<Configuration>>,
         new_entitlement: Option<EntitlementState>,
+        rolling_back: bool,
     ) -> Self
     where
         S: HttpServerFactory,
@@ -132,6 +139,19 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
                 *configuration = new_configuration.or_else(|| configuration.take());
                 *entitlement = new_entitlement.or_else(|| entitlement.take());
 
+                let waiting_for = [
+                    ("configuration", configuration.is_none()),
+                    ("schema", schema.is_none()),
+                    ("entitlement", entitlement.is_none()),
+                ]
+                .into_iter()
+                .filter_map(|(input, missing)| missing.then_some(input))
+                .collect();
+                state_machine.startup_health.set_waiting_for(waiting_for);
+                if let Some(configuration) = configuration.as_deref() {
+                    state_machine.serve_startup_health(configuration).await;
+                }
+
                 if let (Some(schema), Some(configuration), Some(entitlement)) =
                     (schema, configuration, entitlement)
                 {
@@ -143,6 +163,7 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
                             configuration.clone(),
                             schema.clone(),
                             *entitlement,
+                            false,
                             listen_addresses_guard,
                         )
                         .map_ok_or_else(Errored, |f| f)
@@ -169,6 +190,11 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
                     return self;
                 }
 
+                // Kept to roll back the reload if the new router fails more often
+                let previous_configuration = configuration.clone();
+                let previous_schema = schema.clone();
+                let previous_outcomes = router_service_factory.request_outcomes().snapshot();
+
                 // We update the running config. This is OK even in the case that the router could not reload as we always want to retain the latest information for when we try to reload next.
                 // In the case of a failed reload the server handle is retained, which has the old config/schema/entitlements in.
                 if let Some(new_configuration) = new_configuration {
@@ -189,12 +215,23 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
                     configuration.clone(),
                     schema.clone(),
                     *entitlement,
+                    rolling_back,
                     &mut guard,
                 )
                 .await
                 {
                     Ok(new_state) => {
                         tracing::info!("reload complete");
+                        // the previous router already ran with the configuration and schema
+                        // of a rollback, so it is not watched again
+                        if !rolling_back {
+                            state_machine.watch_reload(
+                                &new_state,
+                                previous_configuration,
+                                previous_schema,
+                                previous_outcomes,
+                            );
+                        }
                         Some(new_state)
                     }
                     Err(e) => {
@@ -218,6 +255,29 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
         new_state.unwrap_or(self)
     }
 
+    async fn roll_back<S>(self, state_machine: &mut StateMachine<S, FA>, rollback: RollBack) -> Self
+    where
+        S: HttpServerFactory,
+    {
+        if !matches!(self, Running { .. }) || rollback.generation != state_machine.generation {
+            tracing::debug!("the router was replaced since the rollback was requested, ignoring");
+            return self;
+        }
+        tracing::error!(
+            "rolling back to the previous configuration and schema: {}",
+            rollback.reason
+        );
+        tracing::info!(monotonic_counter.apollo_router_reload_rollback_count = 1u64);
+        self.update_inputs(
+            state_machine,
+            Some(rollback.schema),
+            Some(rollback.configuration),
+            None,
+            true,
+        )
+        .await
+    }
+
     async fn shutdown(self) -> Self {
         match self {
             Running {
@@ -247,6 +307,7 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
         configuration: Arc<Configuration>,
         schema: Arc<String>,
         entitlement: EntitlementState,
+        rolling_back: bool,
         listen_addresses_guard: &mut OwnedRwLockWriteGuard<ListenAddresses>,
     ) -> Result<State<FA>, ApolloRouterError>
     where
@@ -294,6 +355,7 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
             entitlement
         };
 
+        state_machine.startup_health.set_warming_up(true);
         let router_service_factory = state_machine
             .router_configurator
             .create(
@@ -301,9 +363,11 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
                 schema.to_string(),
                 previous_router_service_factory,
                 None,
+                rolling_back,
             )
-            .await
-            .map_err(ServiceCreationError)?;
+            .await;
+        state_machine.startup_health.set_warming_up(false);
+        let router_service_factory = router_service_factory.map_err(ServiceCreationError)?;
 
         // used to track if there are still in flight connections when shutting down
         let (all_connections_stopped_sender, all_connections_stopped_signal) =
@@ -313,13 +377,26 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
         // The point of no return. We take the previous server handle.
         let server_handle = match server_handle.take() {
             None => {
+                // the server takes over the listener of the health checks served while starting
+                let (main_listener, previous_listeners) =
+                    match state_machine.startup_health_handle.take() {
+                        Some(startup_health_handle) => {
+                            let (listen_address, listener) = startup_health_handle.stop().await?;
+                            if listen_address == configuration.supergraph.listen {
+                                (Some(listener), Vec::new())
+                            } else {
+                                (None, vec![(listen_address, listener)])
+                            }
+                        }
+                        None => Default::default(),
+                    };
                 state_machine
                     .http_server_factory
                     .create(
                         router_service_factory.clone(),
                         configuration.clone(),
-                        Default::default(),
-                        Default::default(),
+                        main_listener,
+                        previous_listeners,
                         web_endpoints,
                         effective_entitlement,
                         all_connections_stopped_sender,
@@ -339,6 +416,12 @@ impl<FA: RouterSuperServiceFactory> State<FA> {
             }
         };
 
+        if let Some(last_known_good) = &state_machine.last_known_good {
+            last_known_good.save_schema(&schema);
+            last_known_good.save_entitlement();
+        }
+        state_machine.generation += 1;
+
         listen_addresses_guard.extra_listen_addresses = server_handle.listen_addresses().to_vec();
         listen_addresses_guard.graphql_listen_address =
             server_handle.graphql_listen_address().clone();
@@ -369,6 +452,14 @@ where
     router_configurator: FA,
     pub(crate) listen_addresses: Arc<RwLock<ListenAddresses>>,
     listen_addresses_guard: Option<OwnedRwLockWriteGuard<ListenAddresses>>,
+    last_known_good: Option<LastKnownGood>,
+    /// Incremented every time a new router starts serving requests
+    generation: u64,
+    rollback_sender: mpsc::Sender<RollBack>,
+    rollback_receiver: Option<mpsc::Receiver<RollBack>>,
+    /// Reported by the readiness check until the router is running
+    startup_health: StartupHealth,
+    startup_health_handle: Option<StartupHealthHandle>,
 }
 
 impl<S, FA> StateMachine<S, FA>
@@ -386,11 +477,87 @@ where
                 .try_write_owned()
                 .expect("lock just created, qed"),
         );
+        let (rollback_sender, rollback_receiver) = mpsc::channel(1);
         Self {
             http_server_factory,
             router_configurator: router_factory,
             listen_addresses,
             listen_addresses_guard,
+            last_known_good: None,
+            generation: 0,
+            rollback_sender,
+            rollback_receiver: Some(rollback_receiver),
+            startup_health: StartupHealth::default(),
+            startup_health_handle: None,
+        }
+    }
+
+    /// Save every schema the router successfully starts with
+    pub(crate) fn with_last_known_good(mut self, last_known_good: Option<LastKnownGood>) -> Self {
+        self.last_known_good = last_known_good;
+        self
+    }
+
+    /// Answer the health checks as soon as the health check listen address is known, so the
+    /// router is reported as live while it waits for its inputs and warms up
+    async fn serve_startup_health(&mut self, configuration: &Configuration) {
+        let listen_address = &configuration.health_check.listen;
+        if configuration.health_check.enabled
+            && self
+                .startup_health_handle
+                .as_ref()
+                .map(StartupHealthHandle::listen_address)
+                == Some(listen_address)
+        {
+            return;
+        }
+        self.stop_startup_health().await;
+
+        match self
+            .http_server_factory
+            .create_startup_health(configuration, self.startup_health.clone())
+            .await
+        {
+            Ok(handle) => self.startup_health_handle = handle,
+            Err(e) => tracing::warn!("could not serve the health checks while starting: {}", e),
+        }
+    }
+
+    async fn stop_startup_health(&mut self) {
+        if let Some(handle) = self.startup_health_handle.take() {
+            let _ = handle.stop().await;
+        }
+    }
+
+    /// Watch the error rate of the router that was just reloaded, if enabled in its configuration
+    fn watch_reload(
+        &self,
+        new_state: &State<FA>,
+        previous_configuration: Arc<Configuration>,
+        previous_schema: Arc<String>,
+        previous_outcomes: rollback::Outcomes,
+    ) {
+        if let Running {
+            configuration,
+            router_service_factory,
+            ..
+        } = new_state
+        {
+            let settings = &configuration.experimental_reload_rollback;
+            if settings.enabled {
+                rollback::watch(
+                    settings.clone(),
+                    previous_outcomes,
+                    router_service_factory.request_outcomes(),
+                    RollBack {
+                        generation: self.generation,
+                        configuration: previous_configuration,
+                        schema: previous_schema,
+                        reason: String::new(),
+                    },
+                    self.rollback_sender.clone(),
+                );
+            }
         }
     }
 
@@ -410,30 +577,49 @@ where
                 .expect("must have listen address guard"),
         };
 
+        let mut rollbacks = self
+            .rollback_receiver
+            .take()
+            .expect("must have rollback receiver");
+        self.startup_health
+            .set_waiting_for(vec!["configuration", "schema", "entitlement"]);
+
         // Process all the events in turn until we get to error state or we run out of events.
-        while let Some(event) = messages.next().await {
-            let event_name = format!("{event:?}");
+        loop {
+            let event = tokio::select! {
+                biased;
+                event = messages.next() => match event {
+                    Some(event) => Either::Left(event),
+                    None => break,
+                },
+                Some(rollback) = rollbacks.recv() => Either::Right(rollback),
+            };
+            let event_name = match &event {
+                Either::Left(event) => format!("{event:?}"),
+                Either::Right(_) => "RollBack".to_string(),
+            };
             let last_state = format!("{state:?}");
             state = match event {
-                UpdateConfiguration(configuration) => {
+                Either::Left(UpdateConfiguration(configuration)) => {
                     state
-                        .update_inputs(&mut self, None, Some(Arc::new(configuration)), None)
+                        .update_inputs(&mut self, None, Some(Arc::new(configuration)), None, false)
                         .await
                 }
-                NoMoreConfiguration => state.no_more_configuration().await,
-                UpdateSchema(schema) => {
+                Either::Left(NoMoreConfiguration) => state.no_more_configuration().await,
+                Either::Left(UpdateSchema(schema)) => {
                     state
-                        .update_inputs(&mut self, Some(Arc::new(schema)), None, None)
+                        .update_inputs(&mut self, Some(Arc::new(schema)), None, None, false)
                         .await
                 }
-                NoMoreSchema => state.no_more_schema().await,
-                UpdateEntitlement(entitlement) => {
+                Either::Left(NoMoreSchema) => state.no_more_schema().await,
+                Either::Left(UpdateEntitlement(entitlement)) => {
                     state
-                        .update_inputs(&mut self, None, None, Some(entitlement))
+                        .update_inputs(&mut self, None, None, Some(entitlement), false)
                         .await
                 }
-                NoMoreEntitlement => state.no_more_entitlement().await,
-                Shutdown => state.shutdown().await,
+                Either::Left(NoMoreEntitlement) => state.no_more_entitlement().await,
+                Either::Left(Shutdown) => state.shutdown().await,
+                Either::Right(rollback) => state.roll_back(&mut self, rollback).await,
             };
             tracing::debug!(
                 "state machine event: {event_name}, transitioned from: {last_state} to: {state:?}"
@@ -444,6 +630,8 @@ where
                 break;
             }
         }
+        // the router stopped before it started running
+        self.stop_startup_health().await;
         tracing::info!("stopped");
 
         match state {
@@ -819,7 +1007,7 @@ mod tests {
         router_factory
             .expect_create()
             .times(1)
-            .returning(|_, _, _, _| Err(BoxError::from("Error")));
+            .returning(|_, _, _, _, _| Err(BoxError::from("Error")));
 
         let (server_factory, shutdown_receivers) = create_mock_server_factory(0);
 
@@ -847,7 +1035,7 @@ mod tests {
             .expect_create()
             .times(1)
             .in_sequence(&mut seq)
-            .returning(|_, _, _, _| {
+            .returning(|_, _, _, _, _| {
                 let mut router = MockMyRouterFactory::new();
                 router.expect_clone().return_once(MockMyRouterFactory::new);
                 router.expect_web_endpoints().returning(MultiMap::new);
@@ -857,7 +1045,7 @@ mod tests {
             .expect_create()
             .times(1)
             .in_sequence(&mut seq)
-            .returning(|_, _, _, _| Err(BoxError::from("error")));
+            .returning(|_, _, _, _, _| Err(BoxError::from("error")));
 
         let (server_factory, shutdown_receivers) = create_mock_server_factory(1);
 
@@ -887,7 +1075,7 @@ mod tests {
             .expect_create()
             .times(1)
             .in_sequence(&mut seq)
-            .returning(|_, _, _, _| {
+            .returning(|_, _, _, _, _| {
                 let mut router = MockMyRouterFactory::new();
                 router.expect_clone().return_once(MockMyRouterFactory::new);
                 router.expect_web_endpoints().returning(MultiMap::new);
@@ -897,13 +1085,13 @@ mod tests {
             .expect_create()
             .times(1)
             .in_sequence(&mut seq)
-            .returning(|_, _, _, _| Err(BoxError::from("error")));
+            .returning(|_, _, _, _, _| Err(BoxError::from("error")));
         router_factory
             .expect_create()
             .times(1)
             .in_sequence(&mut seq)
-            .withf(|configuration, _, _, _| configuration.homepage.enabled)
-            .returning(|_, _, _, _| {
+            .withf(|configuration, _, _, _, _| configuration.homepage.enabled)
+            .returning(|_, _, _, _, _| {
                 let mut router = MockMyRouterFactory::new();
                 router.expect_clone().return_once(MockMyRouterFactory::new);
                 router.expect_web_endpoints().returning(MultiMap::new);
@@ -936,6 +1124,104 @@ mod tests {
         assert_eq!(shutdown_receivers.lock().unwrap().len(), 2);
     }
 
+    #[test(tokio::test)]
+    async fn rollback_restores_previous_configuration_and_schema() {
+        let minimal_schema = include_str!("testdata/minimal_supergraph.graphql");
+        let mut seq = Sequence::new();
+        let mut router_factory = MockMyRouterConfigurator::new();
+        router_factory
+            .expect_create()
+            .times(3)
+            .in_sequence(&mut seq)
+            .withf(|_, _, _, _, rolling_back| !*rolling_back)
+            .returning(|_, _, _, _, _| {
+                let mut router = MockMyRouterFactory::new();
+                router.expect_clone().return_once(MockMyRouterFactory::new);
+                router.expect_web_endpoints().returning(MultiMap::new);
+                Ok(router)
+            });
+        router_factory
+            .expect_create()
+            .times(1)
+            .in_sequence(&mut seq)
+            .withf(|configuration, schema, previous_router, _, rolling_back| {
+                *rolling_back
+                    && previous_router.is_some()
+                    && configuration.homepage.enabled
+                    && *schema == example_schema()
+            })
+            .returning(|_, _, _, _, _| {
+                let mut router = MockMyRouterFactory::new();
+                router.expect_clone().return_once(MockMyRouterFactory::new);
+                router.expect_web_endpoints().returning(MultiMap::new);
+                Ok(router)
+            });
+
+        let (server_factory, shutdown_receivers) = create_mock_server_factory(4);
+        let configuration = Configuration::builder()
+            .homepage(Homepage::builder().enabled(true).build())
+            .build()
+            .unwrap();
+
+        assert_matches!(
+            execute_with_rollback(
+                server_factory,
+                router_factory,
+                vec![
+                    UpdateConfiguration(configuration.clone()),
+                    UpdateSchema(example_schema()),
+                    UpdateEntitlement(EntitlementState::default()),
+                    UpdateConfiguration(
+                        Configuration::builder()
+                            .homepage(Homepage::builder().enabled(false).build())
+                            .build()
+                            .unwrap()
+                    ),
+                    UpdateSchema(minimal_schema.to_owned()),
+                ],
+                RollBack {
+                    generation: 3,
+                    configuration: Arc::new(configuration),
+                    schema: Arc::new(example_schema()),
+                    reason: "error rate increased".to_string(),
+                },
+            )
+            .await,
+            Ok(())
+        );
+        assert_eq!(shutdown_receivers.lock().unwrap().len(), 4);
+    }
+
+    #[test(tokio::test)]
+    async fn stale_rollback_is_ignored() {
+        let minimal_schema = include_str!("testdata/minimal_supergraph.graphql");
+        let router_factory = create_mock_router_configurator(2);
+        let (server_factory, shutdown_receivers) = create_mock_server_factory(2);
+
+        // the rollback was requested for the first router, which was replaced by the reload
+        assert_matches!(
+            execute_with_rollback(
+                server_factory,
+                router_factory,
+                vec![
+                    UpdateConfiguration(Configuration::builder().build().unwrap()),
+                    UpdateSchema(example_schema()),
+                    UpdateEntitlement(EntitlementState::default()),
+                    UpdateSchema(minimal_schema.to_owned()),
+                ],
+                RollBack {
+                    generation: 1,
+                    configuration: Arc::new(Configuration::builder().build().unwrap()),
+                    schema: Arc::new(example_schema()),
+                    reason: "error rate increased".to_string(),
+                },
+            )
+            .await,
+            Ok(())
+        );
+        assert_eq!(shutdown_receivers.lock().unwrap().len(), 2);
+    }
+
     mock! {
         #[derive(Debug)]
         MyRouterConfigurator {}
@@ -950,6 +1236,7 @@ mod tests {
                 schema: String,
                 previous_router_service_factory: Option<&'a MockMyRouterFactory>,
                 extra_plugins: Option<Vec<(String, Box<dyn DynPlugin>)>>,
+                rolling_back: bool,
             ) -> Result<MockMyRouterFactory, BoxError>;
         }
     }
@@ -1042,6 +1329,30 @@ mod tests {
             .await
     }
 
+    /// Process the events, then send the rollback and shut down once it was handled
+    async fn execute_with_rollback(
+        server_factory: MockMyHttpServerFactory,
+        router_factory: MockMyRouterConfigurator,
+        events: Vec<Event>,
+        rollback: RollBack,
+    ) -> Result<(), ApolloRouterError> {
+        let state_machine = StateMachine::new(server_factory, router_factory);
+        let rollback_sender = state_machine.rollback_sender.clone();
+        // rollbacks are only received while no event is ready, so the shutdown
+        // waits for one more turn of the loop after sending it
+        let rollback_then_shutdown = stream::once(async move {
+            rollback_sender
+                .send(rollback)
+                .await
+                .expect("rollback receiver must be alive");
+            tokio::task::yield_now().await;
+            Shutdown
+        });
+        state_machine
+            .process_events(stream::iter(events).chain(rollback_then_shutdown).boxed())
+            .await
+    }
+
     fn create_mock_server_factory(
         expect_times_called: usize,
     ) -> (
@@ -1081,6 +1392,7 @@ mod tests {
                         Some(configuration.supergraph.listen.clone()),
                         vec![],
                         all_connections_stopped_sender,
+                        Default::default(),
                     ))
                 },
             );
@@ -1097,7 +1409,7 @@ mod tests {
             } else {
                 expect_times_called
             })
-            .returning(move |_, _, _, _| {
+            .returning(move |_, _, _, _, _| {
                 let mut router = MockMyRouterFactory::new();
                 router.expect_clone().return_once(MockMyRouterFactory::new);
                 router.expect_web_endpoints().returning(MultiMap::new);
@@ -1113,11 +1425,12 @@ mod tests {
                     move |_configuration: &Arc<Configuration>,
                           _,
                           previous_router_service_factory: &Option<&MockMyRouterFactory>,
-                          _extra_plugins: &Option<Vec<(String, Box<dyn DynPlugin>)>>| {
+                          _extra_plugins: &Option<Vec<(String, Box<dyn DynPlugin>)>>,
+                          _rolling_back: &bool| {
                         previous_router_service_factory.is_some()
                     },
                 )
-                .returning(move |_, _, _, _| {
+                .returning(move |_, _, _, _, _| {
                     let mut router = MockMyRouterFactory::new();
                     router.expect_clone().return_once(MockMyRouterFactory::new);
                     router.expect_web_endpoints().returning(MultiMap::new);
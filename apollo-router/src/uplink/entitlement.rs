@@ -174,6 +174,8 @@ impl Display for EntitlementReport {
 #[derive(Debug, Clone, Default)]
 pub struct Entitlement {
     pub(crate) claims: Option<Claims>,
+    /// The JWT the claims were decoded from, to persist the entitlement
+    pub(crate) jwt: Option<String>,
 }
 
 /// Entitlements are converted into a stream of entitlement states by the expander
@@ -234,6 +236,7 @@ impl FromStr for Entitlement {
                 .map_err(Error::InvalidEntitlement)
                 .map(|r| Entitlement {
                     claims: Some(r.claims),
+                    jwt: Some(jwt.trim().to_string()),
                 })
             })
             .find_or_last(|r| r.is_ok())
@@ -422,11 +422,15 @@ mod test {
                 warn_at: now + Duration::from_millis(warn_delta),
                 halt_at: now + Duration::from_millis(halt_delta),
             }),
+            jwt: None,
         }
     }
 
     fn entitlement_with_no_claim() -> Entitlement {
-        Entitlement { claims: None }
+        Entitlement {
+            claims: None,
+            jwt: None,
+        }
     }
 
     #[derive(Eq, PartialEq, Debug)]
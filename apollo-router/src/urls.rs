@@ -0,0 +1,190 @@
+//! Polling of documents served over HTTP, like a supergraph schema published to an artifact store
+
+use std::time::Duration;
+
+use futures::Stream;
+use http::header::ETAG;
+use http::header::IF_MODIFIED_SINCE;
+use http::header::IF_NONE_MATCH;
+use http::header::LAST_MODIFIED;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::StatusCode;
+use tokio::sync::mpsc::channel;
+use tokio_stream::wrappers::ReceiverStream;
+use tower::BoxError;
+use tracing::instrument::WithSubscriber;
+use url::Url;
+
+/// The longest time to wait before fetching the document again after a failure
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Validators of the last document received, sent back so the server can answer that it did not
+/// change
+#[derive(Default)]
+struct Validators {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+}
+
+/// Fetch the document at `url`, then fetch it again every `interval` and send it when it changed.
+///
+/// Failed fetches are retried with an exponential backoff.
+pub(crate) fn poll(
+    url: Url,
+    headers: HeaderMap,
+    interval: Duration,
+    timeout: Duration,
+) -> impl Stream<Item = String> {
+    let (sender, receiver) = channel(2);
+    let task = async move {
+        let client = match reqwest::Client::builder().timeout(timeout).build() {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::error!("could not create the HTTP client to fetch {}: {}", url, e);
+                return;
+            }
+        };
+        let mut validators = Validators::default();
+        let mut last_document = None;
+        let mut failures = 0;
+        loop {
+            let delay = match fetch(&client, &url, &headers, &mut validators).await {
+                Ok(Some(document)) => {
+                    failures = 0;
+                    // servers that do not support validators send the document every time
+                    if last_document.as_ref() != Some(&document) {
+                        last_document = Some(document.clone());
+                        if let Err(e) = sender.send(document).await {
+                            tracing::debug!("failed to push to stream. This is likely to be because the router is shutting down: {e}");
+                            break;
+                        }
+                    }
+                    interval
+                }
+                Ok(None) => {
+                    tracing::debug!("{} did not change", url);
+                    failures = 0;
+                    interval
+                }
+                Err(e) => {
+                    failures += 1;
+                    let delay = backoff(interval, failures);
+                    tracing::error!(
+                        "could not fetch {}: {}, retrying in {}",
+                        url,
+                        e,
+                        humantime::format_duration(delay)
+                    );
+                    delay
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    };
+    drop(tokio::task::spawn(task.with_current_subscriber()));
+
+    ReceiverStream::new(receiver)
+}
+
+/// Fetch the document, `None` if it did not change since the last fetch
+async fn fetch(
+    client: &reqwest::Client,
+    url: &Url,
+    headers: &HeaderMap,
+    validators: &mut Validators,
+) -> Result<Option<String>, BoxError> {
+    let mut request = client.get(url.clone()).headers(headers.clone());
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag.clone());
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified.clone());
+    }
+
+    let response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    let response = response.error_for_status()?;
+    validators.etag = response.headers().get(ETAG).cloned();
+    validators.last_modified = response.headers().get(LAST_MODIFIED).cloned();
+    Ok(Some(response.text().await?))
+}
+
+/// The delay before the next fetch: doubles with every failure, from `interval` up to
+/// [`MAX_BACKOFF`]
+fn backoff(interval: Duration, failures: u32) -> Duration {
+    interval
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF.max(interval))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use http::header::AUTHORIZATION;
+    use wiremock::matchers::header;
+    use wiremock::matchers::method;
+    use wiremock::Mock;
+    use wiremock::MockServer;
+    use wiremock::ResponseTemplate;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn it_polls_with_validators() {
+        let server = MockServer::start().await;
+        // the first mock that matches answers
+        Mock::given(method("GET"))
+            .and(header(IF_NONE_MATCH.as_str(), "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1..)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(header(AUTHORIZATION.as_str(), "Bearer secret"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(ETAG.as_str(), "\"v1\"")
+                    .set_body_string("type Query { me: String }"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        let mut stream = poll(
+            Url::parse(&server.uri()).unwrap(),
+            headers,
+            Duration::from_millis(10),
+            Duration::from_secs(5),
+        )
+        .boxed();
+
+        assert_eq!(
+            stream.next().await.unwrap(),
+            "type Query { me: String }".to_string()
+        );
+        // unchanged documents are not sent again
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), stream.next())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn it_backs_off() {
+        let interval = Duration::from_secs(10);
+        assert_eq!(backoff(interval, 1), Duration::from_secs(10));
+        assert_eq!(backoff(interval, 2), Duration::from_secs(20));
+        assert_eq!(backoff(interval, 3), Duration::from_secs(40));
+        assert_eq!(backoff(interval, 100), MAX_BACKOFF);
+        assert_eq!(
+            backoff(Duration::from_secs(600), 3),
+            Duration::from_secs(600)
+        );
+    }
+}